@@ -0,0 +1,18 @@
+[
+  (mod_item)
+  (function_item)
+  (impl_item)
+  (trait_item)
+  (struct_item)
+  (enum_item)
+  (union_item)
+  (macro_definition)
+  (macro_invocation)
+  (use_declaration)
+  (match_expression)
+  (match_arm)
+  (block)
+  (block_comment)
+  (arguments)
+  (parameters)
+] @fold
@@ -1,84 +1,317 @@
 use ratatui::{
     Frame,
-    crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, MouseEvent, MouseEventKind},
-    layout::{Constraint, Layout},
+    crossterm::event::{Event, KeyCode, KeyEventKind, KeyModifiers, MouseEvent, MouseEventKind},
+    layout::{Alignment, Constraint, Layout},
     style::Stylize,
+    symbols::line,
     widgets::{Paragraph, Widget},
 };
-use ropey::Rope;
 use std::{
-    collections::HashMap,
+    collections::{BTreeSet, HashMap},
     error::Error,
-    fs::File,
-    io::BufReader,
-    path::{Path, PathBuf},
+    io,
+    path::Path,
+    sync::{Arc, mpsc::Receiver},
+    time::{Duration, Instant},
 };
 
 use crate::{
-    command::Command,
-    document::{Document, Mode},
+    buffer::{Buffer, Buffers},
+    cmdline::{self, CommandError},
+    command::{Command, ExCommand},
+    completion::{self, Completion, Item, Source},
+    diagnostic::{Diagnostic, Severity},
+    document::Document,
+    highlighter::Highlighter,
     input::Input,
-    keymap::Keymap,
-    language::{self, Language},
+    keymap::{self, Action, Key, KeyMode, KeyTrie, Keymap},
+    language::Languages,
+    lsp::{self, Lsp},
+    operator::{LastChange, Pending},
+    options::Options,
+    picker::{self, Picker},
+    quickfix::{Entry, Quickfix},
+    register::{Clipboard, Registers},
+    search::Search,
+    substitute::Preview,
     theme::Theme,
+    view::{Mode, View},
+    window::Windows,
 };
 
+/// How often to check for work done in the background, e.g. highlights,
+/// while there is some.
+const BACKGROUND_POLL: Duration = Duration::from_millis(10);
+
 pub struct App {
-    pub doc: Document,
-    pub doc_name: Option<PathBuf>,
+    pub buffers: Buffers,
+    pub windows: Windows,
     pub input: Option<Input>,
+    pub picker: Option<Picker>,
+    pub completion: Option<Completion>,
+    pub pending_key: Option<Command<char>>,
+    pending_keys: Vec<Key>,
+    pending_since: Instant,
+    /// The keys of the command being typed, shown in the bottom right corner.
+    typed: Vec<Key>,
+    count: Option<usize>,
+    pub operator: Option<Pending>,
+    pub last_change: Option<LastChange>,
+    /// The keys typed since a change entered insert mode, for `.` to replay.
+    pub recording: Option<Vec<Key>>,
     err: Option<Box<dyn Error>>,
+    message: Option<String>,
 
-    language: Language,
-    pub commands: HashMap<String, Command<()>>,
+    pub commands: HashMap<String, ExCommand>,
+    /// What keys can be bound to, by name.
+    pub actions: HashMap<String, Action>,
     pub keymap: Keymap,
+    pub languages: Arc<Languages>,
+    pub highlighter: Highlighter,
     pub theme: Theme,
+    pub options: Options,
+    pub registers: Registers,
+    pub search: Option<Search>,
+    pub preview: Option<Preview>,
+    pub quickfix: Quickfix,
+    /// Sends the quickfix entries once the running `:make` command is done.
+    pub make: Option<Receiver<Result<Vec<Entry>, String>>>,
+    pub lsp: Lsp,
 
     pub exit: bool,
 }
 
 impl App {
-    pub fn open(path: &Path) -> Self {
-        let content = File::open(path)
-            .and_then(|file| Rope::from_reader(BufReader::new(file)))
-            .unwrap_or_default();
-
-        let doc = Document::new(content);
+    pub fn new() -> Self {
+        let languages = Arc::new(Languages::default());
 
         Self {
-            doc,
-            doc_name: Some(path.into()),
+            buffers: Buffers::default(),
+            windows: Windows::new(View::new(1)),
             input: None,
+            picker: None,
+            completion: None,
+            pending_key: None,
+            pending_keys: Vec::new(),
+            pending_since: Instant::now(),
+            typed: Vec::new(),
+            count: None,
+            operator: None,
+            last_change: None,
+            recording: None,
             err: None,
-            language: language::rust(),
+            message: None,
             commands: HashMap::new(),
+            actions: HashMap::new(),
             keymap: Keymap::default(),
+            highlighter: Highlighter::new(languages.clone()),
+            languages,
             theme: Theme::default(),
+            options: Options::default(),
+            registers: Registers::new(Clipboard::detect()),
+            search: None,
+            preview: None,
+            quickfix: Quickfix::default(),
+            make: None,
+            lsp: Lsp::default(),
             exit: false,
         }
     }
 
-    pub fn new() -> Self {
-        Self {
-            doc: Document::default(),
-            doc_name: None,
-            input: None,
-            err: None,
-            language: language::rust(),
-            commands: HashMap::new(),
-            keymap: Keymap::default(),
-            theme: Theme::default(),
-            exit: false,
+    pub fn buffer(&self) -> &Buffer {
+        let id = self.windows.focused().buffer;
+        self.buffers.get(id).expect("windows show open buffers")
+    }
+
+    pub fn buffer_mut(&mut self) -> &mut Buffer {
+        let id = self.windows.focused().buffer;
+        self.buffers.get_mut(id).expect("windows show open buffers")
+    }
+
+    /// The focused view and its document.
+    pub fn current(&self) -> (&View, &Document) {
+        (self.windows.focused(), &self.buffer().doc)
+    }
+
+    pub fn current_mut(&mut self) -> (&mut View, &mut Document) {
+        let view = self.windows.focused_mut();
+        let buffer = self
+            .buffers
+            .get_mut(view.buffer)
+            .expect("windows show open buffers");
+
+        (view, &mut buffer.doc)
+    }
+
+    /// Opens `path` in the focused window.
+    pub fn open(&mut self, path: &Path) -> io::Result<()> {
+        let current = self.windows.focused().buffer;
+        let id = self.buffers.open(path, current, &self.languages)?;
+        self.show_buffer(id);
+        self.report_language_error();
+        self.apply_quickfix(id);
+        Ok(())
+    }
+
+    /// Shows the quickfix entries of a buffer's file as its diagnostics,
+    /// unless it was changed since it was saved, which may have moved them.
+    pub fn apply_quickfix(&mut self, id: usize) {
+        if let Some(buffer) = self.buffers.get_mut(id)
+            && let Some(path) = &buffer.path
+            && !buffer.doc.is_modified()
+        {
+            let diagnostics = self.quickfix.diagnostics(path, buffer.doc.rope());
+            buffer.doc.set_diagnostics("make", diagnostics);
+        }
+    }
+
+    /// Reports a grammar that failed to load for a buffer just opened.
+    fn report_language_error(&mut self) {
+        if let Some(err) = self.languages.take_error() {
+            self.report_error(err);
+        }
+    }
+
+    /// Shows a buffer in the focused window, restoring the cursor it had there.
+    pub fn show_buffer(&mut self, id: usize) {
+        let view = self.windows.focused_mut();
+
+        if view.buffer == id {
+            return;
+        }
+
+        let Some(buffer) = self.buffers.get_mut(id) else {
+            return;
+        };
+
+        let restored = buffer.view.take().unwrap_or_else(|| View::new(id));
+        let old = view.replace(restored);
+
+        if let Some(buffer) = self.buffers.get_mut(old.buffer) {
+            buffer.view = Some(old);
+        }
+    }
+
+    /// Closes a buffer, showing another one in the windows that showed it.
+    pub fn close_buffer(&mut self, id: usize) {
+        let Some(replacement) = self.buffers.close(id) else {
+            return;
+        };
+
+        for (_, view) in self.windows.iter_mut() {
+            if view.buffer == id {
+                *view = View::new(replacement);
+            }
+        }
+    }
+
+    /// Reloads the focused buffer from its file.
+    pub fn reload(&mut self) -> io::Result<()> {
+        let id = self.windows.focused().buffer;
+        self.buffers.reload(id, &self.languages)?;
+        self.lsp.close(id);
+        self.report_language_error();
+        self.apply_quickfix(id);
+
+        let buffer = self.buffers.get_mut(id).expect("windows show open buffers");
+
+        for (_, view) in self.windows.iter_mut() {
+            if view.buffer == id {
+                view.clamp(&buffer.doc);
+            }
         }
+
+        buffer.view = None;
+        Ok(())
     }
 
     pub fn view(&mut self, frame: &mut Frame) {
+        self.receive_highlights();
+        self.receive_make();
+
+        for err in self.lsp.update(&mut self.buffers) {
+            self.report_error(err);
+        }
+
+        if let Some((buffer, byte, items)) = self.lsp.take_completions()
+            && let Some(completion) = &mut self.completion
+            && completion.buffer() == buffer
+            && completion.origin() == byte
+        {
+            completion.extend(items);
+        }
+
         let [editor, input_area] =
             Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
 
-        if let Some(pos) =
-            self.doc
-                .render(&mut self.language, &self.theme, editor, frame.buffer_mut())
+        let buf = frame.buffer_mut();
+
+        let separator = self.theme.editor.patch(self.theme.line_numbers);
+        for pos in editor.positions() {
+            buf[pos].set_symbol(line::VERTICAL).set_style(separator);
+        }
+
+        let focus = self.windows.focus();
+        let mut cursor = None;
+
+        for (id, area) in self.windows.layout(editor) {
+            let view = self.windows.get_mut(id).unwrap();
+            let buffer = self
+                .buffers
+                .get_mut(view.buffer)
+                .expect("windows show open buffers");
+            let name = buffer.display_name();
+
+            let pos = view.render(
+                &mut buffer.doc,
+                id,
+                &buffer.language,
+                &mut self.highlighter,
+                &self.theme,
+                self.options.line_numbers,
+                self.search.as_mut(),
+                &name,
+                area,
+                buf,
+            );
+
+            if id == focus {
+                cursor = pos;
+            }
+        }
+
+        // Closed windows and those showing another buffer keep no highlights.
+        for buffer in self.buffers.iter_mut() {
+            let id = buffer.id();
+            buffer.doc.retain_highlights(|window| {
+                self.windows
+                    .get(window)
+                    .is_some_and(|view| view.buffer == id)
+            });
+        }
+
+        if let Some(pos) = cursor {
+            frame.set_cursor_position(pos);
+
+            if let Some(completion) = &mut self.completion {
+                completion.render(&self.theme, pos, editor, frame.buffer_mut());
+            }
+        }
+
+        if let Some(preview) = &self.preview {
+            let [preview_area, _] =
+                Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(editor);
+            preview.render(&self.theme, preview_area, frame.buffer_mut());
+        }
+
+        if let Some(picker) = &mut self.picker
+            && let Some(pos) = picker.render(
+                &self.theme,
+                &self.languages,
+                &mut self.highlighter,
+                editor,
+                frame.buffer_mut(),
+            )
         {
             frame.set_cursor_position(pos);
         }
@@ -97,100 +330,540 @@ impl App {
                     .red()
                     .render(input_area, frame.buffer_mut());
             }
+            None if let Some(message) = &self.message => {
+                Paragraph::new(message.as_str())
+                    .style(self.theme.editor)
+                    .render(input_area, frame.buffer_mut());
+            }
+            None if let Some(diagnostic) = self.cursor_diagnostic() => {
+                let style = self.theme.get(diagnostic.severity.name());
+                Paragraph::new(diagnostic.message.as_str())
+                    .style(self.theme.editor.patch(style.unwrap_or_default()))
+                    .render(input_area, frame.buffer_mut());
+            }
             None => frame.buffer_mut().set_style(input_area, self.theme.editor),
         }
+
+        if self.input.is_none() && !self.typed.is_empty() {
+            let keys: String = self.typed.iter().map(Key::to_string).collect();
+
+            Paragraph::new(format!("{keys} "))
+                .alignment(Alignment::Right)
+                .render(input_area, frame.buffer_mut());
+        }
     }
 
-    pub fn handle_ev(&mut self, ev: Event) {
-        match ev {
-            Event::Key(KeyEvent {
-                code,
-                kind: KeyEventKind::Press,
-                ..
-            }) => match code {
-                KeyCode::Esc => {
-                    if self.input.is_none() {
-                        self.doc.enter_normal();
-                    }
+    /// The most severe diagnostic on the cursor line, shown in place of
+    /// messages.
+    fn cursor_diagnostic(&self) -> Option<&Diagnostic> {
+        let (view, doc) = self.current();
+        let (_, line_idx) = view.position(doc);
+        let line = doc.rope().line_to_byte(line_idx)..doc.rope().line_to_byte(line_idx + 1);
 
-                    self.input = None;
-                    self.err = None;
-                }
+        doc.diagnostics()
+            .iter()
+            .filter(|diagnostic| line.contains(&diagnostic.range.start))
+            .max_by_key(|diagnostic| diagnostic.severity)
+    }
 
-                KeyCode::Char(ch) if let Some(input) = self.input.as_mut() => input.insert(ch),
-                KeyCode::Backspace if let Some(input) = self.input.as_mut() => input.remove(),
-                KeyCode::Enter if let Some(input) = self.input.take() => input.submit(self),
-                key if self.doc.mode() == Mode::Insert => match key {
-                    KeyCode::Enter => self.doc.insert('\n'),
-                    KeyCode::Char(ch) => self.doc.insert(ch),
-                    KeyCode::Backspace => self.doc.remove_before(),
-                    KeyCode::Tab => {
-                        let (x, _) = self.doc.position();
-                        let spaces = if x % 4 == 0 { 4 } else { 4 - x % 4 };
-                        for _ in 0..spaces {
-                            self.doc.insert(' ');
-                        }
-                    }
-                    _ => {}
-                },
-                KeyCode::Char(':') => self.open_command(),
-                KeyCode::Char(key) => {
-                    if let Some(command) = self.keymap.get(key).cloned() {
-                        command.run(self, ());
-                    }
-                }
-                _ => {}
-            },
+    pub fn handle_ev(&mut self, ev: Event) {
+        match ev {
+            Event::Key(event) if event.kind == KeyEventKind::Press => self.handle_key(event.into()),
             Event::Mouse(MouseEvent {
                 kind: MouseEventKind::ScrollUp,
                 ..
             }) => {
-                self.doc.scroll_up();
-                self.doc.move_to_view();
+                let (view, doc) = self.current_mut();
+                view.scroll_up(doc);
+                view.move_to_view(doc);
             }
             Event::Mouse(MouseEvent {
                 kind: MouseEventKind::ScrollDown,
                 ..
             }) => {
-                self.doc.scroll_up();
-                self.doc.move_to_view();
+                let (view, doc) = self.current_mut();
+                view.scroll_down(doc);
+                view.move_to_view(doc);
             }
             _ => {}
         }
+
+        self.sync_views();
+    }
+
+    /// Runs the command bound to the pending keys once no longer sequence
+    /// followed them in time.
+    pub fn handle_timeout(&mut self) {
+        if self.key_timeout().is_some_and(|timeout| timeout.is_zero()) {
+            self.flush_keys();
+            self.sync_views();
+        }
+    }
+
+    /// How long to wait for an event before calling
+    /// [`App::handle_timeout`] and drawing again, if at all.
+    pub fn poll_timeout(&self) -> Option<Duration> {
+        let background = (self.highlighter.is_busy()
+            || self.make.is_some()
+            || self.picker.as_ref().is_some_and(Picker::is_loading))
+        .then_some(BACKGROUND_POLL);
+        let lsp = self.lsp.is_running().then_some(lsp::POLL);
+        // Only shown documents ask for their highlights again.
+        let retry = (self.windows.iter())
+            .filter_map(|(_, view)| self.buffers.get(view.buffer)?.doc.retry_in())
+            .min();
+        self.key_timeout()
+            .into_iter()
+            .chain(background)
+            .chain(lsp)
+            .chain(retry)
+            .min()
+    }
+
+    /// How long to wait for the next key of a pending sequence.
+    fn key_timeout(&self) -> Option<Duration> {
+        (!self.pending_keys.is_empty()).then(|| {
+            self.options
+                .timeout
+                .saturating_sub(self.pending_since.elapsed())
+        })
+    }
+
+    /// Fills the quickfix list once the `:make` command is done.
+    fn receive_make(&mut self) {
+        let Some(result) = self.make.as_ref().and_then(|make| make.try_recv().ok()) else {
+            return;
+        };
+
+        self.make = None;
+
+        let entries = match result {
+            Ok(entries) => entries,
+            Err(err) => return self.report_error(err),
+        };
+
+        let count = |severity| {
+            let count = entries
+                .iter()
+                .filter(|entry| entry.severity == severity)
+                .count();
+            let plural = if count == 1 { "" } else { "s" };
+            format!("{count} {}{plural}", severity.name())
+        };
+        let message = format!(
+            "make: {}, {}",
+            count(Severity::Error),
+            count(Severity::Warning)
+        );
+
+        self.quickfix = Quickfix::new(entries);
+
+        let ids: Vec<usize> = self.buffers.iter().map(Buffer::id).collect();
+        for id in ids {
+            self.apply_quickfix(id);
+        }
+
+        self.report_message(message);
+    }
+
+    /// Hands the highlighter's responses to their documents.
+    fn receive_highlights(&mut self) {
+        for response in self.highlighter.receive() {
+            if response.buffer == picker::PREVIEW_BUFFER
+                && let Some(picker) = &mut self.picker
+            {
+                picker.receive_highlights(response);
+            } else if let Some(buffer) = self.buffers.get_mut(response.buffer) {
+                buffer.doc.receive_highlights(response);
+            }
+        }
+    }
+
+    pub fn handle_key(&mut self, key: Key) {
+        // Only characters typed in insert mode open the completion popup.
+        let typed = key.char().is_some() && self.windows.focused().mode() == Mode::Insert;
+
+        if let Some(recording) = &mut self.recording {
+            recording.push(key);
+
+            if key.code == KeyCode::Esc
+                && let Some(change) = &mut self.last_change
+            {
+                change.inserted = self.recording.take().unwrap_or_default();
+            }
+        }
+
+        match key.code {
+            _ if self.picker.is_some() => self.picker_key(key),
+            KeyCode::Esc => {
+                match self.input.take() {
+                    Some(input) => input.cancel(self),
+                    None => {
+                        let (view, doc) = self.current_mut();
+                        view.enter_normal(doc);
+                    }
+                }
+
+                self.pending_key = None;
+                self.pending_keys.clear();
+                self.typed.clear();
+                self.count = None;
+                self.operator = None;
+                self.preview = None;
+                self.err = None;
+                self.message = None;
+            }
+            KeyCode::Char(ch) if let Some(input) = self.input.as_mut() => {
+                input.insert(ch);
+                self.input_changed();
+            }
+            KeyCode::Backspace if let Some(input) = self.input.as_mut() => {
+                input.remove();
+                self.input_changed();
+            }
+            KeyCode::Enter if let Some(input) = self.input.take() => input.submit(self),
+            _ if self.input.is_some() => {}
+            KeyCode::Char(ch) if let Some(command) = self.pending_key.take() => {
+                command.run(self, ch)
+            }
+            _ if self.completion_key(key) => return,
+            _ => self.press(key),
+        }
+
+        self.update_completion(typed);
+    }
+
+    /// Moves through the items of the completion popup and inserts the
+    /// highlighted one. Returns false for other keys, or while no item
+    /// matches.
+    fn completion_key(&mut self, key: Key) -> bool {
+        let Some(completion) = self.completion.as_mut().filter(|c| !c.is_empty()) else {
+            return false;
+        };
+
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+
+        match key.code {
+            KeyCode::Tab => completion.move_by(1),
+            KeyCode::BackTab => completion.move_by(-1),
+            KeyCode::Char('n') if ctrl => completion.move_by(1),
+            KeyCode::Char('p') if ctrl => completion.move_by(-1),
+            KeyCode::Enter if let Some(item) = completion.selected() => {
+                let (start, text) = (completion.start(), item.text.clone());
+                self.completion = None;
+
+                let (view, doc) = self.current_mut();
+                view.replace_before(doc, start, &text);
+            }
+            _ => return false,
+        }
+
+        true
+    }
+
+    /// Opens the completion popup for the word or path before the cursor,
+    /// with the words of the open buffers, the local variables in scope and
+    /// the items of the buffer's language server, or the files of the
+    /// directory typed.
+    pub fn complete(&mut self) {
+        let (view, doc) = self.current();
+        let cursor = view.position_byte();
+        let context = completion::context(doc.rope(), cursor);
+        let query = doc.rope().byte_slice(context.start..cursor).to_string();
+        let buffer = self.buffer();
+        let id = buffer.id();
+
+        let items = match &context.dir {
+            Some(dir) => completion::paths(dir),
+            None => {
+                let locals = (buffer.language.grammar())
+                    .and_then(|grammar| grammar.locals())
+                    .zip(doc.tree())
+                    .map(|(query, tree)| completion::locals(query, tree, doc.rope(), cursor))
+                    .unwrap_or_default();
+
+                // Not the word being typed, unless it is also elsewhere.
+                let typed = completion::word_at(doc.rope(), cursor);
+                let mut words = BTreeSet::new();
+                for other in self.buffers.iter_mut() {
+                    let own = other.id() == id;
+                    for (word, count) in other.doc.words().iter() {
+                        if count > usize::from(own && word == typed) {
+                            words.insert(word.to_owned());
+                        }
+                    }
+                }
+
+                let locals = locals
+                    .into_iter()
+                    .map(|name| Item::new(name, Source::Local));
+                let words = words.into_iter().map(|word| Item::new(word, Source::Word));
+                locals.chain(words).collect()
+            }
+        };
+
+        if context.dir.is_none() {
+            self.lsp.request_completions(id, cursor);
+        }
+
+        let mut completion = Completion::new(id, context.start, cursor, items);
+        completion.filter(&query);
+        self.completion = Some(completion);
+    }
+
+    /// Opens the completion popup as a word or path is typed in insert mode,
+    /// filters it as typing goes on, and closes it once the cursor leaves
+    /// what it completes.
+    fn update_completion(&mut self, typed: bool) {
+        let (view, doc) = self.current();
+
+        if view.mode() != Mode::Insert || self.input.is_some() || self.picker.is_some() {
+            self.completion = None;
+            return;
+        }
+
+        let (buffer, cursor) = (view.buffer, view.position_byte());
+        let context = completion::context(doc.rope(), cursor);
+        let query = doc.rope().byte_slice(context.start..cursor).to_string();
+
+        match &mut self.completion {
+            Some(completion)
+                if completion.buffer() == buffer && completion.start() == context.start =>
+            {
+                completion.filter(&query)
+            }
+            _ if typed && context.triggers(&query) => self.complete(),
+            _ => self.completion = None,
+        }
+    }
+
+    /// Filters and moves through the items of the open picker.
+    fn picker_key(&mut self, key: Key) {
+        let Some(mut picker) = self.picker.take() else {
+            return;
+        };
+
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+
+        match key.code {
+            KeyCode::Esc => return picker.cancel(self),
+            KeyCode::Enter => return picker.submit(self),
+            KeyCode::Up | KeyCode::BackTab => picker.move_by(-1),
+            KeyCode::Down | KeyCode::Tab => picker.move_by(1),
+            KeyCode::Char('p') if ctrl => picker.move_by(-1),
+            KeyCode::Char('n') if ctrl => picker.move_by(1),
+            KeyCode::Char(ch) if !ctrl => picker.insert(ch),
+            KeyCode::Backspace => picker.remove(),
+            _ => {}
+        }
+
+        picker.change(self);
+
+        if self.picker.is_none() {
+            self.picker = Some(picker);
+        }
+    }
+
+    /// The count typed before the current command, if any.
+    pub fn take_count(&mut self) -> Option<usize> {
+        self.count.take()
+    }
+
+    /// The bindings for the next key: those of the mode, or the motions and
+    /// text objects while an operator is pending.
+    fn bindings(&self) -> &KeyTrie {
+        match self.operator {
+            Some(_) => &self.keymap.operator,
+            None => self.keymap.get(self.windows.focused().mode()),
+        }
+    }
+
+    /// Adds `key` to the pending sequence and runs the command it completes.
+    fn press(&mut self, key: Key) {
+        let mode = self.windows.focused().mode();
+
+        if mode != Mode::Insert
+            && self.pending_keys.is_empty()
+            && let Some(digit) = key.char().and_then(|ch| ch.to_digit(10))
+            && (digit != 0 || self.count.is_some())
+        {
+            let count = self.count.unwrap_or(0);
+            self.count = Some(count.saturating_mul(10).saturating_add(digit as usize));
+            self.typed.push(key);
+            return;
+        }
+
+        self.pending_keys.push(key);
+        self.typed.push(key);
+
+        match self.bindings().get(&self.pending_keys) {
+            Some(node) if node.is_prefix() => self.pending_since = Instant::now(),
+            Some(node) => {
+                let command = node.command().cloned();
+                self.pending_keys.clear();
+
+                if let Some(command) = command {
+                    command.run(self, ());
+                }
+
+                self.command_done();
+            }
+            None => {
+                self.pending_keys.pop();
+
+                if self.pending_keys.is_empty() {
+                    self.type_key(key);
+                    self.command_done();
+                } else {
+                    self.typed.pop();
+                    self.flush_keys();
+                    self.press(key);
+                }
+            }
+        }
+    }
+
+    /// Runs the command bound to the pending keys, if any, or types them.
+    fn flush_keys(&mut self) {
+        let keys = std::mem::take(&mut self.pending_keys);
+
+        match self.bindings().get(&keys).and_then(KeyTrie::command) {
+            Some(command) => command.clone().run(self, ()),
+            None => {
+                for key in keys {
+                    self.type_key(key);
+                }
+            }
+        }
+
+        self.command_done();
+    }
+
+    /// Clears the typed keys once they no longer lead to a command.
+    fn command_done(&mut self) {
+        if self.operator.is_none() {
+            self.count = None;
+            self.typed.clear();
+        }
+    }
+
+    /// Inserts the character of an unbound key in insert mode, and cancels a
+    /// pending operator.
+    fn type_key(&mut self, key: Key) {
+        self.operator = None;
+
+        if self.windows.focused().mode() == Mode::Insert
+            && let Some(ch) = key.char()
+        {
+            let (view, doc) = self.current_mut();
+            view.insert(doc, ch);
+        }
+    }
+
+    /// Moves the cursors of the views that did not make the latest edits
+    /// along with them.
+    fn sync_views(&mut self) {
+        let focus = self.windows.focus();
+
+        for buffer in self.buffers.iter_mut() {
+            let edits = buffer.doc.take_edits();
+
+            if edits.is_empty() {
+                continue;
+            }
+
+            for (id, view) in self.windows.iter_mut() {
+                if id != focus && view.buffer == buffer.id() {
+                    view.map_edits(&buffer.doc, &edits);
+                }
+            }
+
+            if let Some(view) = &mut buffer.view {
+                view.map_edits(&buffer.doc, &edits);
+            }
+        }
     }
 
     pub fn exit(&mut self) {
         self.exit = true;
     }
 
-    pub fn report_error(&mut self, err: impl Error + 'static) {
-        self.err = Some(Box::new(err));
+    pub fn report_error(&mut self, err: impl Into<Box<dyn Error>>) {
+        self.err = Some(err.into());
     }
 
-    fn run_command(&mut self, command: &str) -> Result<(), String> {
-        let (name, _) = command.split_once(' ').unwrap_or((command, ""));
+    pub fn report_message(&mut self, message: impl Into<String>) {
+        self.err = None;
+        self.message = Some(message.into());
+    }
+
+    fn run_command(&mut self, line: &str) -> Result<(), CommandError> {
+        let line = cmdline::parse(line)?;
+
+        let (view, doc) = self.current();
+        let (_, current) = view.position(doc);
+        let last = doc.rope().len_lines().saturating_sub(1);
+        let selection =
+            matches!(view.mode(), Mode::Select { .. }).then(|| view.selected_lines(doc));
+
+        let range = line
+            .range
+            .map(|range| range.resolve(current, last, selection))
+            .transpose()?;
+
+        if line.name.is_empty() {
+            if let Some(range) = range {
+                let (view, doc) = self.current_mut();
+                view.goto_line(doc, range.end - 1);
+                view.scroll_to_cursor(doc);
+            }
 
-        if name == "q" {
-            self.exit();
             return Ok(());
         }
 
-        let Some(command) = self.commands.remove(name) else {
-            return Err(format!("command not found: {name}"));
+        let Some(command) = self.commands.remove(line.name) else {
+            return Err(CommandError::NotFound(line.name.to_owned()));
         };
 
-        self.err = None;
-        command.run(self, ());
+        let result = command.parse_args(&line, range).map(|args| {
+            self.err = None;
+            self.message = None;
+            command.run(self, args);
+        });
 
-        self.commands.insert(name.to_owned(), command);
-        Ok(())
+        self.commands.insert(line.name.to_owned(), command);
+        result
+    }
+
+    fn input_changed(&mut self) {
+        let Some(input) = self.input.take() else {
+            return;
+        };
+
+        input.change(self);
+
+        if self.input.is_none() {
+            self.input = Some(input);
+        }
+    }
+
+    /// Binds `keys`, written as in the README, to the action named `action`.
+    pub fn bind(&mut self, mode: KeyMode, keys: &str, action: &str) -> Result<(), String> {
+        let action = self
+            .actions
+            .get(action)
+            .cloned()
+            .ok_or_else(|| format!("unknown action: {action}"))?;
+        let keys = keymap::parse_keys(keys)?;
+
+        self.keymap.bind(mode, &keys, action)
     }
 
-    fn open_command(&mut self) {
+    pub fn open_command(&mut self) {
         self.input = Some(
             Input::new(|cmd, app| {
                 if let Err(err) = app.run_command(&cmd) {
-                    app.err = Some(err.into());
+                    app.report_error(err);
                 }
             })
             .with_symbol(':'),
@@ -0,0 +1,208 @@
+use ratatui::{
+    Terminal,
+    backend::{self, ClearType, CrosstermBackend, WindowSize},
+    buffer::Cell,
+    crossterm::{
+        cursor::MoveTo,
+        execute, queue,
+        style::{
+            Attribute, Color as CColor, Colors, Print, SetAttribute, SetBackgroundColor, SetColors,
+            SetForegroundColor, SetUnderlineColor,
+        },
+        terminal::{self, EnterAlternateScreen},
+    },
+    layout::{Position, Size},
+    style::Modifier,
+};
+use std::{
+    io::{self, Stdout, stdout},
+    panic,
+};
+
+/// Underline styles that ratatui has no modifier for, kept in modifier bits
+/// it does not use. They replace the straight line of
+/// [`Modifier::UNDERLINED`], and do nothing without it.
+pub const DOUBLE_UNDERLINE: Modifier = Modifier::from_bits_retain(1 << 9);
+pub const UNDERCURL: Modifier = Modifier::from_bits_retain(1 << 10);
+pub const UNDERDOTTED: Modifier = Modifier::from_bits_retain(1 << 11);
+pub const UNDERDASHED: Modifier = Modifier::from_bits_retain(1 << 12);
+
+const ATTRIBUTES: [(Modifier, Attribute); 8] = [
+    (Modifier::BOLD, Attribute::Bold),
+    (Modifier::DIM, Attribute::Dim),
+    (Modifier::ITALIC, Attribute::Italic),
+    (Modifier::SLOW_BLINK, Attribute::SlowBlink),
+    (Modifier::RAPID_BLINK, Attribute::RapidBlink),
+    (Modifier::REVERSED, Attribute::Reverse),
+    (Modifier::HIDDEN, Attribute::Hidden),
+    (Modifier::CROSSED_OUT, Attribute::CrossedOut),
+];
+
+const UNDERLINES: [(Modifier, Attribute); 4] = [
+    (DOUBLE_UNDERLINE, Attribute::DoubleUnderlined),
+    (UNDERCURL, Attribute::Undercurled),
+    (UNDERDOTTED, Attribute::Underdotted),
+    (UNDERDASHED, Attribute::Underdashed),
+];
+
+/// The crossterm backend, drawing cells with the underline styles above.
+pub struct Backend(CrosstermBackend<Stdout>);
+
+/// Like [`ratatui::init`], with [`Backend`].
+pub fn init() -> Terminal<Backend> {
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        ratatui::restore();
+        hook(info);
+    }));
+
+    terminal::enable_raw_mode().expect("failed to enable raw mode");
+    execute!(stdout(), EnterAlternateScreen).expect("failed to enter the alternate screen");
+
+    Terminal::new(Backend(CrosstermBackend::new(stdout()))).expect("failed to create terminal")
+}
+
+impl backend::Backend for Backend {
+    fn draw<'a, I>(&mut self, content: I) -> io::Result<()>
+    where
+        I: Iterator<Item = (u16, u16, &'a Cell)>,
+    {
+        let writer = &mut self.0;
+        let mut last: Option<(u16, u16)> = None;
+        let mut style = None;
+
+        for (x, y, cell) in content {
+            if !matches!(last, Some((last_x, last_y)) if x == last_x + 1 && y == last_y) {
+                queue!(writer, MoveTo(x, y))?;
+            }
+            last = Some((x, y));
+
+            // Attributes cannot be turned off one by one for every underline
+            // style, so a change of style starts over from a reset.
+            let cell_style = (cell.fg, cell.bg, cell.underline_color, cell.modifier);
+            if style != Some(cell_style) {
+                queue!(writer, SetAttribute(Attribute::Reset))?;
+
+                for attribute in attributes(cell.modifier) {
+                    queue!(writer, SetAttribute(attribute))?;
+                }
+
+                queue!(
+                    writer,
+                    SetColors(Colors::new(cell.fg.into(), cell.bg.into())),
+                    SetUnderlineColor(cell.underline_color.into()),
+                )?;
+                style = Some(cell_style);
+            }
+
+            queue!(writer, Print(cell.symbol()))?;
+        }
+
+        queue!(
+            writer,
+            SetForegroundColor(CColor::Reset),
+            SetBackgroundColor(CColor::Reset),
+            SetUnderlineColor(CColor::Reset),
+            SetAttribute(Attribute::Reset),
+        )
+    }
+
+    fn append_lines(&mut self, n: u16) -> io::Result<()> {
+        self.0.append_lines(n)
+    }
+
+    fn hide_cursor(&mut self) -> io::Result<()> {
+        self.0.hide_cursor()
+    }
+
+    fn show_cursor(&mut self) -> io::Result<()> {
+        self.0.show_cursor()
+    }
+
+    fn get_cursor_position(&mut self) -> io::Result<Position> {
+        self.0.get_cursor_position()
+    }
+
+    fn set_cursor_position<P: Into<Position>>(&mut self, position: P) -> io::Result<()> {
+        self.0.set_cursor_position(position)
+    }
+
+    fn clear(&mut self) -> io::Result<()> {
+        self.0.clear()
+    }
+
+    fn clear_region(&mut self, clear_type: ClearType) -> io::Result<()> {
+        self.0.clear_region(clear_type)
+    }
+
+    fn size(&self) -> io::Result<Size> {
+        self.0.size()
+    }
+
+    fn window_size(&mut self) -> io::Result<WindowSize> {
+        self.0.window_size()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        backend::Backend::flush(&mut self.0)
+    }
+}
+
+/// The attributes of `modifier`, with at most one underline.
+fn attributes(modifier: Modifier) -> impl Iterator<Item = Attribute> {
+    let underline = modifier.contains(Modifier::UNDERLINED).then(|| {
+        UNDERLINES
+            .iter()
+            .find(|(style, _)| modifier.contains(*style))
+            .map_or(Attribute::Underlined, |(_, attribute)| *attribute)
+    });
+
+    ATTRIBUTES
+        .into_iter()
+        .filter(move |(flag, _)| modifier.contains(*flag))
+        .map(|(_, attribute)| attribute)
+        .chain(underline)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::{
+        buffer::Buffer,
+        layout::Rect,
+        style::{Color, Style},
+    };
+
+    #[test]
+    fn underline_styles_survive_patching() {
+        let curl = Style::new()
+            .add_modifier(Modifier::UNDERLINED | UNDERCURL)
+            .underline_color(Color::Red);
+        let style = Style::new().fg(Color::Blue).patch(curl);
+
+        let mut buf = Buffer::empty(Rect::new(0, 0, 1, 1));
+        buf.set_style(buf.area, style);
+        let cell = &buf[(0, 0)];
+
+        assert_eq!(
+            attributes(cell.modifier).collect::<Vec<_>>(),
+            [Attribute::Undercurled]
+        );
+        assert_eq!(cell.underline_color, Color::Red);
+    }
+
+    #[test]
+    fn underline_styles_need_underlined() {
+        let attributes = |modifier| attributes(modifier).collect::<Vec<_>>();
+
+        assert_eq!(attributes(UNDERDASHED), []);
+        assert_eq!(
+            attributes(Modifier::BOLD | Modifier::UNDERLINED),
+            [Attribute::Bold, Attribute::Underlined]
+        );
+        assert_eq!(
+            attributes(Modifier::UNDERLINED | DOUBLE_UNDERLINE),
+            [Attribute::DoubleUnderlined]
+        );
+    }
+}
@@ -0,0 +1,103 @@
+extern crate test;
+
+use ropey::Rope;
+use std::{ops::Range, sync::Arc, thread};
+use test::Bencher;
+
+use crate::{
+    document::Document,
+    highlighter::Highlighter,
+    language::{Language, Languages},
+};
+
+/// Lines shown in a window, as highlighted per frame.
+const VIEWPORT: usize = 50;
+
+/// A Rust file of about 20,000 lines, made of copies of ion's sources.
+fn large_file() -> Document {
+    let sources = [
+        include_str!("app.rs"),
+        include_str!("default.rs"),
+        include_str!("document.rs"),
+        include_str!("view.rs"),
+    ];
+
+    let text = sources
+        .concat()
+        .repeat(20_000 / sources.concat().lines().count() + 1);
+    Document::new(Rope::from_str(&text))
+}
+
+/// Requests the highlights of `range` and waits for them.
+fn highlight(
+    doc: &mut Document,
+    language: &Language,
+    range: Range<usize>,
+    highlighter: &mut Highlighter,
+) {
+    doc.request_highlights(0, 0, language, range, highlighter);
+
+    while highlighter.is_busy() {
+        for response in highlighter.receive() {
+            doc.receive_highlights(response);
+        }
+        thread::yield_now();
+    }
+}
+
+/// Highlights `lines` of a large file after a setup step, which can clear
+/// the cached highlights or edit the text.
+fn bench(b: &mut Bencher, lines: Option<usize>, mut setup: impl FnMut(&mut Document, usize)) {
+    let languages = Arc::new(Languages::new(Vec::new()));
+    let language = languages.load("rust").unwrap();
+    let mut highlighter = Highlighter::new(languages);
+    let mut doc = large_file();
+
+    let rope = doc.rope();
+    let start = rope.line_to_byte(rope.len_lines() / 2);
+    let end = lines.map_or(rope.len_bytes(), |lines| {
+        rope.line_to_byte(rope.len_lines() / 2 + lines)
+    });
+
+    highlight(&mut doc, &language, start..end, &mut highlighter);
+
+    b.iter(|| {
+        setup(&mut doc, start);
+        highlight(&mut doc, &language, start..end, &mut highlighter);
+        doc.highlights(0).len()
+    });
+}
+
+/// What every frame used to do.
+#[bench]
+fn whole_file(b: &mut Bencher) {
+    bench(b, None, |doc, _| doc.clear_highlights());
+}
+
+#[bench]
+fn viewport(b: &mut Bencher) {
+    bench(b, Some(VIEWPORT), |doc, _| doc.clear_highlights());
+}
+
+#[bench]
+fn viewport_cached(b: &mut Bencher) {
+    bench(b, Some(VIEWPORT), |_, _| {});
+}
+
+/// Typing in the viewport, which parses again and invalidates the cache.
+#[bench]
+fn viewport_typing(b: &mut Bencher) {
+    bench(b, Some(VIEWPORT), |doc, start| {
+        doc.replace(start..start, " ");
+        doc.replace(start..start + 1, "");
+    });
+}
+
+/// Typing above the viewport, which keeps the cache.
+#[bench]
+fn viewport_typing_above(b: &mut Bencher) {
+    bench(b, Some(VIEWPORT), |doc, _| {
+        doc.replace(0..0, " ");
+        doc.replace(0..1, "");
+    });
+}
@@ -0,0 +1,191 @@
+use ropey::Rope;
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    path::{self, Path, PathBuf},
+};
+
+use crate::{
+    document::Document,
+    language::{Language, Languages},
+    view::View,
+};
+
+pub struct Buffer {
+    id: usize,
+    pub doc: Document,
+    pub path: Option<PathBuf>,
+    pub language: Language,
+    /// The view of the last window that switched away from this buffer, to
+    /// restore the cursor when coming back.
+    pub view: Option<View>,
+}
+
+impl Buffer {
+    fn new(id: usize, doc: Document, path: Option<PathBuf>, language: Language) -> Self {
+        Self {
+            id,
+            doc,
+            path,
+            language,
+            view: None,
+        }
+    }
+
+    pub fn set_language(&mut self, language: Language) {
+        self.language = language;
+        self.doc.clear_tree();
+    }
+
+    /// The number shown by `:ls` and used by `:b`, starting at 1.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn display_name(&self) -> String {
+        match &self.path {
+            Some(path) => path.display().to_string(),
+            None => "[scratch]".into(),
+        }
+    }
+
+    fn is_path(&self, path: &Path) -> bool {
+        self.path.as_deref().is_some_and(|own| same_file(own, path))
+    }
+
+    /// Whether this is the empty buffer ion starts with when no file is given.
+    fn is_scratch(&self) -> bool {
+        self.path.is_none() && !self.doc.is_modified() && self.doc.rope().len_bytes() == 0
+    }
+}
+
+/// The open buffers. There always is at least one.
+pub struct Buffers {
+    list: Vec<Buffer>,
+    last_id: usize,
+}
+
+impl Buffers {
+    pub fn get(&self, id: usize) -> Option<&Buffer> {
+        self.list.iter().find(|buffer| buffer.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Buffer> {
+        self.list.iter_mut().find(|buffer| buffer.id == id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Buffer> {
+        self.list.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Buffer> {
+        self.list.iter_mut()
+    }
+
+    /// Returns the id of the buffer of `path`, loading it if it is not open
+    /// yet. A missing file opens an empty buffer that is created on write. If
+    /// `current` is an untouched scratch buffer, it is replaced by the file.
+    pub fn open(
+        &mut self,
+        path: &Path,
+        current: usize,
+        languages: &Languages,
+    ) -> io::Result<usize> {
+        if let Some(buffer) = self.list.iter().find(|buffer| buffer.is_path(path)) {
+            return Ok(buffer.id);
+        }
+
+        let text = load(path)?;
+        let language = languages.detect(Some(path), &text);
+        let doc = Document::new(text);
+
+        if let Some(buffer) = self.get_mut(current)
+            && buffer.is_scratch()
+        {
+            *buffer = Buffer::new(current, doc, Some(path.into()), language);
+            return Ok(current);
+        }
+
+        let id = self.next_id();
+        self.list
+            .push(Buffer::new(id, doc, Some(path.into()), language));
+        Ok(id)
+    }
+
+    /// Reloads a buffer from its file, detecting its language again.
+    pub fn reload(&mut self, id: usize, languages: &Languages) -> io::Result<()> {
+        if let Some(buffer) = self.get_mut(id)
+            && let Some(path) = &buffer.path
+        {
+            let text = load(path)?;
+            buffer.language = languages.detect(Some(path), &text);
+            buffer.doc = Document::new(text);
+        }
+
+        Ok(())
+    }
+
+    /// The id of the buffer after `id`, wrapping around.
+    pub fn next(&self, id: usize) -> usize {
+        let idx = self.position(id);
+        self.list[(idx + 1) % self.list.len()].id
+    }
+
+    /// The id of the buffer before `id`, wrapping around.
+    pub fn prev(&self, id: usize) -> usize {
+        let idx = self.position(id);
+        self.list[idx.checked_sub(1).unwrap_or(self.list.len() - 1)].id
+    }
+
+    /// Closes the buffer with the given id, returning the id of the buffer to
+    /// show in its place. The last buffer is replaced with a scratch buffer.
+    pub fn close(&mut self, id: usize) -> Option<usize> {
+        let idx = self.list.iter().position(|buffer| buffer.id == id)?;
+
+        if self.list.len() == 1 {
+            let id = self.next_id();
+            self.list[0] = Buffer::new(id, Document::default(), None, Language::plain());
+            return Some(id);
+        }
+
+        self.list.remove(idx);
+        Some(self.list[idx.min(self.list.len() - 1)].id)
+    }
+
+    fn position(&self, id: usize) -> usize {
+        self.list
+            .iter()
+            .position(|buffer| buffer.id == id)
+            .unwrap_or_default()
+    }
+
+    fn next_id(&mut self) -> usize {
+        self.last_id += 1;
+        self.last_id
+    }
+}
+
+impl Default for Buffers {
+    fn default() -> Self {
+        Self {
+            list: vec![Buffer::new(1, Document::default(), None, Language::plain())],
+            last_id: 1,
+        }
+    }
+}
+
+fn load(path: &Path) -> io::Result<Rope> {
+    match File::open(path) {
+        Ok(file) => Rope::from_reader(BufReader::new(file)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Rope::new()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Whether `a` and `b` are the same file, also if it does not exist yet.
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => path::absolute(a).ok() == path::absolute(b).ok(),
+    }
+}
@@ -0,0 +1,318 @@
+use std::{error::Error, fmt, ops::Range};
+
+/// A command line split into its parts, e.g. `'<,'>s/a/b/` or `w! file.rs`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandLine<'a> {
+    pub range: Option<RangeSpec>,
+    pub name: &'a str,
+    pub bang: bool,
+    pub args: &'a str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeSpec {
+    All,
+    Lines(Address, Address),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Address {
+    base: Base,
+    offset: isize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Base {
+    /// A line number, starting at 1.
+    Line(usize),
+    Current,
+    Last,
+    SelectionStart,
+    SelectionEnd,
+}
+
+impl RangeSpec {
+    /// Resolves the range to line indexes, given the cursor line, the last
+    /// line and the lines covered by the selection.
+    pub fn resolve(
+        self,
+        current: usize,
+        last: usize,
+        selection: Option<Range<usize>>,
+    ) -> Result<Range<usize>, CommandError> {
+        let (start, end) = match self {
+            Self::All => return Ok(0..last + 1),
+            Self::Lines(start, end) => (start, end),
+        };
+
+        let resolve = |address: Address| {
+            let line = match address.base {
+                Base::Line(line) => line.saturating_sub(1),
+                Base::Current => current,
+                Base::Last => last,
+                Base::SelectionStart => selection.clone().ok_or(CommandError::NoSelection)?.start,
+                Base::SelectionEnd => selection.clone().ok_or(CommandError::NoSelection)?.end - 1,
+            };
+
+            line.checked_add_signed(address.offset)
+                .filter(|&line| line <= last)
+                .ok_or(CommandError::InvalidRange)
+        };
+
+        let (start, end) = (resolve(start)?, resolve(end)?);
+        Ok(start.min(end)..start.max(end) + 1)
+    }
+}
+
+pub fn parse(line: &str) -> Result<CommandLine<'_>, CommandError> {
+    let line = line.trim_start();
+
+    let (range, rest) = parse_range(line)?;
+    let rest = rest.trim_start();
+
+    let name_len = rest
+        .find(|ch: char| !ch.is_ascii_alphabetic())
+        .unwrap_or(rest.len());
+    let (name, rest) = rest.split_at(name_len);
+
+    let (bang, rest) = match rest.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+
+    Ok(CommandLine {
+        range,
+        name,
+        bang,
+        args: rest.trim_start(),
+    })
+}
+
+fn parse_range(s: &str) -> Result<(Option<RangeSpec>, &str), CommandError> {
+    if let Some(rest) = s.strip_prefix('%') {
+        return Ok((Some(RangeSpec::All), rest));
+    }
+
+    let (start, rest) = parse_address(s)?;
+
+    let Some(rest) = rest.strip_prefix(',') else {
+        return Ok((start.map(|start| RangeSpec::Lines(start, start)), rest));
+    };
+
+    let current = Address {
+        base: Base::Current,
+        offset: 0,
+    };
+
+    let (end, rest) = parse_address(rest)?;
+    let range = RangeSpec::Lines(start.unwrap_or(current), end.unwrap_or(current));
+
+    Ok((Some(range), rest))
+}
+
+fn parse_address(s: &str) -> Result<(Option<Address>, &str), CommandError> {
+    let s = s.trim_start();
+
+    let (base, mut rest) = match s.chars().next() {
+        Some('0'..='9') => {
+            let (number, rest) = split_number(s);
+            (Some(Base::Line(number)), rest)
+        }
+        Some('.') => (Some(Base::Current), &s[1..]),
+        Some('$') => (Some(Base::Last), &s[1..]),
+        Some('\'') => match s[1..].chars().next() {
+            Some('<') => (Some(Base::SelectionStart), &s[2..]),
+            Some('>') => (Some(Base::SelectionEnd), &s[2..]),
+            Some(mark) => return Err(CommandError::UnknownMark(mark)),
+            None => return Err(CommandError::InvalidRange),
+        },
+        _ => (None, s),
+    };
+
+    let mut offset = 0isize;
+    let mut has_offset = false;
+
+    while let Some(sign @ ('+' | '-')) = rest.chars().next() {
+        let (number, after) = match rest[1..].chars().next() {
+            Some('0'..='9') => split_number(&rest[1..]),
+            _ => (1, &rest[1..]),
+        };
+
+        let number = isize::try_from(number).map_err(|_| CommandError::InvalidRange)?;
+        offset += if sign == '+' { number } else { -number };
+        has_offset = true;
+        rest = after;
+    }
+
+    let base = match base {
+        Some(base) => base,
+        None if has_offset => Base::Current,
+        None => return Ok((None, rest)),
+    };
+
+    Ok((Some(Address { base, offset }), rest))
+}
+
+fn split_number(s: &str) -> (usize, &str) {
+    let len = s.find(|ch: char| !ch.is_ascii_digit()).unwrap_or(s.len());
+    let (digits, rest) = s.split_at(len);
+    (digits.parse().unwrap_or(usize::MAX), rest)
+}
+
+/// Splits arguments on whitespace. Single quotes keep their content as is,
+/// double quotes and bare words support `\` escapes.
+pub fn split_args(s: &str) -> Result<Vec<String>, CommandError> {
+    let mut args = Vec::new();
+    let mut chars = s.chars().peekable();
+
+    loop {
+        while chars.next_if(|ch| ch.is_whitespace()).is_some() {}
+
+        if chars.peek().is_none() {
+            return Ok(args);
+        }
+
+        let mut arg = String::new();
+
+        while let Some(ch) = chars.next_if(|ch| !ch.is_whitespace()) {
+            match ch {
+                '\'' => loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(ch) => arg.push(ch),
+                        None => return Err(CommandError::UnterminatedQuote),
+                    }
+                },
+                '"' => loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => arg.push(unescape(chars.next())?),
+                        Some(ch) => arg.push(ch),
+                        None => return Err(CommandError::UnterminatedQuote),
+                    }
+                },
+                '\\' => arg.push(unescape(chars.next())?),
+                ch => arg.push(ch),
+            }
+        }
+
+        args.push(arg);
+    }
+}
+
+fn unescape(ch: Option<char>) -> Result<char, CommandError> {
+    match ch {
+        Some('n') => Ok('\n'),
+        Some('t') => Ok('\t'),
+        Some(ch) => Ok(ch),
+        None => Err(CommandError::TrailingEscape),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandError {
+    NotFound(String),
+    InvalidRange,
+    NoSelection,
+    UnknownMark(char),
+    UnterminatedQuote,
+    TrailingEscape,
+    NoRange(String),
+    NoBang(String),
+    MissingArgument { usage: String },
+    TooManyArguments { usage: String },
+    InvalidArgument { name: String, reason: String },
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound(name) => write!(f, "not an editor command: {name}"),
+            Self::InvalidRange => write!(f, "invalid range"),
+            Self::NoSelection => write!(f, "'< and '> need a selection"),
+            Self::UnknownMark(mark) => write!(f, "unknown mark: '{mark}"),
+            Self::UnterminatedQuote => write!(f, "unterminated quote"),
+            Self::TrailingEscape => write!(f, "trailing \\"),
+            Self::NoRange(name) => write!(f, "{name} does not take a range"),
+            Self::NoBang(name) => write!(f, "{name} does not take !"),
+            Self::MissingArgument { usage } => write!(f, "missing argument, usage: {usage}"),
+            Self::TooManyArguments { usage } => write!(f, "too many arguments, usage: {usage}"),
+            Self::InvalidArgument { name, reason } => write!(f, "invalid {name}: {reason}"),
+        }
+    }
+}
+
+impl Error for CommandError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The lines `line`'s range covers with the cursor on line 5 of 10 and
+    /// lines 2 to 3 selected, counting from 1.
+    fn lines(line: &str) -> Result<Option<Range<usize>>, CommandError> {
+        let range = parse(line)?.range;
+        let lines = range
+            .map(|range| range.resolve(4, 9, Some(1..3)))
+            .transpose()?;
+        Ok(lines.map(|lines| lines.start + 1..lines.end + 1))
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(lines("w"), Ok(None));
+        assert_eq!(lines("%s/a/b/"), Ok(Some(1..11)));
+        assert_eq!(lines("3d"), Ok(Some(3..4)));
+        assert_eq!(lines("2,4d"), Ok(Some(2..5)));
+        assert_eq!(lines("4,2d"), Ok(Some(2..5)));
+        assert_eq!(lines(".,$d"), Ok(Some(5..11)));
+        assert_eq!(lines(".+2,$-1d"), Ok(Some(7..10)));
+        assert_eq!(lines("-,+d"), Ok(Some(4..7)));
+        assert_eq!(lines(",3d"), Ok(Some(3..6)));
+        assert_eq!(lines("'<,'>s/a/b/"), Ok(Some(2..4)));
+        assert_eq!(lines("'<+1,'>+1d"), Ok(Some(3..5)));
+        assert_eq!(lines("11d"), Err(CommandError::InvalidRange));
+        assert_eq!(lines("1-2d"), Err(CommandError::InvalidRange));
+        assert_eq!(lines("'ad"), Err(CommandError::UnknownMark('a')));
+
+        let range = parse("'<d").unwrap().range.unwrap();
+        assert_eq!(range.resolve(0, 9, None), Err(CommandError::NoSelection));
+    }
+
+    #[test]
+    fn name_and_bang() {
+        let line = parse("  2,3w! out.rs").unwrap();
+        assert_eq!(line.name, "w");
+        assert!(line.bang);
+        assert_eq!(line.args, "out.rs");
+
+        let line = parse("s/a/b/g").unwrap();
+        assert_eq!(line.name, "s");
+        assert!(!line.bang);
+        assert_eq!(line.args, "/a/b/g");
+
+        let line = parse("q").unwrap();
+        assert_eq!((line.name, line.bang, line.args), ("q", false, ""));
+    }
+
+    #[test]
+    fn args() {
+        let args = split_args;
+
+        assert_eq!(
+            args("  a  b\tc "),
+            Ok(vec!["a".into(), "b".into(), "c".into()])
+        );
+        assert_eq!(args(""), Ok(vec![]));
+        assert_eq!(args(r"'a b' 'c\n'"), Ok(vec!["a b".into(), r"c\n".into()]));
+        assert_eq!(
+            args(r#""a b" "c\"\n""#),
+            Ok(vec!["a b".into(), "c\"\n".into()])
+        );
+        assert_eq!(args(r"a\ b c\\"), Ok(vec!["a b".into(), "c\\".into()]));
+        assert_eq!(args(r#"x'y z'"w""#), Ok(vec!["xy zw".into()]));
+        assert_eq!(args("'a"), Err(CommandError::UnterminatedQuote));
+        assert_eq!(args("\"a"), Err(CommandError::UnterminatedQuote));
+        assert_eq!(args("a\\"), Err(CommandError::TrailingEscape));
+    }
+}
@@ -1,6 +1,14 @@
-use std::rc::Rc;
+use std::{
+    env,
+    ops::Range,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
-use crate::app::App;
+use crate::{
+    app::App,
+    cmdline::{self, CommandError, CommandLine},
+};
 
 #[derive(Clone)]
 pub struct Command<T> {
@@ -19,3 +27,242 @@ impl<T> Command<T> {
         (self.action)(app, arg)
     }
 }
+
+/// A command run from the command line, with the arguments it accepts.
+#[derive(Clone)]
+pub struct ExCommand {
+    action: Command<Args>,
+    args: Vec<ArgSpec>,
+    range: bool,
+    bang: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgKind {
+    Path,
+    Number,
+    /// `name=value`, `name` or `noname`.
+    Setting,
+    /// The rest of the command line, without any unquoting.
+    Raw,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Arity {
+    Required,
+    Optional,
+    Many,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ArgSpec {
+    name: &'static str,
+    kind: ArgKind,
+    arity: Arity,
+}
+
+impl ExCommand {
+    pub fn new(action: impl Fn(&mut App, Args) + 'static) -> Self {
+        Self {
+            action: Command::new(action),
+            args: Vec::new(),
+            range: false,
+            bang: false,
+        }
+    }
+
+    pub fn with_arg(self, name: &'static str, kind: ArgKind) -> Self {
+        self.with_spec(name, kind, Arity::Required)
+    }
+
+    pub fn with_optional_arg(self, name: &'static str, kind: ArgKind) -> Self {
+        self.with_spec(name, kind, Arity::Optional)
+    }
+
+    pub fn with_many_args(self, name: &'static str, kind: ArgKind) -> Self {
+        self.with_spec(name, kind, Arity::Many)
+    }
+
+    pub fn with_range(mut self) -> Self {
+        self.range = true;
+        self
+    }
+
+    pub fn with_bang(mut self) -> Self {
+        self.bang = true;
+        self
+    }
+
+    fn with_spec(mut self, name: &'static str, kind: ArgKind, arity: Arity) -> Self {
+        self.args.push(ArgSpec { name, kind, arity });
+        self
+    }
+
+    pub fn usage(&self, name: &str) -> String {
+        let mut usage = String::new();
+
+        if self.range {
+            usage.push_str("[range]");
+        }
+
+        usage.push_str(name);
+
+        if self.bang {
+            usage.push_str("[!]");
+        }
+
+        for spec in &self.args {
+            usage.push_str(&match spec.arity {
+                Arity::Required => format!(" {{{}}}", spec.name),
+                Arity::Optional => format!(" [{}]", spec.name),
+                Arity::Many => format!(" [{}...]", spec.name),
+            });
+        }
+
+        usage
+    }
+
+    /// Checks the command line against the command's argument specs.
+    /// `range` is the already resolved range of `line`.
+    pub fn parse_args(
+        &self,
+        line: &CommandLine,
+        range: Option<Range<usize>>,
+    ) -> Result<Args, CommandError> {
+        if range.is_some() && !self.range {
+            return Err(CommandError::NoRange(line.name.to_owned()));
+        }
+
+        if line.bang && !self.bang {
+            return Err(CommandError::NoBang(line.name.to_owned()));
+        }
+
+        let usage = || self.usage(line.name);
+
+        let tokens = match self.args.first() {
+            Some(ArgSpec {
+                kind: ArgKind::Raw, ..
+            }) if !line.args.is_empty() => vec![line.args.to_owned()],
+            Some(ArgSpec {
+                kind: ArgKind::Raw, ..
+            }) => Vec::new(),
+            _ => cmdline::split_args(line.args)?,
+        };
+        let mut tokens = tokens.into_iter();
+
+        let mut values = Vec::new();
+
+        for spec in &self.args {
+            let taken: Vec<String> = match spec.arity {
+                Arity::Required => {
+                    let token = tokens
+                        .next()
+                        .ok_or_else(|| CommandError::MissingArgument { usage: usage() })?;
+                    vec![token]
+                }
+                Arity::Optional => tokens.next().into_iter().collect(),
+                Arity::Many => tokens.by_ref().collect(),
+            };
+
+            for token in taken {
+                values.push((spec.name, Value::parse(spec, token)?));
+            }
+        }
+
+        if tokens.next().is_some() {
+            return Err(CommandError::TooManyArguments { usage: usage() });
+        }
+
+        Ok(Args {
+            range,
+            bang: line.bang,
+            values,
+        })
+    }
+
+    pub fn run(&self, app: &mut App, args: Args) {
+        self.action.run(app, args)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Path(PathBuf),
+    String(String),
+    Number(usize),
+    Setting { name: String, value: Option<String> },
+}
+
+impl Value {
+    fn parse(spec: &ArgSpec, token: String) -> Result<Self, CommandError> {
+        match spec.kind {
+            ArgKind::Path => Ok(Self::Path(expand_home(&token))),
+            ArgKind::Raw => Ok(Self::String(token)),
+            ArgKind::Number => {
+                token
+                    .parse()
+                    .map(Self::Number)
+                    .map_err(|_| CommandError::InvalidArgument {
+                        name: spec.name.to_owned(),
+                        reason: format!("`{token}` is not a number"),
+                    })
+            }
+            ArgKind::Setting => Ok(match token.split_once('=') {
+                Some((name, value)) => Self::Setting {
+                    name: name.to_owned(),
+                    value: Some(value.to_owned()),
+                },
+                None => Self::Setting {
+                    name: token,
+                    value: None,
+                },
+            }),
+        }
+    }
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match path.strip_prefix("~/") {
+        Some(rest) if let Some(home) = env::var_os("HOME") => Path::new(&home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// The arguments of an [`ExCommand`], checked against its specs.
+#[derive(Debug, Clone, Default)]
+pub struct Args {
+    /// The line indexes the command applies to.
+    pub range: Option<Range<usize>>,
+    pub bang: bool,
+    values: Vec<(&'static str, Value)>,
+}
+
+impl Args {
+    pub fn values(&self, name: &str) -> impl Iterator<Item = &Value> {
+        self.values
+            .iter()
+            .filter(move |(arg, _)| *arg == name)
+            .map(|(_, value)| value)
+    }
+
+    pub fn path(&self, name: &str) -> Option<&Path> {
+        self.values(name).find_map(|value| match value {
+            Value::Path(path) => Some(path.as_path()),
+            _ => None,
+        })
+    }
+
+    pub fn string(&self, name: &str) -> Option<&str> {
+        self.values(name).find_map(|value| match value {
+            Value::String(string) => Some(string.as_str()),
+            _ => None,
+        })
+    }
+
+    pub fn number(&self, name: &str) -> Option<usize> {
+        self.values(name).find_map(|value| match value {
+            Value::Number(number) => Some(*number),
+            _ => None,
+        })
+    }
+}
@@ -0,0 +1,469 @@
+use ratatui::{
+    buffer::Buffer,
+    layout::{Position, Rect},
+    text::{Line, Span},
+    widgets::{Clear, Paragraph, Widget},
+};
+use ropey::{Rope, RopeSlice};
+use std::{
+    collections::{BTreeSet, HashMap, hash_map::Entry},
+    env, fs,
+    path::{Path, PathBuf},
+};
+use tree_sitter::{Query, Tree};
+
+use crate::{
+    fuzzy::{self, Match},
+    syntax::{self, Local},
+    theme::Theme,
+};
+
+/// The most items shown at once.
+const HEIGHT: usize = 10;
+
+/// The length of the words offered, and of the typed ones that open the popup.
+const MIN_WORD: usize = 2;
+
+/// Where an item comes from, in order of precedence when several sources offer
+/// the same text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Source {
+    Lsp,
+    Local,
+    Word,
+    Path,
+}
+
+impl Source {
+    /// Its name, shown next to its items.
+    pub fn name(self) -> &'static str {
+        match self {
+            Source::Lsp => "lsp",
+            Source::Local => "local",
+            Source::Word => "word",
+            Source::Path => "path",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Item {
+    pub label: String,
+    /// What replaces the typed text, e.g. a name without the parameters its
+    /// label shows.
+    pub text: String,
+    pub source: Source,
+}
+
+impl Item {
+    pub fn new(text: impl Into<String>, source: Source) -> Self {
+        let text = text.into();
+
+        Self {
+            label: text.clone(),
+            text,
+            source,
+        }
+    }
+}
+
+/// The text being completed before the cursor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Context {
+    /// Where it starts.
+    pub start: usize,
+    /// The directory typed before the file name, when completing a path.
+    pub dir: Option<String>,
+}
+
+/// The items offered for the text before the cursor in insert mode, filtered
+/// as it is typed.
+pub struct Completion {
+    buffer: usize,
+    start: usize,
+    /// Where the cursor was when it opened, which the server's items are for.
+    origin: usize,
+    items: Vec<Item>,
+    query: String,
+    matches: Vec<(usize, Match)>,
+    /// The highlighted match, none until moved to one.
+    selected: Option<usize>,
+    scroll: usize,
+}
+
+impl Completion {
+    pub fn new(buffer: usize, start: usize, origin: usize, items: Vec<Item>) -> Self {
+        let mut completion = Self {
+            buffer,
+            start,
+            origin,
+            items: Vec::new(),
+            query: String::new(),
+            matches: Vec::new(),
+            selected: None,
+            scroll: 0,
+        };
+
+        completion.extend(items);
+        completion
+    }
+
+    pub fn buffer(&self) -> usize {
+        self.buffer
+    }
+
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn origin(&self) -> usize {
+        self.origin
+    }
+
+    /// Whether no item matches, which hides the popup.
+    pub fn is_empty(&self) -> bool {
+        self.matches.is_empty()
+    }
+
+    /// Adds items, e.g. those of a server that answered late, keeping the one
+    /// with the best source of those with the same text.
+    pub fn extend(&mut self, items: impl IntoIterator<Item = Item>) {
+        let selected = self.selected().map(|item| item.text.clone());
+
+        self.items.extend(items);
+        self.items
+            .sort_by(|a, b| (a.source, &a.label).cmp(&(b.source, &b.label)));
+
+        let mut seen = BTreeSet::new();
+        self.items.retain(|item| seen.insert(item.text.clone()));
+
+        self.filter_items();
+        self.selected = selected.and_then(|text| {
+            (self.matches.iter()).position(|(idx, _)| self.items[*idx].text == text)
+        });
+    }
+
+    /// Matches the items against the text typed so far.
+    pub fn filter(&mut self, query: &str) {
+        if self.query != query {
+            query.clone_into(&mut self.query);
+            self.filter_items();
+            self.selected = None;
+        }
+    }
+
+    fn filter_items(&mut self) {
+        let items = &self.items;
+        self.matches = fuzzy::filter(&self.query, items.iter().map(|item| item.label.as_str()));
+
+        // Hidden files only once their name is being typed.
+        if !self.query.starts_with('.') {
+            self.matches.retain(|(idx, _)| {
+                items[*idx].source != Source::Path || !items[*idx].label.starts_with('.')
+            });
+        }
+
+        self.scroll = 0;
+    }
+
+    /// Highlights the match `by` rows down, or up if negative, wrapping around.
+    /// The first move down goes to the first match, and up to the last one.
+    pub fn move_by(&mut self, by: isize) {
+        if self.matches.is_empty() {
+            return;
+        }
+
+        let len = self.matches.len() as isize;
+        let from = match self.selected {
+            Some(selected) => selected as isize,
+            None if by > 0 => -1,
+            None => 0,
+        };
+
+        self.selected = Some((from + by).rem_euclid(len) as usize);
+    }
+
+    /// The highlighted item.
+    pub fn selected(&self) -> Option<&Item> {
+        let (idx, _) = self.matches.get(self.selected?)?;
+        Some(&self.items[*idx])
+    }
+
+    /// Draws the matches under the text being completed, which ends at
+    /// `cursor`, or above it when there is more room there.
+    pub fn render(&mut self, theme: &Theme, cursor: Position, area: Rect, buf: &mut Buffer) {
+        if self.matches.is_empty() {
+            return;
+        }
+
+        // Below the cursor, unless there is more room above.
+        let below = area.bottom().saturating_sub(cursor.y + 1);
+        let above = cursor.y.saturating_sub(area.y);
+        let wanted = self.matches.len().min(HEIGHT) as u16;
+        let (y, height) = if wanted <= below || below >= above {
+            (cursor.y + 1, wanted.min(below))
+        } else {
+            (cursor.y - wanted.min(above), wanted.min(above))
+        };
+
+        let height = usize::from(height);
+        let selected = self.selected.unwrap_or(0);
+        self.scroll = self
+            .scroll
+            .clamp((selected + 1).saturating_sub(height), selected);
+
+        let visible = || {
+            (self.matches.iter().enumerate().skip(self.scroll))
+                .take(height)
+                .map(|(idx, (item, mat))| (idx, &self.items[*item], mat))
+        };
+
+        let label_width = (visible().map(|(_, item, _)| item.label.chars().count()))
+            .max()
+            .unwrap_or_default();
+        let source_width = (visible().map(|(_, item, _)| item.source.name().len()))
+            .max()
+            .unwrap_or_default();
+
+        // The labels line up with the text being completed.
+        let width = (label_width + source_width + 4).min(area.width.into()) as u16;
+        let x = (cursor
+            .x
+            .saturating_sub(self.query.chars().count() as u16 + 1))
+        .min(area.right().saturating_sub(width))
+        .max(area.x);
+
+        let popup = Rect::new(x, y, width, height as u16).intersection(area);
+        Clear.render(popup, buf);
+
+        for (row, (idx, item, mat)) in popup.rows().zip(visible()) {
+            let style = if self.selected == Some(idx) {
+                theme.editor.patch(theme.selection)
+            } else {
+                theme.editor.patch(theme.status_bar)
+            };
+
+            let mut indices = mat.indices.iter().peekable();
+            let label = item.label.chars().enumerate().map(|(char_idx, ch)| {
+                match indices.next_if_eq(&&char_idx) {
+                    Some(_) => Span::styled(ch.to_string(), style.patch(theme.search_match)),
+                    None => Span::styled(ch.to_string(), style),
+                }
+            });
+
+            buf.set_style(row, style);
+            Line::from_iter([Span::raw(" ")].into_iter().chain(label)).render(row, buf);
+            Paragraph::new(format!("{} ", item.source.name()))
+                .style(style.patch(theme.placeholder))
+                .right_aligned()
+                .render(row, buf);
+        }
+    }
+}
+
+impl Context {
+    /// Whether typing `query` opens the popup: a word of a few characters, or
+    /// a path that is not just slashes, e.g. the `//` of a comment.
+    pub fn triggers(&self, query: &str) -> bool {
+        match &self.dir {
+            Some(dir) => !dir.trim_end_matches('/').is_empty() || (dir == "/" && !query.is_empty()),
+            None => query.chars().count() >= MIN_WORD,
+        }
+    }
+}
+
+fn is_word(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+/// The word or file name before `byte`. The text back to the last space or
+/// bracket is a path if it has a `/`.
+pub fn context(rope: &Rope, byte: usize) -> Context {
+    let token: String = {
+        let mut chars: Vec<char> = (rope.chars_at(rope.byte_to_char(byte)).reversed())
+            .take_while(|&ch| !ch.is_whitespace() && !"\"'`()[]{}<>,;=".contains(ch))
+            .collect();
+        chars.reverse();
+        chars.into_iter().collect()
+    };
+
+    if let Some(slash) = token.rfind('/') {
+        let name = &token[slash + 1..];
+
+        return Context {
+            start: byte - name.len(),
+            dir: Some(token[..=slash].to_owned()),
+        };
+    }
+
+    let word: usize = (token.chars().rev())
+        .take_while(|&ch| is_word(ch))
+        .map(char::len_utf8)
+        .sum();
+
+    Context {
+        start: byte - word,
+        dir: None,
+    }
+}
+
+/// How often each word of a text occurs, kept up to date as it is edited so
+/// that completing does not scan every buffer.
+#[derive(Debug, Default)]
+pub struct Words(HashMap<String, usize>);
+
+impl Words {
+    pub fn new(text: RopeSlice) -> Self {
+        let mut words = Self::default();
+        words.add(text);
+        words
+    }
+
+    pub fn add(&mut self, text: RopeSlice) {
+        for_each_word(text, |word| *self.0.entry(word).or_default() += 1);
+    }
+
+    pub fn remove(&mut self, text: RopeSlice) {
+        for_each_word(text, |word| {
+            if let Entry::Occupied(mut entry) = self.0.entry(word) {
+                *entry.get_mut() -= 1;
+
+                if *entry.get() == 0 {
+                    entry.remove();
+                }
+            }
+        });
+    }
+
+    /// The words with the number of times they occur.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.0.iter().map(|(word, &count)| (word.as_str(), count))
+    }
+}
+
+/// Calls `f` with the words of `text` long enough to be offered.
+fn for_each_word(text: RopeSlice, mut f: impl FnMut(String)) {
+    let mut word = String::new();
+
+    for ch in text.chars().chain([' ']) {
+        if is_word(ch) {
+            word.push(ch);
+        } else if !word.is_empty() {
+            if word.chars().count() >= MIN_WORD && !word.starts_with(|ch: char| ch.is_ascii_digit())
+            {
+                f(std::mem::take(&mut word));
+            }
+
+            word.clear();
+        }
+    }
+}
+
+/// The word around `byte`, e.g. the one being typed.
+pub fn word_at(rope: &Rope, byte: usize) -> String {
+    let char_idx = rope.byte_to_char(byte);
+    let mut before: Vec<char> = (rope.chars_at(char_idx).reversed())
+        .take_while(|&ch| is_word(ch))
+        .collect();
+    before.reverse();
+
+    (before.into_iter())
+        .chain(rope.chars_at(char_idx).take_while(|&ch| is_word(ch)))
+        .collect()
+}
+
+/// The names `query` captures as `@local.definition` in the scopes around
+/// `byte`, e.g. the parameters and variables of the function it is in.
+pub fn locals(query: &Query, tree: &Tree, rope: &Rope, byte: usize) -> Vec<String> {
+    let root = tree.root_node();
+
+    // The scopes around `byte` are all in the top-level node around it.
+    let mut walk = root.walk();
+    let range = root
+        .children(&mut walk)
+        .find(|node| node.end_byte() >= byte)
+        .map_or(0..usize::MAX, |top| top.byte_range());
+
+    let mut locals = Vec::new();
+
+    syntax::locals(query, root, rope, range, |local, scopes| {
+        let scope = scopes.last().expect("the file scope is never closed");
+
+        if let Local::Definition(name) = local
+            && (scope.range.start..=scope.range.end).contains(&byte)
+        {
+            locals.push(name.to_owned());
+        }
+    });
+
+    locals
+}
+
+/// The entries of the directory typed as `dir`: relative to the current
+/// directory, or to the home directory after `~/`. Directories end with `/`.
+pub fn paths(dir: &str) -> Vec<Item> {
+    let path = match dir.strip_prefix("~/") {
+        Some(rest) => env::var_os("HOME")
+            .map_or_else(PathBuf::new, PathBuf::from)
+            .join(rest),
+        None => PathBuf::from(dir),
+    };
+
+    let Ok(entries) = fs::read_dir(Path::new(&path)) else {
+        return Vec::new();
+    };
+
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let mut name = entry.file_name().into_string().ok()?;
+
+            if entry.path().is_dir() {
+                name.push('/');
+            }
+
+            Some(Item::new(name, Source::Path))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::Document;
+
+    fn counts(words: &Words) -> BTreeSet<(String, usize)> {
+        words
+            .iter()
+            .map(|(word, count)| (word.into(), count))
+            .collect()
+    }
+
+    #[test]
+    fn words_follow_edits() {
+        let mut doc = Document::new(Rope::from_str("let foo = bar;\nfoo(bar, baz)\n"));
+        doc.words();
+
+        doc.replace(4..7, "quux");
+        doc.replace(10..10, "x\ny ");
+        doc.replace(0..doc.rope().len_bytes() - 4, "bar");
+        doc.replace(3..3, "\n\nfoo1 2x bar ");
+
+        let expected = Words::new(doc.rope().slice(..));
+        assert_eq!(counts(doc.words()), counts(&expected));
+        assert_eq!(
+            counts(&expected),
+            BTreeSet::from([("bar".into(), 2), ("az".into(), 1), ("foo1".into(), 1)])
+        );
+    }
+
+    #[test]
+    fn word_at() {
+        let rope = Rope::from_str("fn café_au(lait)");
+
+        assert_eq!(super::word_at(&rope, 3), "café_au");
+        assert_eq!(super::word_at(&rope, 11), "café_au");
+        assert_eq!(super::word_at(&rope, 12), "lait");
+        assert_eq!(super::word_at(&rope, 2), "fn");
+    }
+}
@@ -0,0 +1,224 @@
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    app::App,
+    highlighter::Highlighter,
+    keymap::KeyMode,
+    language::Languages,
+    register::{Clipboard, Registers},
+    theme::Theme,
+};
+
+/// The user configuration, read from `~/.config/ion/config.toml` by default.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    theme: Option<String>,
+    /// Options as set by `:set`, e.g. `tabwidth = 2`.
+    options: BTreeMap<String, toml::Value>,
+    keys: Keys,
+    /// Language server commands per language, e.g. `rust = ["rust-analyzer"]`.
+    /// An empty command disables the language's server.
+    #[serde(rename = "language-servers")]
+    language_servers: BTreeMap<String, Vec<String>>,
+    clipboard: Option<ClipboardCommands>,
+    /// Languages added to the built-in ones, as `[[language]]` tables.
+    #[serde(rename = "language")]
+    languages: Vec<LanguageEntry>,
+}
+
+/// Key sequences bound to action names, per mode.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Keys {
+    normal: BTreeMap<String, String>,
+    insert: BTreeMap<String, String>,
+    select: BTreeMap<String, String>,
+}
+
+/// The commands behind the `+` and `*` registers. `copy` reads the text from
+/// stdin and `paste` writes it to stdout.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ClipboardCommands {
+    copy: Vec<String>,
+    paste: Vec<String>,
+}
+
+/// A language and how to recognize it, with the grammar library it is
+/// parsed with, e.g. `libtree-sitter-{grammar}.so` in the runtime directory.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct LanguageEntry {
+    name: String,
+    #[serde(default)]
+    file_types: Vec<String>,
+    #[serde(default)]
+    interpreters: Vec<String>,
+    grammar: Option<String>,
+}
+
+impl Config {
+    /// Reads the config file at `path`, or the default one if it exists.
+    pub fn load(path: Option<&Path>) -> Result<Self, String> {
+        let (path, required) = match path {
+            Some(path) => (path.to_owned(), true),
+            None => match config_dir() {
+                Some(dir) => (dir.join("config.toml"), false),
+                None => return Ok(Self::default()),
+            },
+        };
+
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound && !required => {
+                return Ok(Self::default());
+            }
+            Err(err) => return Err(format!("{}: {err}", path.display())),
+        };
+
+        toml::from_str(&text).map_err(|err| {
+            let line = err
+                .span()
+                .map_or(0, |span| text[..span.start].lines().count().max(1));
+            format!("{}:{line}: {}", path.display(), err.message())
+        })
+    }
+
+    /// Applies the config on top of the defaults. Invalid entries are skipped
+    /// and their errors returned.
+    pub fn apply(self, app: &mut App) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if let Some(name) = self.theme {
+            match Theme::load(&name) {
+                Ok(theme) => app.theme = theme,
+                Err(err) => errors.push(err),
+            }
+        }
+
+        if let Some(ClipboardCommands { copy, paste }) = self.clipboard {
+            app.options.clipboard = Some(Clipboard::External { copy, paste });
+        }
+
+        for (name, value) in self.options {
+            let value = match value {
+                toml::Value::String(value) => value,
+                toml::Value::Integer(value) => value.to_string(),
+                value => {
+                    errors.push(format!("invalid value for {name}: {value}"));
+                    continue;
+                }
+            };
+
+            if let Err(err) = app.options.set(&name, Some(&value)) {
+                errors.push(err);
+            }
+        }
+
+        for (mode, bindings) in [
+            (KeyMode::Normal, self.keys.normal),
+            (KeyMode::Insert, self.keys.insert),
+            (KeyMode::Select, self.keys.select),
+        ] {
+            for (keys, action) in bindings {
+                if let Err(err) = app.bind(mode, &keys, &action) {
+                    errors.push(format!("{keys}: {err}"));
+                }
+            }
+        }
+
+        if !self.languages.is_empty() {
+            let mut languages = Languages::default();
+
+            for language in &self.languages {
+                languages.declare(
+                    &language.name,
+                    &language.file_types,
+                    &language.interpreters,
+                    language.grammar.as_deref(),
+                );
+            }
+
+            // Buffers are opened once the config is applied, so none was
+            // detected with the old languages.
+            app.languages = Arc::new(languages);
+            app.highlighter = Highlighter::new(app.languages.clone());
+        }
+
+        for (language, command) in self.language_servers {
+            app.lsp.set_command(&language, command);
+        }
+
+        app.registers = Registers::new(app.options.clipboard());
+
+        errors
+    }
+}
+
+/// `~/.config/ion`, or `$XDG_CONFIG_HOME/ion` if that is set.
+pub fn config_dir() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+
+    Some(dir.join("ion"))
+}
+
+/// Where grammars and queries are looked up: `$ION_RUNTIME` if set, then
+/// `runtime` in the config directory.
+pub fn runtime_dirs() -> Vec<PathBuf> {
+    env::var_os("ION_RUNTIME")
+        .map(PathBuf::from)
+        .into_iter()
+        .chain(config_dir().map(|dir| dir.join("runtime")))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ropey::Rope;
+
+    #[test]
+    fn language_tables() {
+        let config: Config = toml::from_str(
+            r#"
+            [[language]]
+            name = "tsx"
+            file-types = ["tsx"]
+            grammar = "typescript"
+
+            [[language]]
+            name = "zig"
+            "#,
+        )
+        .unwrap();
+
+        let [tsx, zig] = &config.languages[..] else {
+            panic!("{:?}", config.languages);
+        };
+        assert_eq!(tsx.file_types, ["tsx"]);
+        assert_eq!(tsx.grammar.as_deref(), Some("typescript"));
+        assert!(zig.file_types.is_empty() && zig.grammar.is_none());
+
+        let mut languages = Languages::new(Vec::new());
+        languages.declare(
+            &tsx.name,
+            &tsx.file_types,
+            &tsx.interpreters,
+            tsx.grammar.as_deref(),
+        );
+        let language = languages.detect(Some(Path::new("app.tsx")), &Rope::new());
+        assert_eq!(language.name(), "tsx");
+
+        let err = toml::from_str::<Config>("[[language]]\nname = \"zig\"\nextensions = []\n");
+        assert!(err.is_err());
+    }
+}
@@ -1,98 +1,1003 @@
 use std::{
     fs::File,
-    io::{self, BufWriter},
-    path::{Path, PathBuf},
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::mpsc,
 };
 
-use crate::{app::App, command::Command, document::Document, input::Input};
+use ratatui::layout::Direction;
+
+use crate::{
+    app::App,
+    buffer::{Buffer, same_file},
+    command::{ArgKind, Args, Command, ExCommand, Value},
+    document::Document,
+    files, fold, grep,
+    input::Input,
+    keymap::{Action, KeyMode},
+    operator::{self, Motion, MotionKind, Operator, Target, TextObject},
+    picker::{Item, Picker},
+    quickfix,
+    search::Search,
+    substitute::Substitution,
+    syntax,
+    theme::Theme,
+    view::View,
+    window::Side,
+};
 
 pub fn init(app: &mut App) {
-    app.keymap
-        .insert('i', Command::new(|app, ()| _ = app.doc.enter_insert()));
-    app.keymap
-        .insert('v', Command::new(|app, ()| _ = app.doc.enter_select()));
-    app.keymap
-        .insert('V', Command::new(|app, ()| _ = app.doc.enter_select_line()));
-    app.keymap
-        .insert('d', Command::new(|app, ()| _ = app.doc.remove()));
-    app.keymap
-        .insert('h', Command::new(|app, ()| _ = app.doc.move_left()));
-    app.keymap.insert(
-        'j',
-        Command::new(|app, ()| {
-            app.doc.move_up();
-            app.doc.scroll_to_cursor();
-        }),
+    actions(app);
+
+    for (keys, action) in KEYS {
+        for mode in [KeyMode::Normal, KeyMode::Select] {
+            app.bind(mode, keys, action).expect("valid default binding");
+        }
+    }
+
+    for (mode, bindings) in [
+        (KeyMode::Normal, NORMAL_KEYS),
+        (KeyMode::Normal, OBJECT_KEYS),
+        (KeyMode::Select, OBJECT_KEYS),
+        (KeyMode::Insert, INSERT_KEYS),
+    ] {
+        for (keys, action) in bindings {
+            app.bind(mode, keys, action).expect("valid default binding");
+        }
+    }
+
+    app.commands.insert(
+        "q".into(),
+        ExCommand::new(|app, args| {
+            if app.windows.close() {
+                return;
+            }
+
+            let modified = app
+                .buffers
+                .iter()
+                .find(|buffer| buffer.doc.is_modified())
+                .map(Buffer::display_name);
+
+            if !args.bang
+                && let Some(name) = modified
+            {
+                let err = format!("unsaved changes in {name}, add ! to discard them");
+                return app.report_error(err);
+            }
+
+            app.exit();
+        })
+        .with_bang(),
     );
-    app.keymap.insert(
-        'k',
-        Command::new(|app, ()| {
-            app.doc.move_down();
-            app.doc.scroll_to_cursor();
-        }),
+    app.commands.insert(
+        "w".into(),
+        ExCommand::new(write).with_optional_arg("file", ArgKind::Path),
     );
-    app.keymap
-        .insert('l', Command::new(|app, ()| _ = app.doc.move_right()));
-    app.keymap.insert(
-        'w',
-        Command::new(|app, ()| {
-            app.doc.move_next_word();
-            app.doc.scroll_to_cursor();
-        }),
+    app.commands.insert(
+        "e".into(),
+        ExCommand::new(|app, args| {
+            let current = app.buffer().path.as_deref();
+
+            let result = match args.path("file") {
+                Some(path) if current.is_none_or(|current| current != path) => app.open(path),
+                _ if current.is_none() => return app.report_error("no file name"),
+                _ if app.buffer().doc.is_modified() && !args.bang => {
+                    return app.report_error("unsaved changes, add ! to discard them");
+                }
+                _ => app.reload(),
+            };
+
+            if let Err(err) = result {
+                app.report_error(err);
+            }
+        })
+        .with_bang()
+        .with_optional_arg("file", ArgKind::Path),
     );
-    app.keymap.insert(
-        'e',
-        Command::new(|app, ()| {
-            app.doc.move_next_word_end();
-            app.doc.scroll_to_cursor();
+    app.commands.insert(
+        "bn".into(),
+        ExCommand::new(|app, _| {
+            let id = app.buffers.next(app.buffer().id());
+            app.show_buffer(id);
         }),
     );
-    app.keymap.insert(
-        'b',
-        Command::new(|app, ()| {
-            app.doc.move_prev_word_start();
-            app.doc.scroll_to_cursor();
+    app.commands.insert(
+        "bp".into(),
+        ExCommand::new(|app, _| {
+            let id = app.buffers.prev(app.buffer().id());
+            app.show_buffer(id);
         }),
     );
-    app.keymap.insert(
-        'a',
-        Command::new(|app, ()| {
-            app.doc.scroll_up();
-            app.doc.move_to_view();
-        }),
+    app.commands.insert(
+        "b".into(),
+        ExCommand::new(|app, args| {
+            let id = args.number("buffer").unwrap_or_default();
+
+            if app.buffers.get(id).is_none() {
+                return app.report_error(format!("no buffer {id}"));
+            }
+
+            app.show_buffer(id);
+        })
+        .with_arg("buffer", ArgKind::Number),
+    );
+    app.commands.insert(
+        "bd".into(),
+        ExCommand::new(|app, args| {
+            let id = args.number("buffer").unwrap_or(app.buffer().id());
+
+            let Some(buffer) = app.buffers.get(id) else {
+                return app.report_error(format!("no buffer {id}"));
+            };
+
+            if buffer.doc.is_modified() && !args.bang {
+                let err = format!(
+                    "unsaved changes in {}, add ! to discard them",
+                    buffer.display_name()
+                );
+                return app.report_error(err);
+            }
+
+            app.close_buffer(id);
+        })
+        .with_bang()
+        .with_optional_arg("buffer", ArgKind::Number),
     );
-    app.keymap.insert(
-        's',
-        Command::new(|app, ()| {
-            app.doc.scroll_down();
-            app.doc.move_to_view();
+    app.commands.insert(
+        "ls".into(),
+        ExCommand::new(|app, _| {
+            let current = app.buffer().id();
+
+            let list = app
+                .buffers
+                .iter()
+                .map(|buffer| {
+                    let marker = if buffer.id() == current { "%" } else { " " };
+                    let modified = if buffer.doc.is_modified() { " [+]" } else { "" };
+                    format!(
+                        "{}{marker} {}{modified}",
+                        buffer.id(),
+                        buffer.display_name()
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            app.report_message(list.join("  |  "));
         }),
     );
+    let split =
+        ExCommand::new(|app, args| split_window(app, Direction::Vertical, args.path("file")))
+            .with_optional_arg("file", ArgKind::Path);
+    app.commands.insert("sp".into(), split.clone());
+    app.commands.insert("split".into(), split);
+    let vsplit =
+        ExCommand::new(|app, args| split_window(app, Direction::Horizontal, args.path("file")))
+            .with_optional_arg("file", ArgKind::Path);
+    app.commands.insert("vs".into(), vsplit.clone());
+    app.commands.insert("vsplit".into(), vsplit);
+    let close = ExCommand::new(|app, _| close_window(app));
+    app.commands.insert("clo".into(), close.clone());
+    app.commands.insert("close".into(), close);
     app.commands.insert(
-        "w".into(),
-        Command::new(|app, ()| {
-            if let Some(name) = &app.doc_name {
-                if let Err(err) = save_doc(name, &app.doc) {
-                    app.report_error(err);
+        "set".into(),
+        ExCommand::new(|app, args| {
+            for value in args.values("option") {
+                let Value::Setting { name, value } = value else {
+                    continue;
+                };
+
+                let result = match name.as_str() {
+                    "filetype" | "ft" => set_filetype(app, value.as_deref()),
+                    "clipboard" | "cb" => app.options.set(name, value.as_deref()).map(|()| {
+                        app.registers.clipboard = app.options.clipboard();
+                    }),
+                    _ => app.options.set(name, value.as_deref()),
+                };
+
+                if let Err(err) = result {
+                    return app.report_error(err);
                 }
-            } else {
-                app.input = Some(
-                    Input::new(|s, app| {
-                        if let Err(err) = save_doc(&PathBuf::from(&s), &app.doc) {
-                            app.report_error(err);
-                        } else {
-                            app.doc_name = Some(s.into())
-                        }
-                    })
-                    .with_placeholder("Enter file name".into()),
-                );
             }
+        })
+        .with_arg("option", ArgKind::Setting)
+        .with_many_args("option", ArgKind::Setting),
+    );
+    app.commands.insert(
+        "theme".into(),
+        ExCommand::new(|app, args| {
+            let Some(name) = args.string("name") else {
+                let names = Theme::names().join(", ");
+                return app.report_message(format!("{} (available: {names})", app.theme.name));
+            };
+
+            match Theme::load(name) {
+                Ok(theme) => app.theme = theme,
+                Err(err) => app.report_error(err),
+            }
+        })
+        .with_optional_arg("name", ArgKind::Raw),
+    );
+    app.commands.insert(
+        "make".into(),
+        ExCommand::new(|app, args| {
+            let command = match args.string("args") {
+                Some(extra) => format!("{} {extra}", app.options.make),
+                None => app.options.make.clone(),
+            };
+
+            app.report_message(format!("running {command}"));
+            app.make = Some(quickfix::run(&command));
+        })
+        .with_optional_arg("args", ArgKind::Raw),
+    );
+    app.commands.insert(
+        "cn".into(),
+        ExCommand::new(|app, _| goto_quickfix(app, true)),
+    );
+    app.commands.insert(
+        "cp".into(),
+        ExCommand::new(|app, _| goto_quickfix(app, false)),
+    );
+    app.commands.insert(
+        "grep".into(),
+        ExCommand::new(|app, args| grep(app, args.string("pattern").unwrap_or_default()))
+            .with_arg("pattern", ArgKind::Raw),
+    );
+    app.commands.insert(
+        "s".into(),
+        ExCommand::new(substitute)
+            .with_range()
+            .with_arg("pattern", ArgKind::Raw),
+    );
+}
+
+/// Keys bound in both normal and select mode.
+const KEYS: &[(&str, &str)] = &[
+    ("i", "enter_insert"),
+    ("v", "enter_select"),
+    ("V", "enter_select_line"),
+    ("d", "delete"),
+    ("c", "change"),
+    ("y", "yank"),
+    (">", "indent"),
+    ("<lt>", "outdent"),
+    ("p", "paste_after"),
+    ("P", "paste_before"),
+    ("\"", "select_register"),
+    ("u", "undo"),
+    ("U", "redo"),
+    ("h", "move_left"),
+    ("j", "move_up"),
+    ("k", "move_down"),
+    ("l", "move_right"),
+    ("w", "move_next_word_start"),
+    ("e", "move_next_word_end"),
+    ("b", "move_prev_word_start"),
+    ("0", "move_line_start"),
+    ("$", "move_line_end"),
+    ("gg", "goto_first_line"),
+    ("G", "goto_last_line"),
+    ("a", "scroll_up"),
+    ("s", "scroll_down"),
+    ("/", "search"),
+    ("?", "search_backward"),
+    ("n", "search_next"),
+    ("N", "search_prev"),
+    ("<A-o>", "expand_selection"),
+    ("<A-i>", "shrink_selection"),
+    ("<A-n>", "next_sibling"),
+    ("<A-p>", "prev_sibling"),
+    ("zc", "close_fold"),
+    ("zo", "open_fold"),
+    ("za", "toggle_fold"),
+    ("zM", "close_all_folds"),
+    ("zR", "open_all_folds"),
+    ("<space>f", "file_picker"),
+    ("<space>s", "symbol_picker"),
+    ("<space>/", "global_search"),
+    ("]f", "next_function"),
+    ("[f", "prev_function"),
+    (":", "command_mode"),
+    ("<C-w>h", "window_left"),
+    ("<C-w>j", "window_up"),
+    ("<C-w>k", "window_down"),
+    ("<C-w>l", "window_right"),
+    ("<C-w>w", "window_next"),
+    ("<C-w><C-w>", "window_next"),
+    ("<C-w>W", "window_prev"),
+    ("<C-w>s", "split"),
+    ("<C-w>v", "vsplit"),
+    ("<C-w>q", "close_window"),
+    ("<C-w>c", "close_window"),
+];
+
+const NORMAL_KEYS: &[(&str, &str)] = &[("x", "delete_char"), (".", "repeat")];
+
+/// Text objects, taken by operators in normal mode and selected in select
+/// mode.
+const OBJECT_KEYS: &[(&str, &str)] = &[
+    ("iw", "inner_word"),
+    ("aw", "around_word"),
+    ("i(", "inner_paren"),
+    ("a(", "around_paren"),
+    ("ib", "inner_paren"),
+    ("ab", "around_paren"),
+    ("i[", "inner_bracket"),
+    ("a[", "around_bracket"),
+    ("i{", "inner_brace"),
+    ("a{", "around_brace"),
+    ("iB", "inner_brace"),
+    ("aB", "around_brace"),
+    ("i\"", "inner_double_quote"),
+    ("a\"", "around_double_quote"),
+    ("i'", "inner_single_quote"),
+    ("a'", "around_single_quote"),
+    ("if", "inner_function"),
+    ("af", "around_function"),
+    ("it", "inner_class"),
+    ("at", "around_class"),
+    ("ia", "inner_parameter"),
+    ("aa", "around_parameter"),
+    ("ic", "inner_comment"),
+    ("ac", "around_comment"),
+];
+
+const INSERT_KEYS: &[(&str, &str)] = &[
+    ("<Enter>", "insert_newline"),
+    ("<BS>", "delete_char_backward"),
+    ("<Tab>", "insert_tab"),
+    ("<C-n>", "complete"),
+];
+
+fn actions(app: &mut App) {
+    command(app, "enter_insert", |app| {
+        let (view, doc) = app.current_mut();
+        view.enter_insert(doc);
+    });
+    command(app, "enter_select", |app| {
+        app.windows.focused_mut().enter_select()
+    });
+    command(app, "enter_select_line", |app| {
+        app.windows.focused_mut().enter_select_line()
+    });
+
+    for (name, op) in [
+        ("delete", Operator::Delete),
+        ("change", Operator::Change),
+        ("yank", Operator::Yank),
+        ("indent", Operator::Indent),
+        ("outdent", Operator::Outdent),
+    ] {
+        app.actions.insert(name.into(), Action::Operator(op));
+    }
+
+    command(app, "delete_char", |app| {
+        let count = app.take_count();
+        let right = Motion::repeated(MotionKind::Exclusive, View::move_right);
+        operator::apply(app, Operator::Delete, Target::Motion(right), count);
+    });
+    command(app, "repeat", operator::repeat);
+    command(app, "paste_after", |app| paste(app, false));
+    command(app, "paste_before", |app| paste(app, true));
+    command(app, "select_register", |app| {
+        app.pending_key = Some(Command::new(|app, name| {
+            if !app.registers.select(name) {
+                app.report_error(format!("invalid register: {name}"));
+            }
+        }));
+    });
+    command(app, "undo", |app| {
+        let (view, doc) = app.current_mut();
+        view.undo(doc);
+        view.scroll_to_cursor(doc);
+    });
+    command(app, "redo", |app| {
+        let (view, doc) = app.current_mut();
+        view.redo(doc);
+        view.scroll_to_cursor(doc);
+    });
+
+    for (name, kind, f) in [
+        (
+            "move_left",
+            MotionKind::Exclusive,
+            View::move_left as fn(&mut View, &Document),
+        ),
+        ("move_up", MotionKind::Linewise, View::move_up),
+        ("move_down", MotionKind::Linewise, View::move_down),
+        ("move_right", MotionKind::Exclusive, View::move_right),
+        (
+            "move_next_word_start",
+            MotionKind::Exclusive,
+            View::move_next_word,
+        ),
+        (
+            "move_next_word_end",
+            MotionKind::Inclusive,
+            View::move_next_word_end,
+        ),
+        (
+            "move_prev_word_start",
+            MotionKind::Exclusive,
+            View::move_prev_word_start,
+        ),
+    ] {
+        app.actions
+            .insert(name.into(), Action::Motion(Motion::repeated(kind, f)));
+    }
+
+    motion(
+        app,
+        "move_line_start",
+        MotionKind::Exclusive,
+        |view, doc, _| view.move_line_start(doc),
+    );
+    motion(
+        app,
+        "move_line_end",
+        MotionKind::Inclusive,
+        |view, doc, count| view.move_line_end(doc, count.unwrap_or(1)),
+    );
+    motion(
+        app,
+        "goto_first_line",
+        MotionKind::Linewise,
+        |view, doc, count| view.goto_line(doc, count.unwrap_or(1).saturating_sub(1)),
+    );
+    motion(
+        app,
+        "goto_last_line",
+        MotionKind::Linewise,
+        |view, doc, count| {
+            // Not the empty line after a final newline.
+            let rope = doc.rope();
+            let mut last = rope.len_lines() - 1;
+            if last > 0 && rope.line(last).len_chars() == 0 {
+                last -= 1;
+            }
+
+            view.goto_line(doc, count.map_or(last, |line| line.saturating_sub(1)))
+        },
+    );
+
+    for (name, object) in [
+        ("inner_word", TextObject::word(false)),
+        ("around_word", TextObject::word(true)),
+        ("inner_paren", TextObject::pair('(', ')', false)),
+        ("around_paren", TextObject::pair('(', ')', true)),
+        ("inner_bracket", TextObject::pair('[', ']', false)),
+        ("around_bracket", TextObject::pair('[', ']', true)),
+        ("inner_brace", TextObject::pair('{', '}', false)),
+        ("around_brace", TextObject::pair('{', '}', true)),
+        ("inner_double_quote", TextObject::quote('"', false)),
+        ("around_double_quote", TextObject::quote('"', true)),
+        ("inner_single_quote", TextObject::quote('\'', false)),
+        ("around_single_quote", TextObject::quote('\'', true)),
+        ("inner_function", TextObject::syntax("function", false)),
+        ("around_function", TextObject::syntax("function", true)),
+        ("inner_class", TextObject::syntax("class", false)),
+        ("around_class", TextObject::syntax("class", true)),
+        ("inner_parameter", TextObject::syntax("parameter", false)),
+        ("around_parameter", TextObject::syntax("parameter", true)),
+        ("inner_comment", TextObject::syntax("comment", false)),
+        ("around_comment", TextObject::syntax("comment", true)),
+    ] {
+        app.actions.insert(name.into(), Action::TextObject(object));
+    }
+
+    command(app, "scroll_up", |app| {
+        let (view, doc) = app.current_mut();
+        view.scroll_up(doc);
+        view.move_to_view(doc);
+    });
+    command(app, "scroll_down", |app| {
+        let (view, doc) = app.current_mut();
+        view.scroll_down(doc);
+        view.move_to_view(doc);
+    });
+    command(app, "search", |app| open_search(app, false));
+    command(app, "search_backward", |app| open_search(app, true));
+    command(app, "search_next", |app| {
+        let from = app.windows.focused().position_byte();
+        jump_to_match(app, from, false);
+    });
+    command(app, "search_prev", |app| {
+        let from = app.windows.focused().position_byte();
+        jump_to_match(app, from, true);
+    });
+    command(app, "expand_selection", |app| {
+        let (view, doc) = app.current_mut();
+        view.expand_selection(doc);
+        view.scroll_to_cursor(doc);
+    });
+    command(app, "shrink_selection", |app| {
+        let (view, doc) = app.current_mut();
+        view.shrink_selection(doc);
+        view.scroll_to_cursor(doc);
+    });
+    command(app, "next_sibling", |app| {
+        let (view, doc) = app.current_mut();
+        view.goto_sibling(doc, true);
+        view.scroll_to_cursor(doc);
+    });
+    command(app, "prev_sibling", |app| {
+        let (view, doc) = app.current_mut();
+        view.goto_sibling(doc, false);
+        view.scroll_to_cursor(doc);
+    });
+    command(app, "close_fold", close_fold);
+    command(app, "open_fold", |app| {
+        let (view, doc) = app.current_mut();
+        let (_, line_idx) = view.position(doc);
+        let rope = doc.rope().clone();
+        doc.folds_mut().open(&rope, line_idx);
+    });
+    command(app, "toggle_fold", |app| {
+        let (view, doc) = app.current_mut();
+        let (_, line_idx) = view.position(doc);
+        let rope = doc.rope().clone();
+
+        if !doc.folds_mut().open(&rope, line_idx) {
+            close_fold(app);
+        }
+    });
+    command(app, "close_all_folds", |app| {
+        let buffer = app.buffer_mut();
+        let regions = fold::regions(&buffer.language, &buffer.doc);
+        buffer.doc.folds_mut().close_all(regions);
+    });
+    command(app, "open_all_folds", |app| {
+        app.buffer_mut().doc.folds_mut().open_all()
+    });
+    command(app, "file_picker", file_picker);
+    command(app, "symbol_picker", symbol_picker);
+    command(app, "global_search", |app| {
+        app.input = Some(
+            Input::new(|pattern, app| grep(app, &pattern))
+                .with_symbol('/')
+                .with_placeholder("search the workspace".into()),
+        );
+    });
+    command(app, "next_function", |app| {
+        goto_object(app, "function", true)
+    });
+    command(app, "prev_function", |app| {
+        goto_object(app, "function", false)
+    });
+    command(app, "command_mode", App::open_command);
+    command(app, "window_left", |app| app.windows.focus_side(Side::Left));
+    command(app, "window_up", |app| app.windows.focus_side(Side::Up));
+    command(app, "window_down", |app| app.windows.focus_side(Side::Down));
+    command(app, "window_right", |app| {
+        app.windows.focus_side(Side::Right)
+    });
+    command(app, "window_next", |app| app.windows.cycle(false));
+    command(app, "window_prev", |app| app.windows.cycle(true));
+    command(app, "split", |app| app.windows.split(Direction::Vertical));
+    command(app, "vsplit", |app| {
+        app.windows.split(Direction::Horizontal)
+    });
+    command(app, "close_window", close_window);
+    command(app, "insert_newline", |app| {
+        let (view, doc) = app.current_mut();
+        view.insert(doc, '\n');
+    });
+    command(app, "delete_char_backward", |app| {
+        let (view, doc) = app.current_mut();
+        view.remove_before(doc);
+    });
+    command(app, "complete", |app| {
+        app.complete();
+
+        if let Some(completion) = &mut app.completion {
+            completion.move_by(1);
+        }
+    });
+    command(app, "insert_tab", |app| {
+        let tab_width = app.options.tab_width;
+        let (view, doc) = app.current_mut();
+        let (x, _) = view.position(doc);
+        for _ in 0..tab_width - x % tab_width {
+            view.insert(doc, ' ');
+        }
+    });
+}
+
+fn command(app: &mut App, name: &str, f: impl Fn(&mut App) + 'static) {
+    app.actions.insert(
+        name.into(),
+        Action::Command(Command::new(move |app, ()| f(app))),
+    );
+}
+
+fn motion(
+    app: &mut App,
+    name: &str,
+    kind: MotionKind,
+    f: impl Fn(&mut View, &Document, Option<usize>) + 'static,
+) {
+    app.actions
+        .insert(name.into(), Action::Motion(Motion::new(kind, f)));
+}
+
+fn write(app: &mut App, args: Args) {
+    let buffer = app.buffer();
+
+    let Some(path) = args.path("file").or(buffer.path.as_deref()) else {
+        app.input = Some(
+            Input::new(|s, app| write_to(app, Path::new(&s)))
+                .with_placeholder("Enter file name".into()),
+        );
+        return;
+    };
+
+    let path = path.to_owned();
+    write_to(app, &path);
+}
+
+/// Writes the buffer to `path`, which becomes its file if it has none. It
+/// only counts as saved when written to its own file.
+fn write_to(app: &mut App, path: &Path) {
+    let buffer = app.buffer_mut();
+
+    if let Err(err) = save_doc(path, &buffer.doc) {
+        return app.report_error(err);
+    }
+
+    let own = match &buffer.path {
+        Some(own) => same_file(own, path),
+        None => {
+            buffer.path = Some(path.to_owned());
+            true
+        }
+    };
+
+    if own {
+        buffer.doc.mark_saved();
+        let id = buffer.id();
+        app.lsp.saved(id);
+    }
+}
+
+fn split_window(app: &mut App, direction: Direction, path: Option<&Path>) {
+    app.windows.split(direction);
+
+    if let Some(path) = path
+        && let Err(err) = app.open(path)
+    {
+        app.report_error(err);
+    }
+}
+
+fn close_window(app: &mut App) {
+    if !app.windows.close() {
+        app.report_error("cannot close the last window");
+    }
+}
+
+fn open_search(app: &mut App, backward: bool) {
+    let origin = app.windows.focused().position_byte();
+    let previous = app
+        .search
+        .as_ref()
+        .map(|search| search.pattern().to_owned());
+
+    let restore = previous.clone();
+    app.input = Some(
+        Input::new(move |pattern, app| {
+            let Some(pattern) = Some(pattern)
+                .filter(|pattern| !pattern.is_empty())
+                .or(previous)
+            else {
+                return;
+            };
+
+            match Search::new(&pattern, backward) {
+                Ok(search) => {
+                    app.search = Some(search);
+                    let (view, doc) = app.current_mut();
+                    view.move_to_byte(doc, origin);
+                    jump_to_match(app, origin, false);
+                }
+                Err(err) => app.report_error(err),
+            }
+        })
+        .with_symbol(if backward { '?' } else { '/' })
+        .with_on_change(move |pattern, app| {
+            app.search = Search::new(pattern, backward)
+                .ok()
+                .filter(|_| !pattern.is_empty());
+            let rope = app.buffer().doc.rope().clone();
+            let mat = app
+                .search
+                .as_mut()
+                .and_then(|search| search.next(&rope, origin));
+
+            let (view, doc) = app.current_mut();
+            view.move_to_byte(doc, mat.map_or(origin, |mat| mat.start));
+            view.scroll_to_cursor(doc);
+        })
+        .with_on_cancel(move |app| {
+            app.search = restore.and_then(|pattern| Search::new(&pattern, backward).ok());
+            let (view, doc) = app.current_mut();
+            view.move_to_byte(doc, origin);
+            view.scroll_to_cursor(doc);
         }),
     );
 }
 
+/// Moves the cursor to the next match of the current search, or the previous
+/// one if `reverse` is set and the search is forward.
+fn jump_to_match(app: &mut App, from: usize, reverse: bool) {
+    let rope = app.buffer().doc.rope().clone();
+
+    let Some(search) = app.search.as_mut() else {
+        app.report_error("no previous search pattern");
+        return;
+    };
+
+    let mat = if search.backward != reverse {
+        search.prev(&rope, from)
+    } else {
+        search.next(&rope, from)
+    };
+
+    match mat {
+        Some(mat) => {
+            let (view, doc) = app.current_mut();
+            view.move_to_byte(doc, mat.start);
+            view.scroll_to_cursor(doc);
+        }
+        None => {
+            let err = format!("pattern not found: {}", search.pattern());
+            app.report_error(err);
+        }
+    }
+}
+
+/// Picks a file under the current directory to open, listing the files as
+/// they are found.
+fn file_picker(app: &mut App) {
+    let (sender, source) = mpsc::channel();
+    files::walk(Path::new("."), move |path| {
+        sender.send(Item::file(path)).is_ok()
+    });
+
+    app.picker = Some(
+        Picker::new("Files", Vec::new(), |_, item, app| {
+            if let Some(location) = &item.location
+                && let Err(err) = app.open(&location.path)
+            {
+                app.report_error(format!("{}: {err}", location.path.display()));
+            }
+        })
+        .with_source(source)
+        .with_previews(),
+    );
+}
+
+/// Jumps to the next or previous entry of the quickfix list, showing its
+/// message.
+fn goto_quickfix(app: &mut App, next: bool) {
+    let len = app.quickfix.entries().len();
+    let Some((idx, entry)) = app.quickfix.step(next) else {
+        return app.report_error("no quickfix entries");
+    };
+    let entry = entry.clone();
+
+    if let Err(err) = app.open(&entry.path) {
+        return app.report_error(format!("{}: {err}", entry.path.display()));
+    }
+
+    let (view, doc) = app.current_mut();
+    view.move_to_byte(doc, entry.start(doc.rope()));
+    view.scroll_to_cursor(doc);
+
+    let message = format!(
+        "({} of {len}) {}: {}",
+        idx + 1,
+        entry.severity.name(),
+        entry.message
+    );
+    app.report_message(message);
+}
+
+/// Lists the matches of `pattern` in the files under the current directory
+/// as they are found, to jump to one.
+fn grep(app: &mut App, pattern: &str) {
+    if pattern.is_empty() {
+        return;
+    }
+
+    let source = match grep::search(pattern) {
+        Ok(source) => source,
+        Err(err) => return app.report_error(err),
+    };
+
+    let mut picker = Picker::new(format!("Search {pattern}"), Vec::new(), |_, item, app| {
+        let Some(location) = &item.location else {
+            return;
+        };
+
+        if let Err(err) = app.open(&location.path) {
+            return app.report_error(format!("{}: {err}", location.path.display()));
+        }
+
+        let (view, doc) = app.current_mut();
+        view.move_to_byte(doc, location.byte(doc.rope()));
+        view.scroll_to_cursor(doc);
+    })
+    .with_source(source)
+    .with_previews();
+
+    if let Ok(search) = Search::new(pattern, false) {
+        picker = picker.with_search(search);
+    }
+
+    app.picker = Some(picker);
+}
+
+/// Lists the symbols of the focused buffer, moving the cursor to the
+/// highlighted one.
+fn symbol_picker(app: &mut App) {
+    let buffer = app.buffer_mut();
+    let language = buffer.language.clone();
+    let symbols = buffer.doc.symbols(&language).to_vec();
+
+    if symbols.is_empty() {
+        let err = format!("no symbols in {}", buffer.display_name());
+        return app.report_error(err);
+    }
+
+    let items = symbols
+        .iter()
+        .map(|symbol| {
+            let mut path: Vec<String> = (symbol.parents.iter())
+                .map(|&parent| symbols[parent].label())
+                .collect();
+            path.push(symbol.label());
+            Item::new(path.join(" > "))
+        })
+        .collect();
+
+    let saved = app.windows.focused().clone();
+    let cursor = saved.position_byte();
+    let current = symbols
+        .iter()
+        .rposition(|symbol| symbol.range.start <= cursor)
+        .unwrap_or_default();
+
+    let jump = move |app: &mut App, byte: usize| {
+        let (view, doc) = app.current_mut();
+        view.move_to_byte(doc, byte);
+        view.center_cursor(doc);
+    };
+
+    let targets: Vec<usize> = symbols.iter().map(|symbol| symbol.name_byte).collect();
+    let preview = targets.clone();
+
+    app.picker = Some(
+        Picker::new("Symbols", items, move |idx, _, app| jump(app, targets[idx]))
+            .with_on_change(move |idx, _, app| jump(app, preview[idx]))
+            .with_on_cancel(move |app| *app.windows.focused_mut() = saved)
+            .with_selected(current),
+    );
+}
+
+/// Closes the smallest fold around the cursor line that is not closed yet.
+fn close_fold(app: &mut App) {
+    let (view, doc) = app.current();
+    let (_, line_idx) = view.position(doc);
+
+    let buffer = app.buffer_mut();
+    let regions = fold::regions(&buffer.language, &buffer.doc);
+    let rope = buffer.doc.rope().clone();
+    buffer.doc.folds_mut().close(&rope, &regions, line_idx);
+}
+
+/// Moves the cursor to the start of the next or previous text object called
+/// `name`, or the `count`th one.
+fn goto_object(app: &mut App, name: &str, next: bool) {
+    let count = app.take_count().unwrap_or(1);
+    let buffer = app.buffer();
+    let cursor = app.windows.focused().position_byte();
+
+    let (Some(query), Some(tree)) = (
+        buffer
+            .language
+            .grammar()
+            .and_then(|grammar| grammar.textobjects()),
+        buffer.doc.tree(),
+    ) else {
+        return;
+    };
+
+    let rope = buffer.doc.rope();
+    let range = if next {
+        cursor + 1..rope.len_bytes()
+    } else {
+        0..cursor
+    };
+
+    let mut starts: Vec<usize> = syntax::objects(query, tree, rope, name, range)
+        .into_iter()
+        .map(|object| object.around.start)
+        .filter(|&start| if next { start > cursor } else { start < cursor })
+        .collect();
+
+    starts.sort_unstable();
+    starts.dedup();
+
+    if !next {
+        starts.reverse();
+    }
+
+    if let Some(&start) = starts.get(count - 1) {
+        let (view, doc) = app.current_mut();
+        view.move_to_byte(doc, start);
+        view.scroll_to_cursor(doc);
+    }
+}
+
+fn substitute(app: &mut App, args: Args) {
+    let last_pattern = app.search.as_ref().map(Search::pattern);
+
+    let pattern = args.string("pattern").unwrap_or_default();
+
+    let substitution = match Substitution::parse(pattern, last_pattern) {
+        Ok(substitution) => substitution,
+        Err(err) => return app.report_error(err),
+    };
+
+    let (view, doc) = app.current();
+    let lines = args.range.unwrap_or_else(|| view.selected_lines(doc));
+
+    let preview = substitution.preview(doc.rope(), lines);
+
+    if preview.is_empty() {
+        return app.report_error("pattern not found");
+    }
+
+    app.preview = Some(preview);
+    app.pending_key = Some(Command::new(|app, key| {
+        if let Some(preview) = app.preview.take()
+            && key == 'y'
+        {
+            let (view, doc) = app.current_mut();
+            view.apply_edits(doc, &preview.edits);
+            view.enter_normal(doc);
+            view.scroll_to_cursor(doc);
+        }
+    }));
+}
+
+/// Sets the language of the focused buffer, or shows it without `name`.
+fn set_filetype(app: &mut App, name: Option<&str>) -> Result<(), String> {
+    let Some(name) = name else {
+        let name = app.buffer().language.name().to_owned();
+        app.report_message(format!("filetype={name}"));
+        return Ok(());
+    };
+
+    let language = app.languages.load(name)?;
+    app.buffer_mut().set_language(language);
+    Ok(())
+}
+
+fn paste(app: &mut App, before: bool) {
+    match app.registers.paste() {
+        Ok(text) => {
+            let (view, doc) = app.current_mut();
+            view.paste(doc, &text, before);
+        }
+        Err(err) => app.report_error(err),
+    }
+}
+
 fn save_doc(name: &Path, doc: &Document) -> io::Result<()> {
     let file = File::create(name)?;
-    let writer = BufWriter::new(file);
-    doc.rope().write_to(writer)
+    let mut writer = BufWriter::new(file);
+    doc.rope().write_to(&mut writer)?;
+    writer.flush()
 }
@@ -0,0 +1,41 @@
+use std::ops::Range;
+
+use crate::document::Edit;
+
+/// How serious a diagnostic is, ordered from least to most.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Hint,
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    /// Its name, which is also the theme scope of its sign in the gutter.
+    pub fn name(self) -> &'static str {
+        match self {
+            Severity::Hint => "hint",
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+/// A problem reported in a document, e.g. a compiler error.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    /// What reported it, e.g. `make`.
+    pub source: &'static str,
+    pub range: Range<usize>,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// Moves the diagnostic along with an edit.
+    pub fn map(&mut self, edit: &Edit) {
+        self.range = edit.map(self.range.start)..edit.map(self.range.end);
+    }
+}
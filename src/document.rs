@@ -1,565 +1,631 @@
-use ratatui::{
-    buffer::{Buffer, Cell},
-    layout::{Alignment, Constraint, Layout, Position, Rect},
-    widgets::{Paragraph, Widget},
+use ropey::{Rope, RopeSlice};
+use std::{
+    collections::BTreeMap,
+    ops::Range,
+    time::{Duration, Instant},
+};
+use tree_sitter::{InputEdit, Point, TextProvider, Tree};
+
+use crate::{
+    completion::Words,
+    diagnostic::Diagnostic,
+    fold::Folds,
+    highlighter::{self, Highlighter, Request, Response},
+    language::Language,
+    symbol::{self, Symbol},
+    view::Mode,
 };
-use ropey::Rope;
-use std::ops::{Add, RangeInclusive};
-use tree_sitter::{InputEdit, Parser, Point, Query, QueryCursor, QueryMatches, TextProvider, Tree};
-
-use crate::{language::Language, theme::Theme};
 
+/// The text of a buffer with its syntax tree and history. The cursor lives in
+/// the [`View`](crate::view::View)s showing it.
 #[derive(Default)]
 pub struct Document {
     content: Rope,
     tree: Option<Tree>,
-    mode: Mode,
-    position_byte: usize,
-    position_x: usize,
-    scroll_y: usize,
-    // TODO: Might be better to put this behind a `Cell`?
-    // TODO: Invalidate this after resize?
-    last_view_area: Option<Rect>,
+    history: History,
+    edits: Vec<Edit>,
+    /// Counts the edits, to tell whether the tree and highlights are up to
+    /// date.
+    version: usize,
+    /// The highlights drawn by each window showing the document, so windows
+    /// scrolled to different places do not replace each other's.
+    highlights: BTreeMap<usize, Highlights>,
+    request: Option<InFlight>,
+    /// The last parse that took too long, retried with more time while the
+    /// text does not change.
+    failed: Option<Failed>,
+    folds: Folds,
+    /// The symbols of the tree, until it changes.
+    symbols: Option<Vec<Symbol>>,
+    diagnostics: Vec<Diagnostic>,
+    /// The edits not sent to the language server yet, once it has the text.
+    changes: Option<Vec<ContentChange>>,
+    /// The words of the text, once completion asked for them.
+    words: Option<Words>,
+}
+
+/// The highlights of a range of the text, kept until an edit changes the
+/// syntax in it.
+struct Highlights {
+    range: Range<usize>,
+    styles: Vec<TokenStyle>,
+    /// The version of the text they were computed for.
+    version: usize,
+    /// Whether an edit was made in the range since.
+    touched: bool,
+}
+
+/// A request to the highlighter, with the edits made since it was sent.
+struct InFlight {
+    id: usize,
+    /// The window the highlights are for.
+    window: usize,
+    version: usize,
+    edits: Vec<InputEdit>,
+    timeout: Duration,
 }
 
+/// A version of the text whose parse timed out, and when.
+struct Failed {
+    version: usize,
+    timeout: Duration,
+    at: Instant,
+}
+
+/// How long the text has to stay the same before a parse that timed out is
+/// retried.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
 impl Document {
     pub fn new(content: Rope) -> Self {
         Self {
             content,
             tree: None,
-            mode: Mode::Normal,
-            position_byte: 0,
-            position_x: 0,
-            scroll_y: 0,
-            last_view_area: None,
+            history: History::default(),
+            edits: Vec::new(),
+            version: 0,
+            highlights: BTreeMap::new(),
+            request: None,
+            failed: None,
+            folds: Folds::default(),
+            symbols: None,
+            diagnostics: Vec::new(),
+            changes: None,
+            words: None,
         }
     }
 
-    pub fn enter_normal(&mut self) {
-        self.mode = Mode::Normal;
-    }
-
-    pub fn enter_insert(&mut self) {
-        self.mode = Mode::Insert;
+    pub fn rope(&self) -> &Rope {
+        &self.content
     }
 
-    pub fn enter_select(&mut self) {
-        self.mode = Mode::Select {
-            start_byte: self.position_byte,
-            line_mode: false,
-        };
+    pub fn folds(&self) -> &Folds {
+        &self.folds
     }
 
-    pub fn enter_select_line(&mut self) {
-        self.mode = Mode::Select {
-            start_byte: self.position_byte,
-            line_mode: true,
-        }
+    pub fn folds_mut(&mut self) -> &mut Folds {
+        &mut self.folds
     }
 
-    pub fn mode(&self) -> Mode {
-        self.mode
+    /// The diagnostics of the text, moved along with edits.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
     }
 
-    pub fn rope(&self) -> &Rope {
-        &self.content
+    /// Replaces the diagnostics from `source`, e.g. `make`.
+    pub fn set_diagnostics(&mut self, source: &str, diagnostics: Vec<Diagnostic>) {
+        self.diagnostics
+            .retain(|diagnostic| diagnostic.source != source);
+        self.diagnostics.extend(diagnostics);
     }
 
-    pub fn parse(&mut self, parser: &mut Parser) -> &Tree {
-        let tree = parser
-            .parse_with(
-                &mut |byte, _| -> &[u8] {
-                    let Some((mut chunks, chunk_start, ..)) = self.rope().get_chunks_at_byte(byte)
-                    else {
-                        return &[];
-                    };
-
-                    let offset = byte - chunk_start;
+    /// The symbols defined in the text, from the language's `tags.scm`. None
+    /// until the document has been parsed.
+    pub fn symbols(&mut self, language: &Language) -> &[Symbol] {
+        let query = language.grammar().and_then(|grammar| grammar.tags());
 
-                    let Some(chunk) = chunks.next() else {
-                        return &[];
-                    };
-
-                    let chunk = &chunk[offset..];
+        if self.symbols.is_none()
+            && let Some(query) = query
+            && let Some(tree) = &self.tree
+        {
+            self.symbols = Some(symbol::symbols(query, tree, &self.content));
+        }
 
-                    if chunk.is_empty() {
-                        for chunk in chunks {
-                            if !chunk.is_empty() {
-                                return chunk.as_bytes();
-                            }
-                        }
-                        &[]
-                    } else {
-                        chunk.as_bytes()
-                    }
-                },
-                self.tree.as_ref(),
-            )
-            .expect("Parser::set_language was called");
+        self.symbols.as_deref().unwrap_or_default()
+    }
 
-        self.tree.insert(tree)
+    /// The last syntax tree, edited to match the text, or none until the
+    /// document has been parsed.
+    pub fn tree(&self) -> Option<&Tree> {
+        self.tree.as_ref()
     }
 
-    #[must_use]
-    pub fn render(
+    /// Asks the highlighter for the highlights of `range` shown in `window`
+    /// unless the window's cached ones are up to date, or a request is in
+    /// flight. Until the response comes in, the cached highlights are shown,
+    /// moved along with edits.
+    pub fn request_highlights(
         &mut self,
-        language: &mut Language,
-        theme: &Theme,
-        area: Rect,
-        buf: &mut Buffer,
-    ) -> Option<Position> {
-        self.parse(language.parser());
-        let tree = self.tree.as_ref().unwrap();
-        let text_provider = RopeTextProvider::new(&self.content);
-        let mut query_cursor = QueryCursor::new();
-        let matches = query_cursor.matches(language.highlights(), tree.root_node(), text_provider);
-        let styles_vec = highlights_from_matches(language.highlights(), matches);
-        let mut styles = styles_vec.iter().peekable();
-
-        let [editor, status] =
-            Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(area);
-
-        #[expect(unused_variables)]
-        let area = ();
-
-        let (_, y) = self.position();
-        let selection = self.selection();
-
-        let line_number_width = (self.scroll_y + editor.height as usize)
-            .to_string()
-            .chars()
-            .count() as u16
-            + 4;
-
-        let layout =
-            Layout::horizontal([Constraint::Length(line_number_width), Constraint::Min(0)]);
-
-        self.last_view_area = Some(layout.areas::<2>(editor)[1]);
-
-        buf.set_style(editor, theme.editor);
+        buffer: usize,
+        window: usize,
+        language: &Language,
+        range: Range<usize>,
+        highlighter: &mut Highlighter,
+    ) {
+        if language.grammar().is_none() {
+            self.highlights.clear();
+            return;
+        }
 
-        let scrolled_bytes = self.content.line_to_byte(self.scroll_y);
+        if self.request.is_some() {
+            return;
+        }
 
-        let mut rows = editor.rows();
-        let mut byte = scrolled_bytes;
-        let mut cursor = None;
+        // A parse that timed out is retried with twice the time once the
+        // text stayed the same for a while.
+        let timeout = match &self.failed {
+            Some(failed) if failed.version == self.version => {
+                if self.retry_in().is_none_or(|delay| !delay.is_zero()) {
+                    return;
+                }
 
-        for (idx, line) in self
-            .content
-            .byte_slice(scrolled_bytes..)
-            .lines()
-            .enumerate()
-        {
-            let Some(row) = rows.next() else {
-                break;
-            };
+                (failed.timeout * 2).min(highlighter::MAX_PARSE_TIMEOUT)
+            }
+            _ => highlighter::PARSE_TIMEOUT,
+        };
 
-            let [nums, text] = layout.areas(row);
+        let covered = |cached: &&Highlights| {
+            cached.range.start <= range.start && range.end <= cached.range.end
+        };
 
-            let line_style = theme.editor.patch(
-                (self.scroll_y + idx == y)
-                    .then_some(theme.active_line)
-                    .unwrap_or_default(),
-            );
+        let cached = self.highlights.get(&window).filter(covered);
 
-            buf.set_style(row, line_style);
+        if cached.is_some_and(|cached| cached.version == self.version) {
+            return;
+        }
 
-            Paragraph::new(format!("  {}  ", self.scroll_y + idx + 1))
-                .style(line_style.patch(theme.line_numbers))
-                .alignment(Alignment::Right)
-                .render(nums, buf);
+        let cached = cached
+            .filter(|cached| !cached.touched)
+            .map(|cached| cached.range.clone());
+
+        let id = highlighter.send(Request {
+            id: 0,
+            buffer,
+            text: self.content.clone(),
+            tree: self.tree.clone(),
+            language: language.clone(),
+            range,
+            cached,
+            timeout,
+        });
+
+        self.request = Some(InFlight {
+            id,
+            window,
+            version: self.version,
+            edits: Vec::new(),
+            timeout,
+        });
+    }
+
+    /// Whether the current text took too long to parse, so its highlights
+    /// are missing or out of date.
+    pub fn parse_failed(&self) -> bool {
+        self.failed
+            .as_ref()
+            .is_some_and(|failed| failed.version == self.version)
+    }
+
+    /// How long until a parse that timed out is retried, if it will be.
+    pub fn retry_in(&self) -> Option<Duration> {
+        let failed = self.failed.as_ref()?;
+
+        (self.request.is_none()
+            && failed.version == self.version
+            && failed.timeout < highlighter::MAX_PARSE_TIMEOUT)
+            .then(|| RETRY_DELAY.saturating_sub(failed.at.elapsed()))
+    }
+
+    /// Takes in the highlighter's response to the last request, applying the
+    /// edits made since it was sent.
+    pub fn receive_highlights(&mut self, response: Response) {
+        let Some(request) = self.request.take_if(|request| request.id == response.id) else {
+            return;
+        };
 
-            let mut byte_x = byte;
+        let Some(mut tree) = response.tree else {
+            self.failed = Some(Failed {
+                version: request.version,
+                timeout: request.timeout,
+                at: Instant::now(),
+            });
+            return;
+        };
 
-            let mut positions = text.positions();
-            for (ch, pos) in line.chars().zip(&mut positions) {
-                let mut cell = Cell::EMPTY;
-                cell.set_char(ch);
-                cell.set_style(line_style);
+        self.failed = None;
 
-                while styles.peek().is_some_and(|p| p.before(byte_x)) {
-                    styles.next();
-                }
+        for edit in &request.edits {
+            tree.edit(edit);
+        }
 
-                if let Some(peek) = styles.peek()
-                    && peek.contains(byte_x)
-                {
-                    cell.set_style(theme.get_token_style(peek.style));
-                }
+        self.tree = Some(tree);
+        self.symbols = None;
 
-                if selection.contains(&byte_x) {
-                    cell.set_style(theme.editor.patch(theme.selection));
-                }
+        match response.styles {
+            Some(styles) => {
+                let mut highlights = Highlights {
+                    range: response.range,
+                    styles,
+                    version: request.version,
+                    touched: false,
+                };
 
-                if ch == '\n' {
-                    cell.set_char(' ');
+                for edit in &request.edits {
+                    highlights.map(&Edit::from(edit));
                 }
 
-                buf[pos] = cell;
-
-                if self.position_byte == byte_x {
-                    cursor = Some(pos);
+                self.highlights.insert(request.window, highlights);
+            }
+            None => {
+                if let Some(highlights) = self.highlights.get_mut(&request.window) {
+                    highlights.version = request.version;
                 }
-
-                byte_x += ch.len_utf8();
             }
+        }
+    }
 
-            if self.position_byte == byte_x {
-                cursor = positions.next()
-            }
+    /// The highlighted ranges of the text shown in `window`, sorted and not
+    /// overlapping. Plain text has none.
+    pub fn highlights(&self, window: usize) -> &[TokenStyle] {
+        self.highlights
+            .get(&window)
+            .map_or(&[], |highlights| &highlights.styles)
+    }
 
-            byte += line.len_bytes();
-        }
+    /// Drops the highlights of the windows `keep` is false for, e.g. those
+    /// closed or showing another buffer.
+    pub fn retain_highlights(&mut self, mut keep: impl FnMut(usize) -> bool) {
+        self.highlights.retain(|window, _| keep(*window));
+    }
 
-        let mode = match self.mode {
-            Mode::Normal => "NOR",
-            Mode::Insert => "INS",
-            Mode::Select {
-                line_mode: false, ..
-            } => "SEL",
-            Mode::Select {
-                line_mode: true, ..
-            } => "LIN",
-        };
+    /// Drops the syntax tree, which belongs to the previous language after a
+    /// filetype change.
+    pub fn clear_tree(&mut self) {
+        self.tree = None;
+        self.symbols = None;
+        self.request = None;
+        self.failed = None;
+        self.clear_highlights();
+    }
 
-        Paragraph::new(format!(" {mode}")).render(status, buf);
+    /// Drops the cached highlights, to compute them again.
+    pub fn clear_highlights(&mut self) {
+        self.highlights.clear();
+    }
 
-        let (line_idx, x_offset) = self.position();
-        Paragraph::new(format!("{}:{} ", line_idx + 1, x_offset + 1))
-            .style(theme.status_bar)
-            .alignment(Alignment::Right)
-            .render(status, buf);
+    pub fn is_modified(&self) -> bool {
+        self.history.revision() != self.history.saved
+    }
 
-        cursor
+    pub fn mark_saved(&mut self) {
+        self.history.saved = self.history.revision();
     }
 
-    fn selection(&self) -> RangeInclusive<usize> {
-        match self.mode {
-            Mode::Select {
-                start_byte,
-                line_mode: false,
-            } => self.position_byte.min(start_byte)..=self.position_byte.max(start_byte),
-            Mode::Select {
-                start_byte,
-                line_mode: true,
-            } => {
-                let start_line_idx = self.content.byte_to_line(start_byte);
-                let end_line_idx = self.content.byte_to_line(self.position_byte);
+    /// Opens a transaction, returning false if one is already open.
+    pub fn begin(&mut self, before: Snapshot) -> bool {
+        self.history.begin(before)
+    }
 
-                let first_line_idx = start_line_idx.min(end_line_idx);
-                let last_line_idx = start_line_idx.max(end_line_idx);
+    pub fn commit(&mut self, after: Snapshot) {
+        self.history.commit(after);
+    }
 
-                let end_line = self.content.line(last_line_idx);
+    /// Reverts the last transaction, returning the state to restore.
+    pub fn undo(&mut self, current: Snapshot) -> Option<Snapshot> {
+        self.history.commit(current);
 
-                let start_byte = self.content.line_to_byte(first_line_idx);
-                let end_byte = self
-                    .content
-                    .line_to_byte(last_line_idx)
-                    .add(end_line.len_chars())
-                    .saturating_sub(1);
+        let transaction = self.history.undo.pop()?;
 
-                start_byte.min(end_byte)..=start_byte.max(end_byte)
-            }
-            _ => self.position_byte..=self.position_byte,
+        for change in transaction.changes.iter().rev() {
+            let inserted = change.byte..change.byte + change.inserted.len();
+            self.edit(inserted, &change.removed);
         }
-    }
-
-    #[inline(always)]
-    pub fn position(&self) -> (usize, usize) {
-        // TODO: That ain't efficient.
-        let line_idx = self.content.byte_to_line(self.position_byte);
-        let line_start = self.content.line_to_byte(line_idx);
 
-        (self.position_byte - line_start, line_idx)
+        let before = transaction.before;
+        self.history.redo.push(transaction);
+        Some(before)
     }
 
-    #[inline(always)]
-    fn move_to(&mut self, line_idx: usize, x_byte: usize) {
-        let last_line_idx = self.content.len_lines().saturating_sub(1);
-        let line_idx = line_idx.min(last_line_idx);
+    pub fn redo(&mut self, current: Snapshot) -> Option<Snapshot> {
+        self.history.commit(current);
 
-        let line_start = self.content.line_to_byte(line_idx);
+        let transaction = self.history.redo.pop()?;
 
-        let line = self.content.line(line_idx);
-        let max_x_offset = line
-            .len_bytes()
-            .saturating_sub(if line_idx == last_line_idx { 0 } else { 1 });
-        let x_offset = x_byte.min(max_x_offset);
+        for change in &transaction.changes {
+            let removed = change.byte..change.byte + change.removed.len();
+            self.edit(removed, &change.inserted);
+        }
 
-        self.position_byte = line_start + x_offset;
+        let after = transaction.after;
+        self.history.undo.push(transaction);
+        Some(after)
     }
 
-    #[inline(always)]
-    fn update_position_x(&mut self) {
-        let (x, _) = self.position();
-        self.position_x = x;
+    /// The edits made since the last call, for views to follow them.
+    pub fn take_edits(&mut self) -> Vec<Edit> {
+        std::mem::take(&mut self.edits)
     }
 
-    pub fn move_up(&mut self) {
-        let (x_offset, line_idx) = self.position();
-        self.move_to(line_idx.saturating_sub(1), x_offset.max(self.position_x));
+    /// Starts recording the edits for [`Document::take_changes`], e.g. once
+    /// a language server has the text.
+    pub fn track_changes(&mut self) {
+        self.changes = Some(Vec::new());
     }
 
-    pub fn move_down(&mut self) {
-        let (x_offset, line_idx) = self.position();
-        self.move_to(line_idx + 1, x_offset.max(self.position_x));
+    /// The edits made since the last call, once tracked.
+    pub fn take_changes(&mut self) -> Vec<ContentChange> {
+        self.changes
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
     }
 
-    pub fn move_left(&mut self) {
-        let (x_offset, line_idx) = self.position();
-        self.move_to(line_idx, x_offset.saturating_sub(1));
-        self.update_position_x();
+    /// The words of the text, counted the first time they are needed.
+    pub fn words(&mut self) -> &Words {
+        self.words
+            .get_or_insert_with(|| Words::new(self.content.slice(..)))
     }
 
-    pub fn move_right(&mut self) {
-        let (x_offset, line_idx) = self.position();
-        self.move_to(line_idx, x_offset + 1);
-        self.update_position_x();
+    pub fn byte_to_point(&self, byte: usize) -> Point {
+        let row = self.content.byte_to_line(byte);
+        let column = byte - self.content.line_to_byte(row);
+        Point::new(row, column)
     }
 
-    fn find_next_word(&self) -> Option<usize> {
-        let mut offset = self.position_byte;
-        let chars = self
+    /// Like [`Document::byte_to_point`], counting UTF-16 code units.
+    pub fn byte_to_utf16_point(&self, byte: usize) -> Point {
+        let row = self.content.byte_to_line(byte);
+        let line_start = self
             .content
-            .chars_at(self.content.byte_to_char(self.position_byte));
-        chars
-            .map(|ch| {
-                let result = (offset, ch);
-                offset += ch.len_utf8();
-                result
-            })
-            .map_windows(|[(_, a), (byte, b)]| (*byte, CharKind::new(*a) != CharKind::new(*b)))
-            .filter_map(|(byte, is_boundary)| is_boundary.then_some(byte))
-            .next()
-    }
-
-    fn find_next_word_end(&mut self) -> Option<usize> {
-        let mut offset = self.position_byte;
-        let chars = self
+            .char_to_utf16_cu(self.content.line_to_char(row));
+        let column = self
             .content
-            .chars_at(self.content.byte_to_char(self.position_byte));
-        chars
-            .map(|ch| {
-                let result = (offset, ch);
-                offset += ch.len_utf8();
-                result
-            })
-            .skip(1)
-            .map_windows(|[(byte, a), (_, b)]| (*byte, CharKind::new(*a) != CharKind::new(*b)))
-            .filter_map(|(byte, is_boundary)| (is_boundary).then_some(byte))
-            .next()
-    }
-
-    fn find_prev_word_start(&mut self) -> Option<usize> {
-        let mut offset = self.position_byte
-            + self
-                .content
-                .chars()
-                .next()
-                .map(char::len_utf8)
-                .unwrap_or_default();
-
-        self.content
-            .chars_at(
-                self.content
-                    .byte_to_char(self.position_byte)
-                    .add(1)
-                    .min(self.content.len_chars()),
-            )
-            .reversed()
-            .map(|ch| {
-                let byte = offset;
-                offset -= ch.len_utf8();
-                (byte, ch)
-            })
-            .skip(1)
-            .map_windows(|[(_, a), (byte, b)]| (*byte, CharKind::new(*a) != CharKind::new(*b)))
-            .filter_map(|(byte, is_boundary)| (is_boundary).then_some(byte))
-            .next()
-    }
-
-    pub fn move_next_word(&mut self) {
-        self.position_byte = self.find_next_word().unwrap_or(self.content.len_bytes());
-        self.update_position_x();
-    }
-
-    pub fn move_next_word_end(&mut self) {
-        self.position_byte = self
-            .find_next_word_end()
-            .unwrap_or(self.content.len_bytes());
-        self.update_position_x();
-    }
-
-    pub fn move_prev_word_start(&mut self) {
-        self.position_byte = self.find_prev_word_start().unwrap_or(0);
-        self.update_position_x();
-    }
-
-    pub fn scroll_up(&mut self) {
-        self.scroll_y = self.scroll_y.saturating_sub(1);
-    }
-
-    pub fn scroll_down(&mut self) {
-        self.scroll_y = self
-            .scroll_y
-            .add(1)
-            .min(self.content.len_lines().saturating_sub(1));
-    }
-
-    pub fn scroll_to_cursor(&mut self) {
-        let (_, y) = self.position();
-        let Rect { height, .. } = self
-            .last_view_area
-            .expect("scrolled to cursor before first render");
-
-        self.scroll_y = self
-            .scroll_y
-            .clamp(y.saturating_sub(usize::from(height.saturating_sub(1))), y);
-    }
-
-    pub fn move_to_view(&mut self) {
-        let (_, y) = self.position();
-        let Rect { height, .. } = self
-            .last_view_area
-            .expect("scrolled to cursor before first render");
-
-        let y = y.clamp(
-            self.scroll_y,
-            self.scroll_y.add(usize::from(height)).saturating_sub(1),
-        );
-
-        self.move_to(y, self.position_x);
-    }
-
-    pub fn insert(&mut self, ch: char) {
-        let idx = self.content.byte_to_char(self.position_byte);
-        self.content.insert_char(idx, ch);
-
-        let (x, y) = self.position();
-        if let Some(tree) = &mut self.tree {
-            let new_end_byte = self.position_byte + ch.len_utf8();
-
-            let new_end_line = self.content.byte_to_line(new_end_byte);
-            let new_end_col = new_end_byte - self.content.line_to_byte(new_end_line);
-
-            tree.edit(&InputEdit {
-                start_byte: self.position_byte,
-                start_position: Point::new(y, x),
-                old_end_byte: self.position_byte,
-                old_end_position: Point::new(y, x),
-                new_end_byte,
-                new_end_position: Point::new(new_end_line, new_end_col),
-            });
-        }
+            .char_to_utf16_cu(self.content.byte_to_char(byte))
+            - line_start;
+        Point::new(row, column)
+    }
 
-        self.position_byte += ch.len_utf8();
+    /// Replaces `range` with `text` and records the change in the open transaction.
+    pub fn replace(&mut self, range: Range<usize>, text: &str) {
+        let byte = range.start;
+        let removed = self.edit(range, text);
 
-        self.update_position_x();
+        self.history.record(Change {
+            byte,
+            removed,
+            inserted: text.to_owned(),
+        });
     }
 
-    fn byte_to_point(&self, byte: usize) -> Point {
-        let row = self.content.byte_to_line(byte);
-        let column = byte - self.content.line_to_byte(row);
-        Point::new(row, column)
-    }
+    /// Replaces `range` with `text`, keeping the cached tree in sync. Returns the removed text.
+    fn edit(&mut self, range: Range<usize>, text: &str) -> String {
+        let start_position = self.byte_to_point(range.start);
+        let old_end_position = self.byte_to_point(range.end);
 
-    pub fn remove_before(&mut self) {
-        let Some(idx) = self.content.byte_to_char(self.position_byte).checked_sub(1) else {
-            return;
+        if self.changes.is_some() {
+            let change = ContentChange {
+                start: start_position,
+                old_end: old_end_position,
+                start_utf16: self.byte_to_utf16_point(range.start),
+                old_end_utf16: self.byte_to_utf16_point(range.end),
+                text: text.to_owned(),
+            };
+            self.changes.get_or_insert_default().push(change);
+        }
+
+        // Words do not span lines, so recounting those edited is enough.
+        if let Some(words) = &mut self.words {
+            words.remove(lines(&self.content, range.clone()));
+        }
+
+        let start_char = self.content.byte_to_char(range.start);
+        let end_char = self.content.byte_to_char(range.end);
+
+        let removed = self.content.slice(start_char..end_char).to_string();
+        self.content.remove(start_char..end_char);
+        self.content.insert(start_char, text);
+
+        let new_end_byte = range.start + text.len();
+        let new_end_position = self.byte_to_point(new_end_byte);
+
+        if let Some(words) = &mut self.words {
+            words.add(lines(&self.content, range.start..new_end_byte));
+        }
+
+        let edit = InputEdit {
+            start_byte: range.start,
+            start_position,
+            old_end_byte: range.end,
+            old_end_position,
+            new_end_byte,
+            new_end_position,
         };
 
-        let byte_idx = self.content.char_to_byte(idx);
-        let point = self.byte_to_point(byte_idx);
-        let end_point = self.byte_to_point(self.position_byte);
+        self.edits.push(Edit::from(&edit));
+        self.version += 1;
 
         if let Some(tree) = &mut self.tree {
-            tree.edit(&InputEdit {
-                start_byte: byte_idx,
-                start_position: point,
-                old_end_byte: self.position_byte,
-                old_end_position: end_point,
-                new_end_byte: byte_idx,
-                new_end_position: point,
-            });
+            tree.edit(&edit);
+        }
+
+        if let Some(request) = &mut self.request {
+            request.edits.push(edit);
+        }
+
+        for highlights in self.highlights.values_mut() {
+            highlights.map(&Edit::from(&edit));
         }
 
-        self.position_byte -= self.content.char(idx).len_utf8();
-        self.content.remove(idx..=idx);
-        self.update_position_x();
+        self.folds.map(&Edit::from(&edit));
+        self.symbols = None;
+
+        for diagnostic in &mut self.diagnostics {
+            diagnostic.map(&Edit::from(&edit));
+        }
+
+        removed
     }
+}
 
-    pub fn remove(&mut self) {
-        if self.content.len_bytes() == 0 {
-            return;
+impl Highlights {
+    /// Moves the highlights along with an edit. Those of removed text are
+    /// dropped.
+    fn map(&mut self, edit: &Edit) {
+        self.touched |= edit.start <= self.range.end && edit.old_end >= self.range.start;
+        self.range = edit.map(self.range.start)..edit.map(self.range.end);
+
+        for style in &mut self.styles {
+            style.start_byte = edit.map(style.start_byte);
+            style.end_byte = edit.map(style.end_byte);
         }
 
-        let range = self.selection();
+        self.styles
+            .retain(|style| style.start_byte < style.end_byte);
+    }
+}
 
-        let start_point = self.byte_to_point(*range.start());
-        let end_point = self.byte_to_point(*range.end());
+#[derive(Default)]
+struct History {
+    undo: Vec<Transaction>,
+    redo: Vec<Transaction>,
+    pending: Option<Transaction>,
+    last_revision: usize,
+    saved: usize,
+}
 
-        if let Some(tree) = &mut self.tree {
-            tree.edit(&InputEdit {
-                start_byte: *range.start(),
-                start_position: start_point,
-                old_end_byte: *range.end(),
-                old_end_position: end_point,
-                new_end_byte: *range.start(),
-                new_end_position: start_point,
-            });
+impl History {
+    /// Identifies the current state of the content, 0 being the initial one.
+    fn revision(&self) -> usize {
+        self.undo
+            .last()
+            .map_or(0, |transaction| transaction.revision)
+    }
+
+    fn begin(&mut self, before: Snapshot) -> bool {
+        if self.pending.is_some() {
+            return false;
         }
 
-        let start_char = self.content.byte_to_char(*range.start());
-        let end_char = self
-            .content
-            .byte_to_char(*range.end())
-            .min(self.content.len_chars().saturating_sub(1));
+        self.pending = Some(Transaction {
+            revision: 0,
+            changes: Vec::new(),
+            before,
+            after: before,
+        });
 
-        self.content.remove(start_char..=end_char);
+        true
+    }
 
-        self.position_byte = *range.start();
+    fn record(&mut self, change: Change) {
+        let Some(pending) = &mut self.pending else {
+            return;
+        };
 
-        if let Mode::Select { start_byte, .. } = &mut self.mode {
-            *start_byte = *range.start();
+        if let Some(last) = pending.changes.last_mut()
+            && change.removed.is_empty()
+            && last.byte + last.inserted.len() == change.byte
+        {
+            last.inserted.push_str(&change.inserted);
+            return;
         }
 
-        self.update_position_x();
-        self.scroll_to_cursor();
+        pending.changes.push(change);
+    }
+
+    fn commit(&mut self, after: Snapshot) {
+        let Some(mut transaction) = self.pending.take() else {
+            return;
+        };
+
+        if transaction.changes.is_empty() {
+            return;
+        }
+
+        self.last_revision += 1;
+        transaction.revision = self.last_revision;
+        transaction.after = after;
+        self.undo.push(transaction);
+        self.redo.clear();
     }
 }
 
-#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
-pub enum Mode {
-    #[default]
-    Normal,
-    Insert,
-    Select {
-        start_byte: usize,
-        line_mode: bool,
-    },
+struct Transaction {
+    revision: usize,
+    changes: Vec<Change>,
+    before: Snapshot,
+    after: Snapshot,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum CharKind {
-    Lf,
-    Whitespace,
-    Other,
+/// The cursor state restored by undo and redo.
+#[derive(Debug, Clone, Copy)]
+pub struct Snapshot {
+    pub position_byte: usize,
+    pub mode: Mode,
 }
 
-impl CharKind {
-    fn new(ch: char) -> Self {
-        match ch {
-            '\n' | '\r' => Self::Lf,
-            ch if ch.is_whitespace() => Self::Whitespace,
-            _ => Self::Other,
+struct Change {
+    byte: usize,
+    removed: String,
+    inserted: String,
+}
+
+/// An edit as language servers are told about it: `text` replaced the text
+/// from `start` to `old_end`, whose columns count bytes, or UTF-16 code units
+/// for the `_utf16` points.
+#[derive(Debug, Clone)]
+pub struct ContentChange {
+    pub start: Point,
+    pub old_end: Point,
+    pub start_utf16: Point,
+    pub old_end_utf16: Point,
+    pub text: String,
+}
+
+/// A replacement of `start..old_end` with text ending at `new_end`.
+#[derive(Debug, Clone, Copy)]
+pub struct Edit {
+    start: usize,
+    old_end: usize,
+    new_end: usize,
+}
+
+impl From<&InputEdit> for Edit {
+    fn from(edit: &InputEdit) -> Self {
+        Self {
+            start: edit.start_byte,
+            old_end: edit.old_end_byte,
+            new_end: edit.new_end_byte,
         }
     }
 }
 
-struct RopeByteChunksIterator<'a> {
+impl Edit {
+    /// Maps a byte offset from before the edit to after it.
+    pub fn map(&self, byte: usize) -> usize {
+        if byte < self.start {
+            byte
+        } else if byte >= self.old_end {
+            byte - self.old_end + self.new_end
+        } else {
+            self.start
+        }
+    }
+}
+
+pub struct RopeByteChunksIterator<'a> {
     chunks: ropey::iter::Chunks<'a>,
     skip: usize,
-    chunk_byte_start: usize,
+    byte: usize,
     end: usize,
 }
 
@@ -567,24 +633,24 @@ impl<'a> Iterator for RopeByteChunksIterator<'a> {
     type Item = &'a [u8];
 
     fn next(&mut self) -> Option<Self::Item> {
-        let chunk = self.chunks.next()?;
-        let mut part = &chunk[self.skip..];
-        let to_take = self.end - self.chunk_byte_start - self.skip;
-
-        if part.len() > to_take {
-            part = &part[..(part.len() - to_take)];
+        if self.byte >= self.end {
+            return None;
         }
 
+        let chunk = &self.chunks.next()?.as_bytes()[self.skip..];
+        let part = &chunk[..chunk.len().min(self.end - self.byte)];
+
+        self.byte += part.len();
         self.skip = 0;
 
-        Some(part.as_bytes())
+        Some(part)
     }
 }
 
-struct RopeTextProvider<'a>(&'a Rope);
+pub struct RopeTextProvider<'a>(&'a Rope);
 
 impl<'a> RopeTextProvider<'a> {
-    fn new(rope: &'a Rope) -> Self {
+    pub fn new(rope: &'a Rope) -> Self {
         Self(rope)
     }
 }
@@ -600,51 +666,150 @@ impl<'a> TextProvider<&'a [u8]> for RopeTextProvider<'a> {
         RopeByteChunksIterator {
             chunks,
             skip: start - chunk_byte_start,
-            chunk_byte_start,
+            byte: start,
             end,
         }
     }
 }
 
-fn highlights_from_matches<'a>(
-    query: &'a Query,
-    matches: QueryMatches<'a, 'a, RopeTextProvider<'a>, &'a [u8]>,
-) -> Vec<TokenStyle<'a>> {
-    let mut colors = Vec::new();
-
-    for mat in matches {
-        for cap in mat.captures {
-            let node = cap.node;
-
-            let start_byte = node.start_byte();
-            let end_byte = node.end_byte();
-
-            let scope = query.capture_names()[cap.index as usize];
-
-            colors.push(TokenStyle {
-                end_byte,
-                start_byte,
-                style: scope,
-            });
-        }
-    }
-
-    colors
-}
-
 #[derive(Debug, Clone)]
-pub struct TokenStyle<'style> {
+pub struct TokenStyle {
     start_byte: usize,
     end_byte: usize,
-    pub style: &'style str,
+    pub style: String,
 }
 
-impl<'style> TokenStyle<'style> {
+impl TokenStyle {
+    pub fn new(range: Range<usize>, style: &str) -> Self {
+        Self {
+            start_byte: range.start,
+            end_byte: range.end,
+            style: style.to_owned(),
+        }
+    }
+
     pub fn contains(&self, idx_byte: usize) -> bool {
         (self.start_byte..self.end_byte).contains(&idx_byte)
     }
 
     pub fn before(&self, idx_byte: usize) -> bool {
-        self.end_byte < idx_byte
+        self.end_byte <= idx_byte
+    }
+}
+
+/// The whole lines `range` is in.
+fn lines(rope: &Rope, range: Range<usize>) -> RopeSlice<'_> {
+    let start = rope.line_to_char(rope.byte_to_line(range.start));
+    let end = rope.line_to_char((rope.byte_to_line(range.end) + 1).min(rope.len_lines()));
+    rope.slice(start..end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(position_byte: usize) -> Snapshot {
+        Snapshot {
+            position_byte,
+            mode: Mode::Normal,
+        }
+    }
+
+    fn text(doc: &Document) -> String {
+        doc.rope().to_string()
+    }
+
+    #[test]
+    fn typing_is_one_change() {
+        let mut doc = Document::new(Rope::from_str("fn main() {}\n"));
+
+        // Typed characters and a deletion in one insert session.
+        assert!(doc.begin(at(11)));
+        assert!(!doc.begin(at(11)));
+        doc.replace(11..11, "a");
+        doc.replace(12..12, "b");
+        doc.replace(13..13, "c");
+        doc.replace(12..13, "");
+        doc.commit(at(13));
+        assert_eq!(text(&doc), "fn main() {ac}\n");
+        assert!(doc.is_modified());
+
+        // A transaction without changes is not recorded.
+        doc.begin(at(0));
+        doc.commit(at(0));
+
+        assert_eq!(doc.undo(at(13)).map(|s| s.position_byte), Some(11));
+        assert_eq!(text(&doc), "fn main() {}\n");
+        assert!(!doc.is_modified());
+        assert!(doc.undo(at(11)).is_none());
+
+        assert_eq!(doc.redo(at(11)).map(|s| s.position_byte), Some(13));
+        assert_eq!(text(&doc), "fn main() {ac}\n");
+        assert!(doc.redo(at(13)).is_none());
+    }
+
+    #[test]
+    fn transactions() {
+        let mut doc = Document::new(Rope::from_str("one two\n"));
+
+        doc.begin(at(0));
+        doc.replace(0..3, "1");
+        doc.commit(at(0));
+
+        doc.begin(at(2));
+        doc.replace(2..5, "2");
+        doc.replace(3..3, "!");
+        doc.commit(at(3));
+        assert_eq!(text(&doc), "1 2!\n");
+
+        doc.undo(at(3));
+        assert_eq!(text(&doc), "1 two\n");
+
+        // A new change drops what could be redone.
+        doc.begin(at(0));
+        doc.replace(0..1, "uno");
+        doc.commit(at(0));
+        assert!(doc.redo(at(0)).is_none());
+
+        doc.undo(at(0));
+        doc.undo(at(0));
+        assert_eq!(text(&doc), "one two\n");
+        doc.redo(at(0));
+        doc.redo(at(0));
+        assert_eq!(text(&doc), "uno two\n");
+    }
+
+    #[test]
+    fn undo_commits_the_open_transaction() {
+        let mut doc = Document::new(Rope::from_str("a\n"));
+
+        doc.begin(at(1));
+        doc.replace(1..1, "b");
+        assert_eq!(doc.undo(at(2)).map(|s| s.position_byte), Some(1));
+        assert_eq!(text(&doc), "a\n");
+    }
+
+    #[test]
+    fn saved_revision() {
+        let mut doc = Document::new(Rope::from_str("a\n"));
+
+        doc.begin(at(0));
+        doc.replace(0..0, "b");
+        doc.commit(at(0));
+        doc.mark_saved();
+        assert!(!doc.is_modified());
+
+        doc.undo(at(0));
+        assert!(doc.is_modified());
+        doc.redo(at(0));
+        assert!(!doc.is_modified());
+
+        // Undoing and making another change does not come back to it.
+        doc.undo(at(0));
+        doc.begin(at(0));
+        doc.replace(0..0, "b");
+        doc.commit(at(0));
+        assert_eq!(text(&doc), "ba\n");
+        assert!(doc.is_modified());
     }
 }
@@ -0,0 +1,39 @@
+use ignore::{WalkBuilder, WalkState};
+use std::{
+    path::{Path, PathBuf},
+    thread,
+};
+
+/// Calls `visit` with the path of every file under `root`, relative to it,
+/// on a pool of background threads. Hidden files and those ignored by
+/// `.gitignore`, `.ignore` or git's exclude files are skipped, also outside
+/// a git repository. The walk stops once `visit` returns false.
+pub fn walk(root: &Path, visit: impl Fn(PathBuf) -> bool + Clone + Send + 'static) {
+    let root = root.to_path_buf();
+    let walker = WalkBuilder::new(&root).require_git(false).build_parallel();
+
+    thread::spawn(move || {
+        walker.run(|| {
+            let (root, visit) = (root.clone(), visit.clone());
+
+            Box::new(move |entry| {
+                let Ok(entry) = entry else {
+                    return WalkState::Continue;
+                };
+
+                if !entry.file_type().is_some_and(|kind| kind.is_file()) {
+                    return WalkState::Continue;
+                }
+
+                let path = entry.path();
+                let path = path.strip_prefix(&root).unwrap_or(path);
+
+                if visit(path.to_path_buf()) {
+                    WalkState::Continue
+                } else {
+                    WalkState::Quit
+                }
+            })
+        })
+    });
+}
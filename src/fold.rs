@@ -0,0 +1,277 @@
+use ropey::Rope;
+use std::{cmp::Reverse, ops::Range, ops::RangeInclusive};
+use tree_sitter::QueryCursor;
+
+use crate::{
+    document::{Document, Edit, RopeTextProvider},
+    language::Language,
+};
+
+/// The closed folds of a document, as the byte ranges they were computed
+/// from, moved along with edits.
+#[derive(Debug, Default)]
+pub struct Folds {
+    closed: Vec<Range<usize>>,
+}
+
+/// The lines of the outermost closed folds, sorted. The first line of a fold
+/// is shown with a marker and the others are hidden.
+pub struct FoldedLines(Vec<RangeInclusive<usize>>);
+
+impl Folds {
+    /// Closes the smallest of `regions` around `line` that hides more than
+    /// the closed fold already there, if any.
+    pub fn close(&mut self, rope: &Rope, regions: &[Range<usize>], line: usize) {
+        let current = self.lines(rope).get(line);
+        let size = |lines: &RangeInclusive<usize>| lines.end() - lines.start();
+
+        let region = regions
+            .iter()
+            .filter(|region| {
+                let lines = lines(rope, region);
+                lines.contains(&line)
+                    && current
+                        .as_ref()
+                        .is_none_or(|current| size(&lines) > size(current))
+            })
+            .min_by_key(|region| size(&lines(rope, region)));
+
+        if let Some(region) = region {
+            self.closed.push(region.clone());
+        }
+    }
+
+    /// Opens the outermost closed fold around `line`, keeping the folds
+    /// inside it closed. Returns whether there was one.
+    pub fn open(&mut self, rope: &Rope, line: usize) -> bool {
+        let Some(fold) = self.lines(rope).get(line) else {
+            return false;
+        };
+
+        self.closed
+            .retain(|range| lines(rope, range).start() != fold.start());
+        true
+    }
+
+    pub fn close_all(&mut self, regions: Vec<Range<usize>>) {
+        self.closed = regions;
+    }
+
+    pub fn open_all(&mut self) {
+        self.closed.clear();
+    }
+
+    /// Moves the folds along with an edit. Folds whose text was removed are
+    /// dropped.
+    pub fn map(&mut self, edit: &Edit) {
+        for range in &mut self.closed {
+            *range = edit.map(range.start)..edit.map(range.end);
+        }
+
+        self.closed.retain(|range| !range.is_empty());
+    }
+
+    pub fn lines(&self, rope: &Rope) -> FoldedLines {
+        let mut folds: Vec<_> = self
+            .closed
+            .iter()
+            .map(|range| lines(rope, range))
+            .filter(|lines| lines.start() < lines.end())
+            .collect();
+
+        folds.sort_by_key(|lines| (*lines.start(), Reverse(*lines.end())));
+
+        let mut outermost: Vec<RangeInclusive<usize>> = Vec::new();
+
+        for fold in folds {
+            match outermost.last_mut() {
+                Some(last) if fold.start() <= last.end() => {
+                    *last = *last.start()..=*last.end().max(fold.end());
+                }
+                _ => outermost.push(fold),
+            }
+        }
+
+        FoldedLines(outermost)
+    }
+}
+
+impl FoldedLines {
+    /// The closed fold `line` is in.
+    pub fn get(&self, line: usize) -> Option<RangeInclusive<usize>> {
+        let idx = self.0.partition_point(|fold| fold.end() < &line);
+        self.0.get(idx).filter(|fold| fold.contains(&line)).cloned()
+    }
+
+    /// The line `line` is shown on: the first line of its fold, or itself.
+    pub fn start(&self, line: usize) -> usize {
+        self.get(line).map_or(line, |fold| *fold.start())
+    }
+
+    /// The shown line after `line`.
+    pub fn next(&self, line: usize) -> usize {
+        self.get(line).map_or(line, |fold| *fold.end()) + 1
+    }
+
+    /// The shown line before `line`, if any.
+    pub fn prev(&self, line: usize) -> Option<usize> {
+        let line = self.start(line).checked_sub(1)?;
+        Some(self.start(line))
+    }
+}
+
+/// The ranges that can be folded, from the language's `folds.scm` query, or
+/// from indentation without one.
+pub fn regions(language: &Language, doc: &Document) -> Vec<Range<usize>> {
+    let rope = doc.rope();
+
+    let Some(query) = language.grammar().and_then(|grammar| grammar.folds()) else {
+        return indent_regions(rope);
+    };
+
+    let Some(tree) = doc.tree() else {
+        return Vec::new();
+    };
+
+    let names = query.capture_names();
+    let mut cursor = QueryCursor::new();
+    let mut regions: Vec<_> = cursor
+        .matches(query, tree.root_node(), RopeTextProvider::new(rope))
+        .flat_map(|mat| mat.captures)
+        .filter(|cap| names[cap.index as usize] == "fold")
+        .map(|cap| cap.node.byte_range())
+        .filter(|range| {
+            let lines = lines(rope, range);
+            lines.start() < lines.end()
+        })
+        .collect();
+
+    // Nodes on the same lines, like a function and its body, are one fold.
+    regions.sort_by_key(|range| (range.start, Reverse(range.end)));
+    regions.dedup_by_key(|range| lines(rope, range));
+    regions
+}
+
+/// A line and the lines after it that are more indented, without the blank
+/// lines at the end, for each line followed by more indented ones.
+fn indent_regions(rope: &Rope) -> Vec<Range<usize>> {
+    let indents: Vec<Option<usize>> = rope
+        .lines()
+        .map(|line| {
+            let indent = line
+                .chars()
+                .take_while(|ch| *ch == ' ' || *ch == '\t')
+                .count();
+            line.chars()
+                .nth(indent)
+                .is_some_and(|ch| !ch.is_whitespace())
+                .then_some(indent)
+        })
+        .collect();
+
+    let mut regions = Vec::new();
+
+    for (first, indent) in indents.iter().enumerate() {
+        let Some(indent) = indent else {
+            continue;
+        };
+
+        let mut last = first;
+        for (idx, other) in indents.iter().enumerate().skip(first + 1) {
+            match other {
+                None => {}
+                Some(other) if other > indent => last = idx,
+                Some(_) => break,
+            }
+        }
+
+        if last > first {
+            let line = rope.line(last).to_string();
+            let end = rope.line_to_byte(last) + line.trim_end_matches(['\n', '\r']).len();
+            regions.push(rope.line_to_byte(first)..end);
+        }
+    }
+
+    regions
+}
+
+/// The lines `range` spans.
+fn lines(rope: &Rope, range: &Range<usize>) -> RangeInclusive<usize> {
+    let len = rope.len_bytes();
+    let last = range.end.max(range.start + 1) - 1;
+    rope.byte_to_line(range.start.min(len))..=rope.byte_to_line(last.min(len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "fn main() {\n    if x {\n        y();\n\n    }\n}\n\nz\n";
+
+    fn folded(doc: &Document) -> Vec<RangeInclusive<usize>> {
+        doc.folds().lines(doc.rope()).0
+    }
+
+    #[test]
+    fn indentation() {
+        let rope = Rope::from_str(TEXT);
+
+        // The blank line at the end of the `if` is not part of it.
+        assert_eq!(indent_regions(&rope), [0..42, 12..35]);
+        assert!(indent_regions(&Rope::from_str("a\nb\n")).is_empty());
+        assert_eq!(
+            indent_regions(&Rope::from_str("a\n\tb\n\t\tc\nd")),
+            [0..8, 2..8]
+        );
+    }
+
+    #[test]
+    fn close_and_open() {
+        let mut doc = Document::new(Rope::from_str(TEXT));
+        let rope = doc.rope().clone();
+        let regions = indent_regions(&rope);
+
+        // Closing again around a closed fold closes the one around it.
+        doc.folds_mut().close(&rope, &regions, 2);
+        assert_eq!(folded(&doc), [1..=2]);
+        doc.folds_mut().close(&rope, &regions, 1);
+        assert_eq!(folded(&doc), [0..=4]);
+        doc.folds_mut().close(&rope, &regions, 1);
+        assert_eq!(folded(&doc), [0..=4]);
+
+        let lines = doc.folds().lines(&rope);
+        assert_eq!(lines.get(3), Some(0..=4));
+        assert_eq!(lines.get(5), None);
+        assert_eq!(lines.start(3), 0);
+        assert_eq!(lines.next(0), 5);
+        assert_eq!(lines.prev(5), Some(0));
+        assert_eq!(lines.prev(2), None);
+
+        // Opening keeps the inner fold closed.
+        assert!(doc.folds_mut().open(&rope, 3));
+        assert_eq!(folded(&doc), [1..=2]);
+        assert!(!doc.folds_mut().open(&rope, 0));
+    }
+
+    #[test]
+    fn edits_move_folds() {
+        let mut doc = Document::new(Rope::from_str(TEXT));
+        let rope = doc.rope().clone();
+        doc.folds_mut().close(&rope, &indent_regions(&rope), 2);
+
+        doc.replace(0..0, "// c\n");
+        assert_eq!(folded(&doc), [2..=3]);
+
+        // A line inserted inside the fold is hidden with it.
+        let byte = doc.rope().line_to_byte(3);
+        doc.replace(byte..byte, "        w();\n");
+        assert_eq!(folded(&doc), [2..=4]);
+
+        // Removing the text of the fold drops it.
+        let start = doc.rope().line_to_byte(2);
+        let end = doc.rope().line_to_byte(5);
+        doc.replace(start..end, "");
+        assert!(folded(&doc).is_empty());
+        assert!(doc.folds().closed.is_empty());
+    }
+}
@@ -0,0 +1,197 @@
+/// How `pattern` matched a text: higher scores are better matches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    pub score: i64,
+    /// The indices of the matched characters.
+    pub indices: Vec<usize>,
+}
+
+const CONSECUTIVE: i64 = 200;
+const AFTER_SLASH: i64 = 180;
+const AFTER_SEPARATOR: i64 = 160;
+const CAPITAL: i64 = 140;
+const AFTER_DOT: i64 = 120;
+const GAP: i64 = -2;
+const LEADING_GAP: i64 = -1;
+
+/// Matches the characters of `pattern` in order anywhere in `text`, ignoring
+/// case unless the pattern has uppercase letters. Characters after a `/`, a
+/// separator, a `.` or at the start of a camelCase word score higher, as do
+/// consecutive ones, and gaps between them score lower.
+pub fn score(pattern: &str, text: &str) -> Option<Match> {
+    let case_sensitive = pattern.chars().any(char::is_uppercase);
+    let normalize = |ch: char| {
+        if case_sensitive {
+            ch
+        } else {
+            ch.to_ascii_lowercase()
+        }
+    };
+
+    let pattern: Vec<char> = pattern.chars().map(normalize).collect();
+    let chars: Vec<char> = text.chars().collect();
+
+    if pattern.is_empty() {
+        return Some(Match {
+            score: 0,
+            indices: Vec::new(),
+        });
+    }
+
+    if pattern.len() > chars.len() {
+        return None;
+    }
+
+    let bonus: Vec<i64> = (0..chars.len())
+        .map(|idx| match idx.checked_sub(1).map(|prev| chars[prev]) {
+            None => AFTER_SLASH,
+            Some('/' | '\\') => AFTER_SLASH,
+            Some('_' | '-' | ' ' | ':') => AFTER_SEPARATOR,
+            Some('.') => AFTER_DOT,
+            Some(prev) if prev.is_lowercase() && chars[idx].is_uppercase() => CAPITAL,
+            Some(_) => 0,
+        })
+        .collect();
+
+    // The best score of the pattern up to each character ending at each
+    // text character, and where the previous character matched.
+    let mut scores = vec![vec![None; chars.len()]; pattern.len()];
+    let mut from = vec![vec![0; chars.len()]; pattern.len()];
+
+    for (pat_idx, &pat) in pattern.iter().enumerate() {
+        // The best previous match so far, with the gap to it counted up to
+        // the current character.
+        let mut best: Option<(i64, usize)> = None;
+
+        for idx in 0..chars.len() {
+            if pat_idx > 0
+                && idx > 0
+                && let Some(prev) = scores[pat_idx - 1][idx - 1]
+                && best.is_none_or(|(score, _)| prev > score)
+            {
+                best = Some((prev, idx - 1));
+            }
+
+            if normalize(chars[idx]) == pat {
+                let score = if pat_idx == 0 {
+                    Some((LEADING_GAP * idx as i64 + bonus[idx], 0))
+                } else {
+                    let consecutive = idx
+                        .checked_sub(1)
+                        .and_then(|prev| scores[pat_idx - 1][prev])
+                        .map(|score| (score + CONSECUTIVE.max(bonus[idx]), idx - 1));
+                    let gapped = best.map(|(score, prev)| (score + bonus[idx], prev));
+
+                    consecutive
+                        .into_iter()
+                        .chain(gapped)
+                        .max_by_key(|(score, _)| *score)
+                };
+
+                if let Some((score, prev)) = score {
+                    scores[pat_idx][idx] = Some(score);
+                    from[pat_idx][idx] = prev;
+                }
+            }
+
+            if let Some((score, _)) = &mut best {
+                *score += GAP;
+            }
+        }
+    }
+
+    let last = pattern.len() - 1;
+    let (end, score) = (0..chars.len())
+        .filter_map(|idx| Some((idx, scores[last][idx]?)))
+        .max_by_key(|&(idx, score)| (score, std::cmp::Reverse(idx)))?;
+
+    let mut indices = vec![end];
+    for pat_idx in (1..pattern.len()).rev() {
+        indices.push(from[pat_idx][indices[indices.len() - 1]]);
+    }
+    indices.reverse();
+
+    // Shorter texts win ties.
+    let score = score * 16 - chars.len() as i64;
+
+    Some(Match { score, indices })
+}
+
+/// The indices of the `items` matching `pattern` and their matches, best
+/// first. Ties keep the order of the items.
+pub fn filter<'a>(pattern: &str, items: impl IntoIterator<Item = &'a str>) -> Vec<(usize, Match)> {
+    let mut matches: Vec<_> = items
+        .into_iter()
+        .enumerate()
+        .filter_map(|(idx, item)| Some((idx, score(pattern, item)?)))
+        .collect();
+
+    matches.sort_by_key(|(idx, mat)| (std::cmp::Reverse(mat.score), *idx));
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn indices(pattern: &str, text: &str) -> Option<Vec<usize>> {
+        score(pattern, text).map(|mat| mat.indices)
+    }
+
+    fn ranked<'a>(pattern: &str, items: &[&'a str]) -> Vec<&'a str> {
+        filter(pattern, items.iter().copied())
+            .into_iter()
+            .map(|(idx, _)| items[idx])
+            .collect()
+    }
+
+    #[test]
+    fn matches_in_order() {
+        assert_eq!(indices("", "anything"), Some(Vec::new()));
+        assert_eq!(indices("abc", "abc"), Some(vec![0, 1, 2]));
+        assert_eq!(indices("ac", "abc"), Some(vec![0, 2]));
+        assert_eq!(indices("ca", "abc"), None);
+        assert_eq!(indices("abcd", "abc"), None);
+    }
+
+    #[test]
+    fn smart_case() {
+        assert_eq!(indices("readme", "README.md"), Some(vec![0, 1, 2, 3, 4, 5]));
+        assert_eq!(indices("README", "readme.md"), None);
+        assert_eq!(indices("Rm", "readme.md"), None);
+        assert_eq!(indices("Rm", "README.md"), Some(vec![0, 7]));
+    }
+
+    #[test]
+    fn prefers_word_starts() {
+        assert_eq!(indices("fb", "foobar/foo_bar"), Some(vec![7, 11]));
+        assert_eq!(indices("mr", "src/main.rs"), Some(vec![4, 9]));
+        assert_eq!(indices("gf", "fooGetFile"), Some(vec![3, 6]));
+        assert_eq!(indices("lib", "alibi/lib.rs"), Some(vec![6, 7, 8]));
+    }
+
+    #[test]
+    fn ranking() {
+        assert_eq!(
+            ranked(
+                "main",
+                &["src/domain.rs", "src/main.rs", "maintainers/index.md"]
+            ),
+            ["maintainers/index.md", "src/main.rs", "src/domain.rs"]
+        );
+        assert_eq!(
+            ranked(
+                "view",
+                &["src/view.rs", "src/preview.rs", "src/view/mod.rs"]
+            ),
+            ["src/view.rs", "src/view/mod.rs", "src/preview.rs"]
+        );
+        assert_eq!(ranked("x", &["a", "b"]), [""; 0]);
+    }
+
+    #[test]
+    fn ties_keep_item_order() {
+        assert_eq!(ranked("a", &["ba", "ca", "da"]), ["ba", "ca", "da"]);
+        assert_eq!(ranked("", &["one", "two"]), ["one", "two"]);
+    }
+}
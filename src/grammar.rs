@@ -0,0 +1,101 @@
+use serde::Deserialize;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{config, language};
+
+/// The queries copied from a grammar checkout next to the compiled grammar.
+const QUERIES: [&str; 6] = [
+    "highlights",
+    "injections",
+    "locals",
+    "textobjects",
+    "folds",
+    "tags",
+];
+
+#[derive(Deserialize)]
+struct GrammarJson {
+    name: String,
+}
+
+/// Compiles the grammar in a tree-sitter grammar checkout with the system C
+/// compiler into the runtime directory, and copies its queries there unless
+/// they already exist. Returns the path of the compiled grammar.
+pub fn build(checkout: &Path) -> Result<PathBuf, String> {
+    let src = checkout.join("src");
+    let name = name(&src)?;
+
+    let runtime = config::runtime_dirs()
+        .into_iter()
+        .next()
+        .ok_or("no runtime directory, set ION_RUNTIME")?;
+
+    let grammars = runtime.join("grammars");
+    fs::create_dir_all(&grammars).map_err(|err| format!("{}: {err}", grammars.display()))?;
+    let output = grammars.join(language::library_name(&name));
+
+    // A C++ scanner needs the whole grammar linked as C++.
+    let cpp_scanner = src.join("scanner.cc");
+    let (compiler, scanner) = if cpp_scanner.is_file() {
+        (env::var("CXX").unwrap_or("c++".into()), Some(cpp_scanner))
+    } else {
+        let scanner = src.join("scanner.c");
+        (
+            env::var("CC").unwrap_or("cc".into()),
+            scanner.is_file().then_some(scanner),
+        )
+    };
+
+    let mut command = Command::new(&compiler);
+    command
+        .args(["-shared", "-fPIC", "-O2", "-I"])
+        .arg(&src)
+        .args(["-x", "c"])
+        .arg(src.join("parser.c"));
+
+    if let Some(scanner) = scanner {
+        if scanner.extension().is_some_and(|ext| ext == "cc") {
+            command.args(["-x", "c++"]);
+        }
+        command.arg(scanner);
+    }
+
+    let status = command
+        .arg("-o")
+        .arg(&output)
+        .status()
+        .map_err(|err| format!("{compiler}: {err}"))?;
+
+    if !status.success() {
+        return Err(format!("{compiler} failed: {status}"));
+    }
+
+    let queries = runtime.join("queries").join(&name);
+
+    for kind in QUERIES {
+        let from = checkout.join("queries").join(format!("{kind}.scm"));
+        let to = queries.join(format!("{kind}.scm"));
+
+        if from.is_file() && !to.exists() {
+            fs::create_dir_all(&queries)
+                .and_then(|()| fs::copy(&from, &to))
+                .map_err(|err| format!("{}: {err}", to.display()))?;
+        }
+    }
+
+    Ok(output)
+}
+
+/// The grammar's name, from the `grammar.json` generated next to its parser.
+fn name(src: &Path) -> Result<String, String> {
+    let path = src.join("grammar.json");
+    let text = fs::read_to_string(&path).map_err(|err| format!("{}: {err}", path.display()))?;
+
+    serde_json::from_str::<GrammarJson>(&text)
+        .map(|grammar| grammar.name)
+        .map_err(|err| format!("{}: {err}", path.display()))
+}
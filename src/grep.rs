@@ -0,0 +1,78 @@
+use regex::{Regex, RegexBuilder};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, Sender},
+};
+
+use crate::{
+    files,
+    picker::{Item, Location},
+};
+
+/// Searches the files under the current directory for `pattern` on a pool of
+/// background threads, sending an item per match as they are found. The
+/// search is case insensitive unless the pattern contains an uppercase letter,
+/// and stops once the receiver is dropped.
+pub fn search(pattern: &str) -> Result<Receiver<Item>, String> {
+    let regex = RegexBuilder::new(pattern)
+        .case_insensitive(!pattern.chars().any(char::is_uppercase))
+        .build()
+        .map_err(|err| {
+            // Syntax errors point at the pattern over several lines.
+            let err = err.to_string();
+            let reason = err.lines().last().unwrap_or_default();
+            format!("invalid pattern: {}", reason.trim_start_matches("error: "))
+        })?;
+
+    let (sender, receiver) = mpsc::channel();
+    files::walk(Path::new("."), move |path| {
+        search_file(&regex, path, &sender)
+    });
+
+    Ok(receiver)
+}
+
+/// Sends the matches of `regex` in each line of the file at `path`, labelled
+/// as `path:line:column: text`. Binary files are skipped. Returns false once
+/// the receiver is gone.
+fn search_file(regex: &Regex, path: PathBuf, sender: &Sender<Item>) -> bool {
+    let Ok(Ok(text)) = fs::read(&path).map(String::from_utf8) else {
+        return true;
+    };
+
+    for (line_idx, line) in text.lines().enumerate() {
+        for mat in regex.find_iter(line) {
+            // Only the first match of an empty pattern on each line.
+            if mat.is_empty() && mat.start() > 0 {
+                break;
+            }
+
+            let indent = line.len() - line.trim_start().len();
+            let column = line[..mat.start()].chars().count();
+            let prefix = format!("{}:{}:{}: ", path.display(), line_idx + 1, column + 1);
+
+            let start =
+                prefix.chars().count() + line[indent.min(mat.start())..mat.start()].chars().count();
+            let len = line[mat.start().max(indent)..mat.end().max(indent)]
+                .chars()
+                .count();
+
+            let item = Item {
+                label: format!("{prefix}{}", line[indent..].trim_end()),
+                highlight: Some(start..start + len),
+                location: Some(Location {
+                    path: path.clone(),
+                    line: line_idx,
+                    column: mat.start(),
+                }),
+            };
+
+            if sender.send(item).is_err() {
+                return false;
+            }
+        }
+    }
+
+    true
+}
@@ -0,0 +1,454 @@
+use ropey::Rope;
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    ops::Range,
+    sync::{
+        Arc,
+        mpsc::{self, Receiver, Sender},
+    },
+    thread,
+    time::Duration,
+};
+use tree_sitter::{Node, Parser, Query, QueryCursor, QueryMatches, Tree};
+
+use crate::{
+    document::{RopeTextProvider, TokenStyle},
+    injection::{self, Injection, Layer},
+    language::{Grammar, Language, Languages},
+    syntax::{self, Local},
+};
+
+/// How long parsing a tree can take before it is given up, so a huge file
+/// cannot keep the highlighter busy.
+pub const PARSE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// The most time a parse that timed out is retried with.
+pub const MAX_PARSE_TIMEOUT: Duration = Duration::from_secs(8);
+
+/// Parses documents and highlights them on a background thread, so typing
+/// never waits for tree-sitter.
+pub struct Highlighter {
+    requests: Sender<Request>,
+    responses: Receiver<Response>,
+    /// The number of requests sent without a response yet.
+    pending: usize,
+    last_id: usize,
+}
+
+/// A snapshot of a document to highlight. Ropes and trees are cheap to clone.
+pub struct Request {
+    pub id: usize,
+    pub buffer: usize,
+    pub text: Rope,
+    /// The last tree, edited to match `text`.
+    pub tree: Option<Tree>,
+    pub language: Language,
+    pub range: Range<usize>,
+    /// A range whose highlights the document has and can keep if its syntax
+    /// did not change.
+    pub cached: Option<Range<usize>>,
+    /// How long parsing `text` can take.
+    pub timeout: Duration,
+}
+
+pub struct Response {
+    pub id: usize,
+    pub buffer: usize,
+    /// The new tree, or none if parsing took too long.
+    pub tree: Option<Tree>,
+    pub range: Range<usize>,
+    /// The highlights of `range`, or none if the cached ones are still valid.
+    pub styles: Option<Vec<TokenStyle>>,
+}
+
+impl Highlighter {
+    pub fn new(languages: Arc<Languages>) -> Self {
+        let (requests, requests_rx) = mpsc::channel();
+        let (responses_tx, responses) = mpsc::channel();
+
+        thread::spawn(move || {
+            let mut parsers = Parsers::default();
+
+            for request in requests_rx {
+                let response = highlight(&languages, &mut parsers, request);
+
+                if responses_tx.send(response).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            requests,
+            responses,
+            pending: 0,
+            last_id: 0,
+        }
+    }
+
+    /// Queues a request, returning its id.
+    pub fn send(&mut self, mut request: Request) -> usize {
+        self.last_id += 1;
+        request.id = self.last_id;
+
+        if self.requests.send(request).is_ok() {
+            self.pending += 1;
+        }
+
+        self.last_id
+    }
+
+    /// The responses received since the last call.
+    pub fn receive(&mut self) -> Vec<Response> {
+        let responses: Vec<_> = self.responses.try_iter().collect();
+        self.pending -= responses.len();
+        responses
+    }
+
+    /// Whether responses are still expected.
+    pub fn is_busy(&self) -> bool {
+        self.pending > 0
+    }
+}
+
+/// A parser per language, reused across requests.
+#[derive(Default)]
+struct Parsers(HashMap<&'static str, Parser>);
+
+impl Parsers {
+    fn get(&mut self, language: &Language) -> &mut Parser {
+        self.0.entry(language.name()).or_insert_with(|| {
+            let mut parser = Parser::new();
+            let grammar = language
+                .grammar()
+                .expect("only languages with a grammar are parsed");
+            parser
+                .set_language(grammar.language())
+                .expect("checked when loading the grammar");
+            parser
+        })
+    }
+}
+
+fn highlight(languages: &Languages, parsers: &mut Parsers, request: Request) -> Response {
+    let Request {
+        id,
+        buffer,
+        text,
+        tree: old,
+        language,
+        range,
+        cached,
+        timeout,
+    } = request;
+
+    let grammar = language
+        .grammar()
+        .expect("only languages with a grammar are parsed");
+    let parser = parsers.get(&language);
+    parser
+        .set_included_ranges(&[])
+        .expect("the whole text is a valid range");
+
+    let Some(tree) = parse(&text, parser, old.as_ref(), timeout) else {
+        return Response {
+            id,
+            buffer,
+            tree: None,
+            range,
+            styles: None,
+        };
+    };
+
+    let unchanged = cached.is_some_and(|cached| {
+        old.is_some_and(|old| {
+            !old.changed_ranges(&tree)
+                .any(|changed| changed.start_byte <= cached.end && changed.end_byte >= cached.start)
+        })
+    });
+
+    let styles = (!unchanged).then(|| {
+        let layers = parse_injections(&text, grammar, &tree, languages, parsers, range.clone());
+        let mut captures = tree_captures(&text, grammar, &tree, 0, range.clone());
+
+        for layer in &layers {
+            let grammar = layer
+                .language
+                .grammar()
+                .expect("injected languages have a grammar");
+            captures.extend(tree_captures(
+                &text,
+                grammar,
+                &layer.tree,
+                layer.depth,
+                range.clone(),
+            ));
+        }
+
+        flatten(captures)
+    });
+
+    Response {
+        id,
+        buffer,
+        tree: Some(tree),
+        range,
+        styles,
+    }
+}
+
+/// Parses `text`, reusing the unchanged parts of `old`. Returns none if
+/// `timeout` runs out.
+fn parse(text: &Rope, parser: &mut Parser, old: Option<&Tree>, timeout: Duration) -> Option<Tree> {
+    parser.set_timeout_micros(timeout.as_micros() as u64);
+
+    let tree = parser.parse_with(
+        &mut |byte, _| -> &[u8] {
+            let Some((mut chunks, chunk_start, ..)) = text.get_chunks_at_byte(byte) else {
+                return &[];
+            };
+
+            let offset = byte - chunk_start;
+
+            let Some(chunk) = chunks.next() else {
+                return &[];
+            };
+
+            let chunk = &chunk[offset..];
+
+            if chunk.is_empty() {
+                for chunk in chunks {
+                    if !chunk.is_empty() {
+                        return chunk.as_bytes();
+                    }
+                }
+                &[]
+            } else {
+                chunk.as_bytes()
+            }
+        },
+        old,
+    );
+
+    if tree.is_none() {
+        // Otherwise the next parse resumes this one.
+        parser.reset();
+    }
+
+    tree
+}
+
+/// Parses the injections of `tree` overlapping `range` and those nested in
+/// them, each with the parser of its language restricted to the injected
+/// ranges.
+fn parse_injections(
+    text: &Rope,
+    grammar: &Grammar,
+    tree: &Tree,
+    languages: &Languages,
+    parsers: &mut Parsers,
+    range: Range<usize>,
+) -> Vec<Layer> {
+    let mut injections: Vec<(Injection, usize)> = grammar
+        .injections()
+        .map(|query| injection::find(query, tree, text, range.clone()))
+        .unwrap_or_default()
+        .into_iter()
+        .map(|injection| (injection, 1))
+        .collect();
+
+    let mut layers = Vec::new();
+
+    while let Some((injection, depth)) = injections.pop() {
+        let Some(language) = languages.injected(&injection.language) else {
+            continue;
+        };
+
+        let parser = parsers.get(&language);
+
+        if parser.set_included_ranges(&injection.ranges).is_err() {
+            continue;
+        }
+
+        let Some(tree) = parse(text, parser, None, PARSE_TIMEOUT) else {
+            continue;
+        };
+
+        let grammar = language
+            .grammar()
+            .expect("injected languages have a grammar");
+
+        if depth < injection::MAX_DEPTH
+            && let Some(query) = grammar.injections()
+        {
+            let nested = injection::find(query, &tree, text, range.clone());
+            injections.extend(nested.into_iter().map(|injection| (injection, depth + 1)));
+        }
+
+        layers.push(Layer {
+            language,
+            tree,
+            depth,
+        });
+    }
+
+    layers
+}
+
+/// The highlight captures of a parsed tree overlapping `range`. With a
+/// locals query, captures from the start of the top-level node containing
+/// `range` are included, for definitions above it.
+fn tree_captures<'a>(
+    text: &Rope,
+    grammar: &'a Grammar,
+    tree: &Tree,
+    depth: usize,
+    mut range: Range<usize>,
+) -> Vec<Capture<'a>> {
+    let highlights = grammar.highlights();
+    let root = tree.root_node();
+
+    if grammar.locals().is_some() {
+        let mut cursor = root.walk();
+        let top = root
+            .children(&mut cursor)
+            .find(|node| node.end_byte() > range.start);
+
+        if let Some(top) = top {
+            range.start = range.start.min(top.start_byte());
+        }
+    }
+
+    let mut query_cursor = QueryCursor::new();
+    query_cursor.set_byte_range(range.clone());
+    let matches = query_cursor.matches(highlights, root, RopeTextProvider::new(text));
+    let mut captures = captures_from_matches(highlights, matches, depth);
+
+    if let Some(locals) = grammar.locals() {
+        resolve_locals(text, locals, root, range, &mut captures);
+    }
+
+    captures
+}
+
+/// Gives references to a local definition the style of the definition, e.g.
+/// a parameter used in the function body, as found by the `@local` captures
+/// of `locals`.
+fn resolve_locals(
+    text: &Rope,
+    locals: &Query,
+    root: Node,
+    range: Range<usize>,
+    styles: &mut [Capture],
+) {
+    let mut references = Vec::new();
+
+    syntax::locals(locals, root, text, range, |local, scopes| {
+        if let Local::Reference(name, reference) = local
+            && let Some(definition) = scopes.iter().rev().find_map(|scope| scope.find(name))
+        {
+            references.push((reference, definition));
+        }
+    });
+
+    for (reference, definition) in references {
+        let Some(style) = styles
+            .iter()
+            .find(|style| style.range == definition)
+            .map(|style| style.style)
+        else {
+            continue;
+        };
+
+        for token in styles.iter_mut().filter(|style| style.range == reference) {
+            token.style = style;
+        }
+    }
+}
+
+/// A highlight capture, before overlapping captures are resolved.
+struct Capture<'q> {
+    range: Range<usize>,
+    style: &'q str,
+    /// The injection depth of the tree the capture is from.
+    depth: usize,
+    pattern: usize,
+}
+
+impl Capture<'_> {
+    /// Of overlapping captures, the one of the deepest injection wins, then
+    /// the innermost one, then the one of the earliest pattern.
+    fn priority(&self) -> (usize, usize, Reverse<usize>, Reverse<usize>) {
+        (
+            self.depth,
+            self.range.start,
+            Reverse(self.range.end),
+            Reverse(self.pattern),
+        )
+    }
+}
+
+fn captures_from_matches<'q, 'a>(
+    query: &'q Query,
+    matches: QueryMatches<'_, '_, RopeTextProvider<'a>, &'a [u8]>,
+    depth: usize,
+) -> Vec<Capture<'q>> {
+    let mut captures = Vec::new();
+
+    for mat in matches {
+        for cap in mat.captures {
+            captures.push(Capture {
+                range: cap.node.byte_range(),
+                style: query.capture_names()[cap.index as usize],
+                depth,
+                pattern: mat.pattern_index,
+            });
+        }
+    }
+
+    captures
+}
+
+/// Splits overlapping captures into the sorted, disjoint ranges of the
+/// capture winning each of them.
+fn flatten(mut captures: Vec<Capture>) -> Vec<TokenStyle> {
+    captures.sort_by_key(|capture| capture.range.start);
+
+    let mut bounds: Vec<usize> = captures
+        .iter()
+        .flat_map(|capture| [capture.range.start, capture.range.end])
+        .collect();
+    bounds.sort_unstable();
+    bounds.dedup();
+
+    let mut styles: Vec<(Range<usize>, &str)> = Vec::new();
+    let mut active = BinaryHeap::new();
+    let mut captures = captures.iter().peekable();
+
+    for (start, end) in bounds.iter().map_windows(|&[&start, &end]| (start, end)) {
+        while let Some(capture) = captures.next_if(|capture| capture.range.start <= start) {
+            active.push((capture.priority(), capture.range.end, capture.style));
+        }
+
+        while active.peek().is_some_and(|&(_, end, _)| end <= start) {
+            active.pop();
+        }
+
+        let Some(&(_, _, style)) = active.peek() else {
+            continue;
+        };
+
+        match styles.last_mut() {
+            Some((last, last_style)) if last.end == start && *last_style == style => {
+                last.end = end;
+            }
+            _ => styles.push((start..end, style)),
+        }
+    }
+
+    styles
+        .into_iter()
+        .map(|(range, style)| TokenStyle::new(range, style))
+        .collect()
+}
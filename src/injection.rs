@@ -0,0 +1,136 @@
+use ropey::Rope;
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    ops,
+};
+use tree_sitter::{Node, Query, QueryCursor, Range, Tree};
+
+use crate::{document::RopeTextProvider, language::Language};
+
+/// How deep languages can be injected into each other, e.g. code in a
+/// Markdown fence in a doc comment.
+pub const MAX_DEPTH: usize = 4;
+
+/// Ranges of the text written in another language, e.g. SQL in a string.
+pub struct Injection {
+    /// The name or a file type of the language, e.g. `py` in a Markdown fence.
+    pub language: String,
+    /// Sorted and not overlapping, as the parser requires.
+    pub ranges: Vec<Range>,
+}
+
+/// An injection parsed with the grammar of its language.
+pub struct Layer {
+    pub language: Language,
+    pub tree: Tree,
+    /// 1 for injections into the document's language, 2 for injections into
+    /// those, and so on.
+    pub depth: usize,
+}
+
+/// The injections `query` finds in `tree` overlapping `range`. The `injection.content` captures
+/// of a match are parsed together, without the text of their children unless
+/// the pattern sets `injection.include-children`. A pattern setting
+/// `injection.combined` parses all its matches for a language together.
+pub fn find(query: &Query, tree: &Tree, text: &Rope, range: ops::Range<usize>) -> Vec<Injection> {
+    let names = query.capture_names();
+    let mut injections: Vec<Injection> = Vec::new();
+    let mut combined: HashMap<(usize, String), usize> = HashMap::new();
+
+    let mut query_cursor = QueryCursor::new();
+    query_cursor.set_byte_range(range);
+    let matches = query_cursor.matches(query, tree.root_node(), RopeTextProvider::new(text));
+
+    for mat in matches {
+        let properties = query.property_settings(mat.pattern_index);
+        let property = |key: &str| properties.iter().find(|property| &*property.key == key);
+
+        let mut language = property("injection.language")
+            .and_then(|property| property.value.as_deref())
+            .map(str::to_owned);
+        let mut ranges = Vec::new();
+
+        for cap in mat.captures {
+            match names[cap.index as usize] {
+                "injection.language" => {
+                    let name = text.byte_slice(cap.node.byte_range()).to_string();
+                    language = Some(name.trim().to_owned());
+                }
+                "injection.content" => ranges.extend(content_ranges(
+                    cap.node,
+                    property("injection.include-children").is_some(),
+                )),
+                _ => {}
+            }
+        }
+
+        let Some(language) = language.filter(|language| !language.is_empty()) else {
+            continue;
+        };
+
+        if ranges.is_empty() {
+            continue;
+        }
+
+        if property("injection.combined").is_none() {
+            injections.push(Injection { language, ranges });
+            continue;
+        }
+
+        match combined.entry((mat.pattern_index, language.clone())) {
+            Entry::Occupied(entry) => injections[*entry.get()].ranges.extend(ranges),
+            Entry::Vacant(entry) => {
+                entry.insert(injections.len());
+                injections.push(Injection { language, ranges });
+            }
+        }
+    }
+
+    for injection in &mut injections {
+        injection.ranges.sort_by_key(|range| range.start_byte);
+    }
+
+    injections
+}
+
+/// The range of `node`, without the ranges of its children unless
+/// `include_children` is set.
+fn content_ranges(node: Node, include_children: bool) -> Vec<Range> {
+    if include_children {
+        return vec![node.range()];
+    }
+
+    let mut ranges = Vec::new();
+    let mut gap = |start: Range, end: Range| {
+        if start.end_byte < end.start_byte {
+            ranges.push(Range {
+                start_byte: start.end_byte,
+                start_point: start.end_point,
+                end_byte: end.start_byte,
+                end_point: end.start_point,
+            });
+        }
+    };
+
+    // The text between the start of the node, its children and its end.
+    let mut last = Range {
+        end_byte: node.start_byte(),
+        end_point: node.start_position(),
+        ..node.range()
+    };
+
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        gap(last, child.range());
+        last = child.range();
+    }
+
+    let end = Range {
+        start_byte: node.end_byte(),
+        start_point: node.end_position(),
+        ..node.range()
+    };
+    gap(last, end);
+
+    ranges
+}
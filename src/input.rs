@@ -11,7 +11,12 @@ pub struct Input {
     position: usize,
     symbol: Option<char>,
     placeholder: Option<String>,
+    #[allow(clippy::type_complexity)]
     submit: Box<dyn FnOnce(String, &mut App)>,
+    #[allow(clippy::type_complexity)]
+    change: Option<Box<dyn Fn(&str, &mut App)>>,
+    #[allow(clippy::type_complexity)]
+    cancel: Option<Box<dyn FnOnce(&mut App)>>,
 }
 
 impl Input {
@@ -22,6 +27,8 @@ impl Input {
             symbol: None,
             placeholder: None,
             submit: Box::new(submit),
+            change: None,
+            cancel: None,
         }
     }
 
@@ -35,6 +42,16 @@ impl Input {
         self
     }
 
+    pub fn with_on_change(mut self, change: impl Fn(&str, &mut App) + 'static) -> Self {
+        self.change = Some(Box::new(change));
+        self
+    }
+
+    pub fn with_on_cancel(mut self, cancel: impl FnOnce(&mut App) + 'static) -> Self {
+        self.cancel = Some(Box::new(cancel));
+        self
+    }
+
     pub fn insert(&mut self, ch: char) {
         self.buf.push(ch);
         self.position += ch.len_utf8();
@@ -50,6 +67,18 @@ impl Input {
         (self.submit)(self.buf, app)
     }
 
+    pub fn change(&self, app: &mut App) {
+        if let Some(change) = &self.change {
+            change(&self.buf, app)
+        }
+    }
+
+    pub fn cancel(self, app: &mut App) {
+        if let Some(cancel) = self.cancel {
+            cancel(app)
+        }
+    }
+
     pub fn render(&self, theme: &Theme, buf: &mut Buffer, mut area: Rect) -> Option<Position> {
         if let Some(symbol) = self.symbol {
             let [symbol_area, other] =
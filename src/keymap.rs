@@ -1,18 +1,394 @@
-use std::collections::BTreeMap;
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::{collections::HashMap, fmt};
 
-use crate::command::Command;
+use crate::{
+    command::Command,
+    operator::{self, Motion, Operator, Target, TextObject},
+    view::Mode,
+};
 
+/// The key bindings of each mode.
 #[derive(Default)]
 pub struct Keymap {
-    keys: BTreeMap<char, Command<()>>,
+    pub normal: KeyTrie,
+    pub insert: KeyTrie,
+    pub select: KeyTrie,
+    /// The motions and text objects taken by operators, e.g. `w` in `dw`.
+    pub operator: KeyTrie,
 }
 
 impl Keymap {
-    pub fn insert(&mut self, key: char, value: Command<()>) {
-        self.keys.insert(key, value);
+    pub fn get(&self, mode: Mode) -> &KeyTrie {
+        match mode {
+            Mode::Normal => &self.normal,
+            Mode::Insert => &self.insert,
+            Mode::Select { .. } => &self.select,
+        }
     }
 
-    pub fn get(&self, key: char) -> Option<&Command<()>> {
-        self.keys.get(&key)
+    /// Binds `keys` to `action` in `mode`. Motions are also bound as the
+    /// target of operators, and operators as their own linewise target, as
+    /// in `dd`.
+    pub fn bind(&mut self, mode: KeyMode, keys: &[Key], action: Action) -> Result<(), String> {
+        let table = match mode {
+            KeyMode::Normal => &mut self.normal,
+            KeyMode::Insert => &mut self.insert,
+            KeyMode::Select => &mut self.select,
+        };
+
+        match action {
+            Action::Command(command) => table.bind(keys, command),
+            Action::Motion(motion) => {
+                if mode != KeyMode::Insert {
+                    let target = motion.clone();
+                    self.operator.bind(
+                        keys,
+                        Command::new(move |app, ()| {
+                            operator::finish(app, Target::Motion(target.clone()))
+                        }),
+                    );
+                }
+
+                table.bind(
+                    keys,
+                    Command::new(move |app, ()| {
+                        let count = app.take_count();
+                        let (view, doc) = app.current_mut();
+                        motion.apply(view, doc, count);
+                        view.scroll_to_cursor(doc);
+                    }),
+                );
+            }
+            Action::Operator(op) => match mode {
+                KeyMode::Normal => {
+                    table.bind(keys, Command::new(move |app, ()| operator::begin(app, op)));
+                    self.operator.bind(
+                        keys,
+                        Command::new(move |app, ()| operator::finish_line(app, op)),
+                    );
+                }
+                KeyMode::Select => table.bind(
+                    keys,
+                    Command::new(move |app, ()| operator::apply_selection(app, op)),
+                ),
+                KeyMode::Insert => return Err("operators can't be bound in insert mode".into()),
+            },
+            Action::TextObject(object) => match mode {
+                KeyMode::Normal => self.operator.bind(
+                    keys,
+                    Command::new(move |app, ()| {
+                        operator::finish(app, Target::Object(object.clone()))
+                    }),
+                ),
+                KeyMode::Select => table.bind(
+                    keys,
+                    Command::new(move |app, ()| operator::select(app, &object)),
+                ),
+                KeyMode::Insert => {
+                    return Err("text objects can't be bound in insert mode".into());
+                }
+            },
+        }
+
+        Ok(())
+    }
+}
+
+/// The modes keys can be bound in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyMode {
+    Normal,
+    Insert,
+    Select,
+}
+
+/// Something keys can be bound to, by name in the config file.
+#[derive(Clone)]
+pub enum Action {
+    Command(Command<()>),
+    Motion(Motion),
+    Operator(Operator),
+    /// Bound as the target of operators, e.g. `iw` in `diw`, and as what to
+    /// select in select mode, e.g. `iw` in `viw`.
+    TextObject(TextObject),
+}
+
+/// Bindings keyed on key sequences. A node can both run a command and start
+/// longer sequences, e.g. `d` and `dd`.
+#[derive(Default, Clone)]
+pub struct KeyTrie {
+    command: Option<Command<()>>,
+    children: HashMap<Key, KeyTrie>,
+}
+
+impl KeyTrie {
+    pub fn bind(&mut self, keys: &[Key], command: Command<()>) {
+        let node = keys
+            .iter()
+            .fold(self, |node, key| node.children.entry(*key).or_default());
+
+        node.command = Some(command);
+    }
+
+    pub fn get(&self, keys: &[Key]) -> Option<&KeyTrie> {
+        keys.iter()
+            .try_fold(self, |node, key| node.children.get(key))
+    }
+
+    pub fn command(&self) -> Option<&Command<()>> {
+        self.command.as_ref()
+    }
+
+    /// Whether longer sequences start with the keys leading here.
+    pub fn is_prefix(&self) -> bool {
+        !self.children.is_empty()
+    }
+}
+
+/// A key press with its modifiers. Shift is folded into the character for
+/// character keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Key {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+impl Key {
+    pub fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        let modifiers = match code {
+            KeyCode::Char(_) | KeyCode::BackTab => modifiers - KeyModifiers::SHIFT,
+            _ => modifiers,
+        };
+
+        Self { code, modifiers }
+    }
+
+    /// The character typed by this key, if it has no other modifier.
+    pub fn char(&self) -> Option<char> {
+        match self.code {
+            KeyCode::Char(ch) if self.modifiers.is_empty() => Some(ch),
+            _ => None,
+        }
+    }
+}
+
+impl From<KeyEvent> for Key {
+    fn from(event: KeyEvent) -> Self {
+        Self::new(event.code, event.modifiers)
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.code {
+            KeyCode::Char(' ') => "space".into(),
+            KeyCode::Char('<') => "lt".into(),
+            KeyCode::Char(ch) if self.modifiers.is_empty() => return write!(f, "{ch}"),
+            KeyCode::Char(ch) => ch.to_string(),
+            KeyCode::F(n) => format!("F{n}"),
+            code => NAMES
+                .iter()
+                .find(|(_, named)| *named == code)
+                .map_or("?", |(name, _)| name)
+                .to_owned(),
+        };
+
+        f.write_str("<")?;
+
+        for (modifier, prefix) in MODIFIERS {
+            if self.modifiers.contains(modifier) {
+                write!(f, "{prefix}-")?;
+            }
+        }
+
+        write!(f, "{name}>")
+    }
+}
+
+const MODIFIERS: [(KeyModifiers, char); 3] = [
+    (KeyModifiers::CONTROL, 'C'),
+    (KeyModifiers::ALT, 'A'),
+    (KeyModifiers::SHIFT, 'S'),
+];
+
+const NAMES: [(&str, KeyCode); 15] = [
+    ("Esc", KeyCode::Esc),
+    ("Enter", KeyCode::Enter),
+    ("Tab", KeyCode::Tab),
+    ("S-Tab", KeyCode::BackTab),
+    ("BS", KeyCode::Backspace),
+    ("Del", KeyCode::Delete),
+    ("Ins", KeyCode::Insert),
+    ("Left", KeyCode::Left),
+    ("Right", KeyCode::Right),
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("Home", KeyCode::Home),
+    ("End", KeyCode::End),
+    ("PageUp", KeyCode::PageUp),
+    ("PageDown", KeyCode::PageDown),
+];
+
+/// Parses a key sequence such as `gg`, `<space>f` or `<C-w><Left>`.
+pub fn parse_keys(s: &str) -> Result<Vec<Key>, String> {
+    let mut keys = Vec::new();
+    let mut rest = s;
+
+    while let Some(ch) = rest.chars().next() {
+        if ch == '<'
+            && let Some(end) = rest.find('>')
+            && end > 1
+        {
+            keys.push(parse_key(&rest[1..end])?);
+            rest = &rest[end + 1..];
+        } else {
+            keys.push(Key::new(KeyCode::Char(ch), KeyModifiers::NONE));
+            rest = &rest[ch.len_utf8()..];
+        }
+    }
+
+    if keys.is_empty() {
+        return Err("empty key sequence".into());
+    }
+
+    Ok(keys)
+}
+
+/// Parses the inside of `<...>`, e.g. `C-w` or `PageDown`.
+fn parse_key(s: &str) -> Result<Key, String> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut name = s;
+
+    while let Some((prefix, rest)) = name.split_once('-')
+        && !rest.is_empty()
+    {
+        modifiers |= match prefix {
+            "C" | "c" => KeyModifiers::CONTROL,
+            "A" | "a" | "M" | "m" => KeyModifiers::ALT,
+            "S" | "s" => KeyModifiers::SHIFT,
+            _ => return Err(format!("unknown modifier in <{s}>")),
+        };
+        name = rest;
+    }
+
+    let code = match name.to_ascii_lowercase().as_str() {
+        _ if name.chars().count() == 1 => KeyCode::Char(name.chars().next().unwrap()),
+        "space" => KeyCode::Char(' '),
+        "lt" => KeyCode::Char('<'),
+        "cr" | "return" => KeyCode::Enter,
+        "backspace" => KeyCode::Backspace,
+        lower
+            if let Some(n) = lower.strip_prefix('f')
+                && let Ok(n) = n.parse() =>
+        {
+            KeyCode::F(n)
+        }
+        lower => NAMES
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(lower))
+            .map(|(_, code)| *code)
+            .ok_or_else(|| format!("unknown key <{s}>"))?,
+    };
+
+    if modifiers.contains(KeyModifiers::SHIFT) {
+        // The terminal sends a shifted letter as the uppercase letter.
+        match code {
+            KeyCode::Tab => return Ok(Key::new(KeyCode::BackTab, modifiers)),
+            KeyCode::Char(ch) if ch.is_alphabetic() => {
+                let upper = ch.to_uppercase().next().unwrap_or(ch);
+                return Ok(Key::new(KeyCode::Char(upper), modifiers));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(Key::new(code, modifiers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> Key {
+        Key::new(code, modifiers)
+    }
+
+    fn char(ch: char) -> Key {
+        key(KeyCode::Char(ch), KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn sequences() {
+        assert_eq!(parse_keys("gg"), Ok(vec![char('g'), char('g')]));
+        assert_eq!(parse_keys("<space>f"), Ok(vec![char(' '), char('f')]));
+        assert_eq!(
+            parse_keys("<C-w><Left>"),
+            Ok(vec![
+                key(KeyCode::Char('w'), KeyModifiers::CONTROL),
+                key(KeyCode::Left, KeyModifiers::NONE),
+            ])
+        );
+        assert_eq!(parse_keys("é"), Ok(vec![char('é')]));
+        assert_eq!(parse_keys(""), Err("empty key sequence".into()));
+    }
+
+    #[test]
+    fn angle_brackets() {
+        // A `<` that does not start a key name is typed as is.
+        assert_eq!(parse_keys("<"), Ok(vec![char('<')]));
+        assert_eq!(parse_keys("<>"), Ok(vec![char('<'), char('>')]));
+        assert_eq!(parse_keys("<lt>"), Ok(vec![char('<')]));
+        assert_eq!(parse_keys("<->"), Ok(vec![char('-')]));
+    }
+
+    #[test]
+    fn names_and_modifiers() {
+        let one = |s: &str| parse_keys(s).map(|keys| keys[0]);
+
+        assert_eq!(one("<CR>"), Ok(key(KeyCode::Enter, KeyModifiers::NONE)));
+        assert_eq!(one("<esc>"), Ok(key(KeyCode::Esc, KeyModifiers::NONE)));
+        assert_eq!(one("<BS>"), Ok(key(KeyCode::Backspace, KeyModifiers::NONE)));
+        assert_eq!(one("<F12>"), Ok(key(KeyCode::F(12), KeyModifiers::NONE)));
+        assert_eq!(
+            one("<PageDown>"),
+            Ok(key(KeyCode::PageDown, KeyModifiers::NONE))
+        );
+        assert_eq!(
+            one("<C-A-x>"),
+            Ok(key(
+                KeyCode::Char('x'),
+                KeyModifiers::CONTROL | KeyModifiers::ALT
+            ))
+        );
+        assert_eq!(one("<M-Up>"), Ok(key(KeyCode::Up, KeyModifiers::ALT)));
+        assert_eq!(
+            one("<c-->"),
+            Ok(key(KeyCode::Char('-'), KeyModifiers::CONTROL))
+        );
+
+        // Shift is part of the character, and turns Tab into BackTab.
+        assert_eq!(one("<S-a>"), Ok(char('A')));
+        assert_eq!(one("<S-A>"), Ok(char('A')));
+        assert_eq!(
+            one("<C-S-a>"),
+            Ok(key(KeyCode::Char('A'), KeyModifiers::CONTROL))
+        );
+        assert_eq!(
+            one("<S-Tab>"),
+            Ok(key(KeyCode::BackTab, KeyModifiers::NONE))
+        );
+
+        assert_eq!(one("<X-a>"), Err("unknown modifier in <X-a>".into()));
+        assert_eq!(one("<Foo>"), Err("unknown key <Foo>".into()));
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for keys in [
+            "<C-w>", "<space>", "<lt>", "<A-Left>", "<S-Tab>", "<F5>", "x",
+        ] {
+            let key = parse_keys(keys).unwrap()[0];
+            assert_eq!(key.to_string(), keys);
+        }
     }
 }
@@ -1,27 +1,600 @@
+use libloading::Library;
+use ropey::Rope;
+use std::{
+    collections::HashMap,
+    env::consts::{DLL_EXTENSION, DLL_PREFIX},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
 use tree_sitter::{Parser, Query};
 
+use crate::config;
+
+/// A buffer's filetype, with its grammar if there is one.
+#[derive(Clone)]
 pub struct Language {
+    name: &'static str,
+    grammar: Option<Arc<Grammar>>,
+}
+
+/// A tree-sitter grammar and its queries, loaded once and shared by the
+/// buffers of its language and the highlighter.
+pub struct Grammar {
     highlights: Query,
-    parser: Parser,
+    injections: Option<Query>,
+    locals: Option<Query>,
+    textobjects: Option<Query>,
+    folds: Option<Query>,
+    tags: Option<Query>,
+    language: tree_sitter::Language,
+    /// The library of a grammar loaded from the runtime directory, which must
+    /// outlive everything using the language.
+    _library: Option<Library>,
 }
 
 impl Language {
+    /// Plain text, which is not parsed.
+    pub fn plain() -> Self {
+        Self::unparsed("text")
+    }
+
+    fn unparsed(name: &'static str) -> Self {
+        Self {
+            name,
+            grammar: None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn grammar(&self) -> Option<&Grammar> {
+        self.grammar.as_deref()
+    }
+}
+
+impl Grammar {
+    pub fn language(&self) -> &tree_sitter::Language {
+        &self.language
+    }
+
     pub fn highlights(&self) -> &Query {
         &self.highlights
     }
 
-    pub fn parser(&mut self) -> &mut Parser {
-        &mut self.parser
+    pub fn injections(&self) -> Option<&Query> {
+        self.injections.as_ref()
+    }
+
+    pub fn locals(&self) -> Option<&Query> {
+        self.locals.as_ref()
+    }
+
+    pub fn textobjects(&self) -> Option<&Query> {
+        self.textobjects.as_ref()
+    }
+
+    pub fn folds(&self) -> Option<&Query> {
+        self.folds.as_ref()
+    }
+
+    pub fn tags(&self) -> Option<&Query> {
+        self.tags.as_ref()
+    }
+}
+
+/// How to recognize a language, and the grammar ion ships for it if any.
+pub struct LanguageConfig {
+    pub name: &'static str,
+    /// Extensions, or whole file names such as `Makefile`.
+    file_types: &'static [&'static str],
+    /// Interpreters named by a shebang, without version numbers.
+    interpreters: &'static [&'static str],
+    /// The name of its grammar library in the runtime directory, usually the
+    /// language's.
+    grammar: &'static str,
+    builtin: Option<Builtin>,
+}
+
+#[derive(Clone, Copy)]
+struct Builtin {
+    language: fn() -> tree_sitter::Language,
+    highlights: &'static str,
+    injections: &'static str,
+    locals: &'static str,
+    textobjects: &'static str,
+    folds: &'static str,
+    tags: &'static str,
+}
+
+/// The known languages, and the grammars loaded for them so far. Shared
+/// with the highlighter thread.
+pub struct Languages {
+    configs: Vec<LanguageConfig>,
+    /// Directories with `grammars/` and `queries/<language>/`, in order of
+    /// precedence.
+    runtime: Vec<PathBuf>,
+    /// The grammar of each language loaded so far, if it has one.
+    grammars: Mutex<HashMap<&'static str, Option<Arc<Grammar>>>>,
+    error: Mutex<Option<String>>,
+}
+
+impl Languages {
+    pub fn new(runtime: Vec<PathBuf>) -> Self {
+        Self {
+            configs: builtin(),
+            runtime,
+            grammars: Mutex::default(),
+            error: Mutex::default(),
+        }
+    }
+
+    /// Adds a language declared in the config, or changes how a built-in one
+    /// is recognized. Declared languages are matched before the built-in
+    /// ones, and their grammar defaults to the one named like them.
+    pub fn declare(
+        &mut self,
+        name: &str,
+        file_types: &[String],
+        interpreters: &[String],
+        grammar: Option<&str>,
+    ) {
+        // Languages live as long as the editor, and their names are shared
+        // as `&'static str` like those of the built-in ones.
+        let leak = |s: &str| -> &'static str { Box::leak(s.into()) };
+        let leak_all = |list: &[String]| -> &'static [&'static str] {
+            Box::leak(list.iter().map(|s| leak(s)).collect())
+        };
+
+        let idx = self.configs.iter().position(|config| config.name == name);
+        let existing = idx.map(|idx| self.configs.remove(idx));
+        let name = existing
+            .as_ref()
+            .map_or_else(|| leak(name), |config| config.name);
+        let grammar = match (grammar, &existing) {
+            (Some(grammar), _) => leak(grammar),
+            (None, Some(existing)) => existing.grammar,
+            (None, None) => name,
+        };
+
+        self.configs.insert(
+            0,
+            LanguageConfig {
+                name,
+                file_types: leak_all(file_types),
+                interpreters: leak_all(interpreters),
+                grammar,
+                builtin: existing.and_then(|config| config.builtin),
+            },
+        );
+    }
+
+    pub fn get(&self, name: &str) -> Option<&LanguageConfig> {
+        self.configs.iter().find(|config| config.name == name)
+    }
+
+    /// The language of a file, from a modeline, its name or its shebang, or
+    /// plain text. If its grammar fails to load, the file is not highlighted
+    /// and the error is kept for [`Languages::take_error`].
+    pub fn detect(&self, path: Option<&Path>, text: &Rope) -> Language {
+        let config = modeline(text)
+            .and_then(|name| self.get(&name).or_else(|| self.by_file_type(&name)))
+            .or_else(|| path.and_then(|path| self.by_path(path)))
+            .or_else(|| shebang(text).and_then(|name| self.by_interpreter(&name)));
+
+        let Some(config) = config else {
+            return Language::plain();
+        };
+
+        self.load_config(config).unwrap_or_else(|err| {
+            *self.error.lock().unwrap() = Some(err);
+            Language::unparsed(config.name)
+        })
+    }
+
+    /// The language called `name`, as set by `:set filetype=name`.
+    pub fn load(&self, name: &str) -> Result<Language, String> {
+        let config = self
+            .get(name)
+            .ok_or_else(|| format!("unknown filetype: {name}"))?;
+
+        self.load_config(config)
+    }
+
+    /// The language to parse an injection named `name` with, which may also
+    /// be a file type such as `py` in a Markdown fence. Languages without a
+    /// grammar cannot be injected.
+    pub fn injected(&self, name: &str) -> Option<Language> {
+        let config = self.get(name).or_else(|| self.by_file_type(name))?;
+
+        self.load_config(config)
+            .ok()
+            .filter(|language| language.grammar.is_some())
+    }
+
+    /// The last error from loading a grammar while detecting a language.
+    pub fn take_error(&self) -> Option<String> {
+        self.error.lock().unwrap().take()
+    }
+
+    fn load_config(&self, config: &LanguageConfig) -> Result<Language, String> {
+        let mut grammars = self.grammars.lock().unwrap();
+
+        let grammar = match grammars.get(config.name) {
+            Some(grammar) => grammar.clone(),
+            None => {
+                let grammar = self.load_grammar(config)?.map(Arc::new);
+                grammars.insert(config.name, grammar.clone());
+                grammar
+            }
+        };
+
+        Ok(Language {
+            name: config.name,
+            grammar,
+        })
+    }
+
+    /// Loads the grammar of a language from the runtime directory, or the
+    /// built-in one, with its queries. Query files in the runtime directory
+    /// take precedence over the built-in queries.
+    fn load_grammar(&self, config: &LanguageConfig) -> Result<Option<Grammar>, String> {
+        let name = config.name;
+
+        let (language, library) = match (self.grammar_library(config), config.builtin) {
+            (Some(path), _) => {
+                let (language, library) = load_library(&path, config.grammar)?;
+                (language, Some(library))
+            }
+            (None, Some(builtin)) => ((builtin.language)(), None),
+            (None, None) => return Ok(None),
+        };
+
+        Parser::new()
+            .set_language(&language)
+            .map_err(|err| format!("grammar {name}: {err}"))?;
+
+        // Queries are looked up under the language's name, then the
+        // grammar's, where `ion --grammar build` puts them.
+        let query = |kind: &str, builtin: Option<&str>| -> Result<Option<Query>, String> {
+            let file = |dir: &str| Path::new("queries").join(dir).join(format!("{kind}.scm"));
+            let path = self
+                .find(&file(name))
+                .or_else(|| self.find(&file(config.grammar)));
+
+            let source = match path {
+                Some(path) => {
+                    fs::read_to_string(&path).map_err(|err| format!("{}: {err}", path.display()))?
+                }
+                None => match builtin {
+                    Some(source) if !source.is_empty() => source.to_owned(),
+                    _ => return Ok(None),
+                },
+            };
+
+            Query::new(&language, &source)
+                .map(Some)
+                .map_err(|err| format!("{name} {kind}.scm: {err}"))
+        };
+
+        let highlights = query("highlights", config.builtin.map(|b| b.highlights))?;
+        let highlights = match highlights {
+            Some(highlights) => highlights,
+            None => Query::new(&language, "").expect("empty query is valid"),
+        };
+
+        Ok(Some(Grammar {
+            highlights,
+            injections: query("injections", config.builtin.map(|b| b.injections))?,
+            locals: query("locals", config.builtin.map(|b| b.locals))?,
+            textobjects: query("textobjects", config.builtin.map(|b| b.textobjects))?,
+            folds: query("folds", config.builtin.map(|b| b.folds))?,
+            tags: query("tags", config.builtin.map(|b| b.tags))?,
+            language,
+            _library: library,
+        }))
+    }
+
+    /// The grammar library of a language in the runtime directories, if any.
+    fn grammar_library(&self, config: &LanguageConfig) -> Option<PathBuf> {
+        self.find(&Path::new("grammars").join(library_name(config.grammar)))
+    }
+
+    /// The first runtime directory containing `path`.
+    fn find(&self, path: &Path) -> Option<PathBuf> {
+        self.runtime
+            .iter()
+            .map(|dir| dir.join(path))
+            .find(|path| path.is_file())
+    }
+
+    fn by_file_type(&self, file_type: &str) -> Option<&LanguageConfig> {
+        self.configs
+            .iter()
+            .find(|config| config.file_types.contains(&file_type))
+    }
+
+    fn by_path(&self, path: &Path) -> Option<&LanguageConfig> {
+        let file_name = path.file_name()?.to_str()?;
+
+        self.by_file_type(file_name).or_else(|| {
+            let extension = path.extension()?.to_str()?;
+            self.by_file_type(extension)
+        })
+    }
+
+    fn by_interpreter(&self, interpreter: &str) -> Option<&LanguageConfig> {
+        self.configs
+            .iter()
+            .find(|config| config.interpreters.contains(&interpreter))
     }
 }
 
-pub fn rust() -> Language {
-    let lang = tree_sitter_rust::language();
+impl Default for Languages {
+    fn default() -> Self {
+        Self::new(config::runtime_dirs())
+    }
+}
+
+/// The file name of a compiled grammar, e.g. `libtree-sitter-rust.so`.
+pub fn library_name(name: &str) -> String {
+    format!("{DLL_PREFIX}tree-sitter-{name}.{DLL_EXTENSION}")
+}
 
-    let highlights = Query::new(&lang, tree_sitter_rust::HIGHLIGHTS_QUERY).unwrap();
+/// Loads a grammar compiled into a shared library, which exports it as
+/// `tree_sitter_<name>`.
+fn load_library(path: &Path, name: &str) -> Result<(tree_sitter::Language, Library), String> {
+    let symbol = format!("tree_sitter_{}", name.replace('-', "_"));
+    let error = |err| format!("grammar {name}: {err}");
 
-    let mut parser = Parser::new();
-    parser.set_language(&lang).unwrap();
+    // SAFETY: grammar libraries only run their initializers when loaded, and
+    // the symbol has the signature every generated parser exports. The
+    // library is kept alive by the grammar.
+    unsafe {
+        let library = Library::new(path).map_err(error)?;
+        let language = library
+            .get::<unsafe extern "C" fn() -> tree_sitter::Language>(symbol.as_bytes())
+            .map_err(error)?();
+
+        Ok((language, library))
+    }
+}
+
+fn builtin() -> Vec<LanguageConfig> {
+    let config = |name, file_types, interpreters| LanguageConfig {
+        name,
+        file_types,
+        interpreters,
+        grammar: name,
+        builtin: None,
+    };
+
+    vec![
+        LanguageConfig {
+            builtin: Some(Builtin {
+                language: tree_sitter_rust::language,
+                highlights: tree_sitter_rust::HIGHLIGHTS_QUERY,
+                injections: tree_sitter_rust::INJECTIONS_QUERY,
+                locals: include_str!("../runtime/queries/rust/locals.scm"),
+                textobjects: include_str!("../runtime/queries/rust/textobjects.scm"),
+                folds: include_str!("../runtime/queries/rust/folds.scm"),
+                tags: include_str!("../runtime/queries/rust/tags.scm"),
+            }),
+            ..config("rust", &["rs"], &["rust-script"])
+        },
+        config("toml", &["toml", "Cargo.lock"], &[]),
+        config("markdown", &["md", "markdown"], &[]),
+        config("json", &["json", "jsonc"], &[]),
+        config("yaml", &["yml", "yaml"], &[]),
+        config("python", &["py", "pyi"], &["python"]),
+        config(
+            "bash",
+            &["sh", "bash", "zsh", ".bashrc", ".bash_profile", ".zshrc"],
+            &["sh", "bash", "zsh", "dash"],
+        ),
+        config("c", &["c", "h"], &[]),
+        config("cpp", &["cpp", "cc", "cxx", "hpp", "hh", "hxx"], &[]),
+        config("go", &["go"], &[]),
+        config("javascript", &["js", "mjs", "cjs"], &["node"]),
+        config("typescript", &["ts", "mts", "cts"], &["deno"]),
+        config("html", &["html", "htm"], &[]),
+        config("css", &["css"], &[]),
+        config(
+            "make",
+            &["Makefile", "makefile", "GNUmakefile", "mk"],
+            &["make"],
+        ),
+        config("text", &["txt"], &[]),
+    ]
+}
+
+/// The filetype set by a vim (`vim: set ft=rust:`) or emacs
+/// (`-*- mode: rust -*-`) modeline in the first or last five lines.
+fn modeline(text: &Rope) -> Option<String> {
+    let len = text.len_lines();
+    let lines = (0..len.min(5)).chain(len.saturating_sub(5).max(5)..len);
+
+    lines
+        .map(|idx| text.line(idx).to_string())
+        .find_map(|line| parse_modeline(&line).map(str::to_owned))
+}
+
+fn parse_modeline(line: &str) -> Option<&str> {
+    if let Some((_, rest)) = line.split_once("-*-")
+        && let Some((inner, _)) = rest.split_once("-*-")
+    {
+        if !inner.contains(':') {
+            return Some(inner.trim());
+        }
+
+        return inner.split(';').find_map(|part| {
+            let (name, value) = part.split_once(':')?;
+            name.trim()
+                .eq_ignore_ascii_case("mode")
+                .then_some(value.trim())
+        });
+    }
+
+    let start = ["vim:", "vi:", "ex:"].iter().find_map(|marker| {
+        let idx = line.find(marker)?;
+        let prefixed = idx == 0 || line[..idx].ends_with(char::is_whitespace);
+        prefixed.then_some(idx + marker.len())
+    })?;
+
+    line[start..]
+        .split([' ', '\t', ':'])
+        .find_map(|option| {
+            option
+                .strip_prefix("ft=")
+                .or_else(|| option.strip_prefix("filetype="))
+        })
+        .map(str::trim)
+}
 
-    Language { highlights, parser }
+/// The interpreter named by a `#!` line, without its directory and version,
+/// e.g. `python` for `#!/usr/bin/env python3`.
+fn shebang(text: &Rope) -> Option<String> {
+    let line = text.get_line(0)?.to_string();
+    let mut words = line.strip_prefix("#!")?.split_whitespace();
+
+    let mut program = words.next()?.rsplit('/').next()?;
+
+    if program == "env" {
+        program = words.find(|word| !word.starts_with('-'))?;
+    }
+
+    let name = program.trim_end_matches(|ch: char| ch.is_ascii_digit() || ch == '.');
+    Some(name.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    #[test]
+    fn vim_modelines() {
+        assert_eq!(parse_modeline("// vim: set ft=rust:"), Some("rust"));
+        assert_eq!(parse_modeline("# vi: ts=4 filetype=python"), Some("python"));
+        assert_eq!(parse_modeline("vim:ft=toml"), Some("toml"));
+        assert_eq!(parse_modeline("\tex: sw=2\tft=sh\n"), Some("sh"));
+        assert_eq!(parse_modeline("// vim: set ts=4:"), None);
+        assert_eq!(parse_modeline("let novim: ft=rust"), None);
+    }
+
+    #[test]
+    fn emacs_modelines() {
+        assert_eq!(parse_modeline("# -*- python -*-"), Some("python"));
+        assert_eq!(
+            parse_modeline("/* -*- coding: utf-8; mode: c; -*- */"),
+            Some("c")
+        );
+        assert_eq!(parse_modeline("-*- Mode: Rust -*-"), Some("Rust"));
+        assert_eq!(parse_modeline("# -*- coding: utf-8 -*-"), None);
+        assert_eq!(parse_modeline("# -*- python"), None);
+    }
+
+    #[test]
+    fn modeline_lines() {
+        let text = |lines: &[&str]| Rope::from_str(&lines.join("\n"));
+        let filler = ["x"; 10];
+
+        let first = [&["vim: ft=go"][..], &filler].concat();
+        assert_eq!(modeline(&text(&first)).as_deref(), Some("go"));
+
+        let last = [&filler[..], &["vim: ft=go"]].concat();
+        assert_eq!(modeline(&text(&last)).as_deref(), Some("go"));
+
+        let middle = [&filler[..], &["vim: ft=go"], &filler].concat();
+        assert_eq!(modeline(&text(&middle)), None);
+    }
+
+    #[test]
+    fn shebangs() {
+        let interpreter = |text| shebang(&Rope::from_str(text));
+
+        assert_eq!(interpreter("#!/bin/sh\n").as_deref(), Some("sh"));
+        assert_eq!(
+            interpreter("#!/usr/bin/env python3\n").as_deref(),
+            Some("python")
+        );
+        assert_eq!(
+            interpreter("#!/usr/bin/env -S python3.12 -u\n").as_deref(),
+            Some("python")
+        );
+        assert_eq!(interpreter("#! /usr/bin/bash -e").as_deref(), Some("bash"));
+        assert_eq!(interpreter("#!/usr/bin/env -i\n"), None);
+        assert_eq!(interpreter("# not a shebang\n"), None);
+        assert_eq!(interpreter(""), None);
+    }
+
+    #[test]
+    fn declared_languages() {
+        let runtime = env::temp_dir().join(format!("ion-languages-{}", process::id()));
+        let grammars = runtime.join("grammars");
+        fs::create_dir_all(&grammars).unwrap();
+
+        // A grammar named unlike its language, which fails to load.
+        let library = grammars.join(library_name("typescript"));
+        fs::write(&library, "not a library").unwrap();
+
+        let mut languages = Languages::new(vec![runtime.clone()]);
+        let strings = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        languages.declare("zig", &strings(&["zig", "build.zon"]), &[], None);
+        languages.declare(
+            "tsx",
+            &strings(&["tsx"]),
+            &strings(&["tsx-node"]),
+            Some("typescript"),
+        );
+        // A built-in language recognized differently keeps its grammar.
+        languages.declare("cpp", &strings(&["h", "cpp"]), &[], None);
+
+        let detect = |path: Option<&str>, text| {
+            languages
+                .detect(path.map(Path::new), &Rope::from_str(text))
+                .name
+        };
+
+        assert_eq!(detect(Some("main.zig"), ""), "zig");
+        assert_eq!(detect(Some("build.zon"), ""), "zig");
+        assert_eq!(detect(Some("util.h"), ""), "cpp");
+        assert_eq!(detect(Some("main.cc"), ""), Language::plain().name);
+        assert_eq!(detect(Some("main.rs"), ""), "rust");
+        assert_eq!(languages.take_error(), None);
+
+        assert_eq!(detect(None, "#!/usr/bin/env tsx-node\n"), "tsx");
+        let err = languages.take_error().unwrap();
+        assert!(err.starts_with("grammar typescript: "), "{err}");
+
+        let grammar = |name| languages.grammar_library(languages.get(name).unwrap());
+        assert_eq!(grammar("tsx"), Some(library));
+        assert_eq!(grammar("zig"), None);
+        assert_eq!(languages.get("cpp").unwrap().grammar, "cpp");
+
+        fs::remove_dir_all(&runtime).unwrap();
+    }
+
+    #[test]
+    fn detection_order() {
+        let languages = Languages::new(Vec::new());
+        let detect = |path: Option<&str>, text| {
+            languages
+                .detect(path.map(Path::new), &Rope::from_str(text))
+                .name
+        };
+
+        assert_eq!(detect(Some("main.rs"), ""), "rust");
+        assert_eq!(detect(Some("Makefile"), ""), "make");
+        assert_eq!(detect(None, "#!/usr/bin/env python3\n"), "python");
+        assert_eq!(
+            detect(Some("script.sh"), "#!/usr/bin/env python3\n"),
+            "bash"
+        );
+        assert_eq!(detect(Some("notes"), "vim: ft=py\n"), "python");
+        assert_eq!(detect(Some("main.rs"), "// vim: ft=text\n"), "text");
+        assert_eq!(detect(None, "plain\n"), Language::plain().name);
+    }
 }
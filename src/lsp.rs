@@ -0,0 +1,852 @@
+use ropey::Rope;
+use serde_json::{Value, json};
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    io::{self, BufRead, BufReader, Write},
+    path::{self, Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    buffer::{Buffer, Buffers},
+    completion::{Item, Source},
+    diagnostic::{Diagnostic, Severity},
+    document::ContentChange,
+};
+
+/// The servers started for each language unless configured otherwise.
+const SERVERS: &[(&str, &[&str])] = &[
+    ("rust", &["rust-analyzer"]),
+    ("python", &["pylsp"]),
+    ("c", &["clangd"]),
+    ("cpp", &["clangd"]),
+    ("go", &["gopls"]),
+    ("javascript", &["typescript-language-server", "--stdio"]),
+    ("typescript", &["typescript-language-server", "--stdio"]),
+    ("bash", &["bash-language-server", "start"]),
+];
+
+/// How often to check for messages while a server is running.
+pub const POLL: Duration = Duration::from_millis(50);
+
+/// The source of the diagnostics published by language servers.
+const SOURCE: &str = "lsp";
+
+const INITIALIZE: i64 = 0;
+
+/// The id of the `shutdown` request, answered to the client rather than
+/// [`Lsp`].
+const SHUTDOWN: &str = "shutdown";
+
+/// How long a server has to answer `shutdown` and exit before it is killed.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// The language servers, started for the languages of the open buffers as
+/// needed, and the documents they were told about.
+pub struct Lsp {
+    /// The commands configured per language, overriding [`SERVERS`]. An empty
+    /// one disables the language's server.
+    commands: HashMap<String, Vec<String>>,
+    clients: HashMap<&'static str, Client>,
+    /// The languages whose server could not start or exited, which are not
+    /// started again.
+    failed: HashSet<&'static str>,
+    /// The documents opened on a server, by buffer id.
+    documents: HashMap<usize, Opened>,
+    completion: Option<CompletionRequest>,
+    /// The items of the last completion request, with its buffer and byte.
+    completions: Option<(usize, usize, Vec<Item>)>,
+    sender: Sender<Incoming>,
+    receiver: Receiver<Incoming>,
+}
+
+/// Completions asked for at a byte of a buffer, sent once its edits are.
+struct CompletionRequest {
+    buffer: usize,
+    byte: usize,
+    /// The language and id of the request once sent.
+    sent: Option<(&'static str, i64)>,
+}
+
+/// A message read from a language's server, or None once it exited.
+struct Incoming {
+    language: &'static str,
+    message: Option<Value>,
+}
+
+struct Opened {
+    language: &'static str,
+    uri: String,
+    version: i64,
+}
+
+struct Client {
+    /// The program, for errors.
+    name: String,
+    child: Child,
+    /// Sends messages to the thread writing them to the server.
+    writer: Sender<Value>,
+    next_id: i64,
+    /// Set once the server answered `initialize`.
+    capabilities: Option<Capabilities>,
+    /// Receives the answer to `shutdown`.
+    shutdown: Receiver<()>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Capabilities {
+    sync: Sync,
+    /// Whether positions count bytes rather than UTF-16 code units.
+    utf8: bool,
+    completion: bool,
+}
+
+/// How the server wants to be told about edits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Sync {
+    None,
+    Full,
+    Incremental,
+}
+
+impl Lsp {
+    pub fn set_command(&mut self, language: &str, command: Vec<String>) {
+        self.commands.insert(language.to_owned(), command);
+    }
+
+    pub fn is_running(&self) -> bool {
+        !self.clients.is_empty()
+    }
+
+    /// Handles the messages received from the servers, then tells them about
+    /// the buffers opened, edited and closed since the last call, starting
+    /// servers as needed. Returns the errors to report.
+    pub fn update(&mut self, buffers: &mut Buffers) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        while let Ok(Incoming { language, message }) = self.receiver.try_recv() {
+            match message {
+                Some(message) => self.handle(language, message, buffers, &mut errors),
+                None => self.exited(language, buffers, &mut errors),
+            }
+        }
+
+        self.sync(buffers, &mut errors);
+        self.send_completion_request(buffers);
+        errors
+    }
+
+    /// Tells the server a buffer was closed or replaced, e.g. reloaded.
+    pub fn close(&mut self, id: usize) {
+        if let Some(opened) = self.documents.remove(&id)
+            && let Some(client) = self.clients.get_mut(opened.language)
+        {
+            client.notify(
+                "textDocument/didClose",
+                json!({ "textDocument": { "uri": opened.uri } }),
+            );
+        }
+    }
+
+    /// Asks the server of a buffer for completions at `byte`, dropping the
+    /// previous request. [`Lsp::take_completions`] returns them once it
+    /// answered.
+    pub fn request_completions(&mut self, buffer: usize, byte: usize) {
+        self.completion = Some(CompletionRequest {
+            buffer,
+            byte,
+            sent: None,
+        });
+    }
+
+    /// The buffer, byte and items of the last completion request, once its
+    /// server answered.
+    pub fn take_completions(&mut self) -> Option<(usize, usize, Vec<Item>)> {
+        self.completions.take()
+    }
+
+    /// Tells the server a buffer was written, which some only check then.
+    pub fn saved(&mut self, id: usize) {
+        if let Some(opened) = self.documents.get(&id)
+            && let Some(client) = self.clients.get_mut(opened.language)
+        {
+            client.notify(
+                "textDocument/didSave",
+                json!({ "textDocument": { "uri": opened.uri } }),
+            );
+        }
+    }
+
+    fn handle(
+        &mut self,
+        language: &'static str,
+        message: Value,
+        buffers: &mut Buffers,
+        errors: &mut Vec<String>,
+    ) {
+        let Some(client) = self.clients.get_mut(language) else {
+            return;
+        };
+
+        let method = message["method"].as_str();
+        let params = &message["params"];
+
+        match (method, message.get("id")) {
+            // Requests from the server, none of which ion supports beyond
+            // answering.
+            (Some(method), Some(id)) => {
+                let result = match method {
+                    "workspace/configuration" => {
+                        let items = params["items"].as_array().map_or(0, Vec::len);
+                        Value::Array(vec![Value::Null; items])
+                    }
+                    _ => Value::Null,
+                };
+
+                client.send(json!({ "jsonrpc": "2.0", "id": id, "result": result }));
+            }
+            (None, Some(id)) if id == INITIALIZE => {
+                if let Some(error) = message["error"]["message"].as_str() {
+                    errors.push(format!("{}: {error}", client.name));
+                    self.clients.remove(language);
+                    self.failed.insert(language);
+                    return;
+                }
+
+                client.capabilities = Some(Capabilities::new(&message["result"]["capabilities"]));
+                client.notify("initialized", json!({}));
+            }
+            (None, Some(id))
+                if let Some(request) = &self.completion
+                    && request.sent == Some((language, id.as_i64().unwrap_or(-1))) =>
+            {
+                let result = &message["result"];
+                let items = (result.as_array().or_else(|| result["items"].as_array()))
+                    .into_iter()
+                    .flatten()
+                    .filter_map(completion_item)
+                    .collect();
+
+                self.completions = Some((request.buffer, request.byte, items));
+                self.completion = None;
+            }
+            (Some("textDocument/publishDiagnostics"), None) => {
+                let utf8 = client
+                    .capabilities
+                    .is_some_and(|capabilities| capabilities.utf8);
+                // Compared as paths, as servers may encode URIs differently.
+                let path = uri_path(params["uri"].as_str().unwrap_or_default());
+
+                let ids = (self.documents.iter())
+                    .filter(|(_, opened)| path.is_some() && uri_path(&opened.uri) == path)
+                    .map(|(id, _)| *id);
+
+                for id in ids {
+                    let Some(buffer) = buffers.get_mut(id) else {
+                        continue;
+                    };
+
+                    let rope = buffer.doc.rope();
+                    let diagnostics = (params["diagnostics"].as_array().into_iter().flatten())
+                        .map(|diagnostic| {
+                            let range = &diagnostic["range"];
+                            let start = byte(rope, &range["start"], utf8);
+                            let end = byte(rope, &range["end"], utf8).max(start);
+
+                            Diagnostic {
+                                source: SOURCE,
+                                range: start..end,
+                                severity: match diagnostic["severity"].as_u64() {
+                                    Some(2) => Severity::Warning,
+                                    Some(3) => Severity::Info,
+                                    Some(4) => Severity::Hint,
+                                    _ => Severity::Error,
+                                },
+                                message: (diagnostic["message"].as_str())
+                                    .unwrap_or_default()
+                                    .to_owned(),
+                            }
+                        })
+                        .collect();
+
+                    buffer.doc.set_diagnostics(SOURCE, diagnostics);
+                }
+            }
+            (Some("window/showMessage"), None) if params["type"] == 1 => {
+                let message = params["message"].as_str().unwrap_or_default();
+                errors.push(format!("{}: {message}", client.name));
+            }
+            _ => {}
+        }
+    }
+
+    /// Forgets a server that exited and the documents it had.
+    fn exited(&mut self, language: &'static str, buffers: &mut Buffers, errors: &mut Vec<String>) {
+        let Some(client) = self.clients.remove(language) else {
+            return;
+        };
+
+        errors.push(format!("{} exited", client.name));
+        self.failed.insert(language);
+
+        let ids: Vec<usize> = (self.documents.iter())
+            .filter(|(_, opened)| opened.language == language)
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in ids {
+            self.documents.remove(&id);
+
+            if let Some(buffer) = buffers.get_mut(id) {
+                buffer.doc.set_diagnostics(SOURCE, Vec::new());
+            }
+        }
+    }
+
+    fn sync(&mut self, buffers: &mut Buffers, errors: &mut Vec<String>) {
+        // Close the documents of buffers that are gone, or now have another
+        // file or language.
+        let stale: Vec<usize> = (self.documents.iter())
+            .filter(|(id, opened)| {
+                buffers
+                    .get(**id)
+                    .and_then(document)
+                    .is_none_or(|(language, uri)| language != opened.language || uri != opened.uri)
+            })
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in stale {
+            self.close(id);
+
+            if let Some(buffer) = buffers.get_mut(id) {
+                buffer.doc.set_diagnostics(SOURCE, Vec::new());
+            }
+        }
+
+        for buffer in buffers.iter_mut() {
+            let Some((language, uri)) = document(buffer) else {
+                continue;
+            };
+
+            self.start(language, errors);
+            let Some(client) = self.clients.get_mut(language) else {
+                continue;
+            };
+
+            let Some(capabilities) = client.capabilities else {
+                continue;
+            };
+
+            if capabilities.sync == Sync::None {
+                continue;
+            }
+
+            let Some(opened) = self.documents.get_mut(&buffer.id()) else {
+                client.notify(
+                    "textDocument/didOpen",
+                    json!({
+                        "textDocument": {
+                            "uri": uri,
+                            "languageId": language,
+                            "version": 0,
+                            "text": buffer.doc.rope().to_string(),
+                        }
+                    }),
+                );
+
+                buffer.doc.track_changes();
+                self.documents.insert(
+                    buffer.id(),
+                    Opened {
+                        language,
+                        uri,
+                        version: 0,
+                    },
+                );
+                continue;
+            };
+
+            let changes = buffer.doc.take_changes();
+            if changes.is_empty() {
+                continue;
+            }
+
+            let content_changes: Vec<Value> = match capabilities.sync {
+                Sync::Incremental => (changes.iter())
+                    .map(|change| content_change(change, capabilities.utf8))
+                    .collect(),
+                _ => vec![json!({ "text": buffer.doc.rope().to_string() })],
+            };
+
+            opened.version += 1;
+            client.notify(
+                "textDocument/didChange",
+                json!({
+                    "textDocument": { "uri": opened.uri, "version": opened.version },
+                    "contentChanges": content_changes,
+                }),
+            );
+        }
+    }
+
+    /// Sends the completion request once the document it is for is open and
+    /// up to date, or drops it if the buffer's server cannot complete.
+    fn send_completion_request(&mut self, buffers: &Buffers) {
+        let Some(request) = &mut self.completion else {
+            return;
+        };
+
+        if request.sent.is_some() {
+            return;
+        }
+
+        if let Some(opened) = self.documents.get(&request.buffer)
+            && let Some(client) = self.clients.get_mut(opened.language)
+            && let Some(capabilities) = client.capabilities
+            && capabilities.completion
+            && let Some(buffer) = buffers.get(request.buffer)
+        {
+            let point = if capabilities.utf8 {
+                buffer.doc.byte_to_point(request.byte)
+            } else {
+                buffer.doc.byte_to_utf16_point(request.byte)
+            };
+
+            let id = client.request(
+                "textDocument/completion",
+                json!({
+                    "textDocument": { "uri": opened.uri },
+                    "position": { "line": point.row, "character": point.column },
+                }),
+            );
+            request.sent = Some((opened.language, id));
+        } else {
+            self.completion = None;
+        }
+    }
+
+    /// Starts the server of `language` unless it is running, failed, or there
+    /// is none.
+    fn start(&mut self, language: &'static str, errors: &mut Vec<String>) {
+        if !self.clients.contains_key(language) && !self.failed.contains(language) {
+            let configured = self.commands.get(language);
+            let command: Vec<String> = match configured {
+                Some(command) => command.clone(),
+                None => (SERVERS.iter())
+                    .find(|(name, _)| *name == language)
+                    .map(|(_, command)| command.iter().map(|arg| arg.to_string()).collect())
+                    .unwrap_or_default(),
+            };
+
+            if command.is_empty() {
+                return;
+            }
+
+            match Client::start(language, &command, self.sender.clone()) {
+                Ok(client) => {
+                    self.clients.insert(language, client);
+                }
+                // Only complain about missing servers that were asked for.
+                Err(err) if err.kind() == io::ErrorKind::NotFound && configured.is_none() => {
+                    self.failed.insert(language);
+                }
+                Err(err) => {
+                    errors.push(format!("{}: {err}", command[0]));
+                    self.failed.insert(language);
+                }
+            }
+        }
+    }
+}
+
+impl Default for Lsp {
+    fn default() -> Self {
+        let (sender, receiver) = mpsc::channel();
+
+        Self {
+            commands: HashMap::new(),
+            clients: HashMap::new(),
+            failed: HashSet::new(),
+            documents: HashMap::new(),
+            completion: None,
+            completions: None,
+            sender,
+            receiver,
+        }
+    }
+}
+
+impl Client {
+    /// Starts `command` and sends it `initialize`. Its messages are sent to
+    /// `sender`, followed by None once it exits.
+    fn start(
+        language: &'static str,
+        command: &[String],
+        sender: Sender<Incoming>,
+    ) -> io::Result<Self> {
+        let mut child = Command::new(&command[0])
+            .args(&command[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+
+        let (writer, messages) = mpsc::channel::<Value>();
+        thread::spawn(move || {
+            for message in messages {
+                if write_message(&mut stdin, &message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let (shutdown_sender, shutdown) = mpsc::channel();
+        thread::spawn(move || {
+            loop {
+                let message = read_message(&mut stdout);
+                let exited = message.is_none();
+
+                if let Some(message) = &message
+                    && message.get("method").is_none()
+                    && message["id"] == SHUTDOWN
+                {
+                    _ = shutdown_sender.send(());
+                    continue;
+                }
+
+                if sender.send(Incoming { language, message }).is_err() || exited {
+                    break;
+                }
+            }
+        });
+
+        let root = env::current_dir().unwrap_or_default();
+        let mut client = Self {
+            name: command[0].clone(),
+            child,
+            writer,
+            next_id: INITIALIZE,
+            capabilities: None,
+            shutdown,
+        };
+
+        client.request(
+            "initialize",
+            json!({
+                "processId": std::process::id(),
+                "clientInfo": { "name": "ion" },
+                "rootUri": uri(&root),
+                "workspaceFolders": [{
+                    "uri": uri(&root),
+                    "name": root.file_name().unwrap_or_default().to_string_lossy(),
+                }],
+                "capabilities": {
+                    "general": { "positionEncodings": ["utf-8", "utf-16"] },
+                    "textDocument": {
+                        "synchronization": { "didSave": true },
+                        "publishDiagnostics": {},
+                        "completion": { "completionItem": { "snippetSupport": false } },
+                    },
+                    "workspace": { "configuration": true },
+                },
+            }),
+        );
+
+        Ok(client)
+    }
+
+    fn send(&self, message: Value) {
+        _ = self.writer.send(message);
+    }
+
+    /// Sends a request, returning its id.
+    fn request(&mut self, method: &str, params: Value) -> i64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }));
+        id
+    }
+
+    fn notify(&self, method: &str, params: Value) {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+    }
+}
+
+impl Drop for Client {
+    /// Asks the server to exit, killing it if it does not in time.
+    fn drop(&mut self) {
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+
+        self.send(json!({ "jsonrpc": "2.0", "id": SHUTDOWN, "method": "shutdown" }));
+        if self.shutdown.recv_timeout(SHUTDOWN_TIMEOUT).is_ok() {
+            self.notify("exit", Value::Null);
+
+            while Instant::now() < deadline {
+                if let Ok(Some(_)) = self.child.try_wait() {
+                    return;
+                }
+
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+
+        _ = self.child.kill();
+        _ = self.child.wait();
+    }
+}
+
+impl Capabilities {
+    fn new(capabilities: &Value) -> Self {
+        let sync = &capabilities["textDocumentSync"];
+        let kind = sync.as_u64().or_else(|| sync["change"].as_u64());
+
+        Self {
+            sync: match kind {
+                Some(1) => Sync::Full,
+                Some(2) => Sync::Incremental,
+                _ => Sync::None,
+            },
+            utf8: capabilities["positionEncoding"] == "utf-8",
+            completion: capabilities["completionProvider"].is_object(),
+        }
+    }
+}
+
+/// The language and URI a buffer is opened on its server with, if it has a
+/// file.
+fn document(buffer: &Buffer) -> Option<(&'static str, String)> {
+    let path = path::absolute(buffer.path.as_ref()?).ok()?;
+    Some((buffer.language.name(), uri(&path)))
+}
+
+/// An item of a completion response. Snippets are inserted as their label,
+/// as servers are told ion does not support them.
+fn completion_item(item: &Value) -> Option<Item> {
+    let label = item["label"].as_str()?;
+    let text = match item["insertTextFormat"].as_u64() {
+        Some(2) => item["filterText"].as_str(),
+        _ => (item["textEdit"]["newText"].as_str()).or(item["insertText"].as_str()),
+    };
+
+    Some(Item {
+        label: label.to_owned(),
+        text: text.unwrap_or(label).to_owned(),
+        source: Source::Lsp,
+    })
+}
+
+fn content_change(change: &ContentChange, utf8: bool) -> Value {
+    let (start, end) = if utf8 {
+        (change.start, change.old_end)
+    } else {
+        (change.start_utf16, change.old_end_utf16)
+    };
+
+    json!({
+        "range": {
+            "start": { "line": start.row, "character": start.column },
+            "end": { "line": end.row, "character": end.column },
+        },
+        "text": change.text,
+    })
+}
+
+/// The byte at an LSP position in `rope`, whose character counts bytes if
+/// `utf8`, or else UTF-16 code units. Positions past the end are clamped.
+fn byte(rope: &Rope, position: &Value, utf8: bool) -> usize {
+    let number = |key: &str| position[key].as_u64().unwrap_or_default() as usize;
+    let (line_idx, character) = (number("line"), number("character"));
+
+    if line_idx >= rope.len_lines() {
+        return rope.len_bytes();
+    }
+
+    let start = rope.line_to_byte(line_idx);
+    let line = rope.line(line_idx);
+
+    let column = if utf8 {
+        line.char_to_byte(line.byte_to_char(character.min(line.len_bytes())))
+    } else {
+        line.char_to_byte(line.utf16_cu_to_char(character.min(line.len_utf16_cu())))
+    };
+
+    start + column
+}
+
+/// The `file://` URI of an absolute path.
+fn uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+
+    for &byte in path.to_string_lossy().as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                uri.push(byte as char);
+            }
+            _ => uri.push_str(&format!("%{byte:02X}")),
+        }
+    }
+
+    uri
+}
+
+/// The path of a `file://` URI.
+fn uri_path(uri: &str) -> Option<PathBuf> {
+    let encoded = uri.strip_prefix("file://")?.as_bytes();
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut idx = 0;
+
+    while idx < encoded.len() {
+        let hex = encoded
+            .get(idx + 1..idx + 3)
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+
+        match (encoded[idx], hex) {
+            (b'%', Some(byte)) => {
+                bytes.push(byte);
+                idx += 3;
+            }
+            (byte, _) => {
+                bytes.push(byte);
+                idx += 1;
+            }
+        }
+    }
+
+    Some(PathBuf::from(String::from_utf8(bytes).ok()?))
+}
+
+fn write_message(writer: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    writer.flush()
+}
+
+/// Reads the next message with a valid JSON body. None once the stream ends
+/// or breaks.
+fn read_message(reader: &mut impl BufRead) -> Option<Value> {
+    loop {
+        let mut len = None;
+
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+
+            if let Some((name, value)) = line.split_once(':')
+                && name.eq_ignore_ascii_case("content-length")
+            {
+                len = value.trim().parse().ok();
+            }
+        }
+
+        let mut body = vec![0; len?];
+        reader.read_exact(&mut body).ok()?;
+
+        if let Ok(message) = serde_json::from_slice(&body) {
+            return Some(message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ratatui::{buffer, layout::Rect};
+    use std::{fs, sync::Arc};
+
+    use super::*;
+    use crate::{
+        highlighter::Highlighter, language::Languages, options::LineNumbers, theme::Theme,
+        view::View,
+    };
+
+    /// Calls [`Lsp::update`] until `done` or a few seconds passed.
+    fn update_until(lsp: &mut Lsp, buffers: &mut Buffers, done: impl Fn(&Lsp, &Buffers) -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+
+        while !done(lsp, buffers) {
+            assert_eq!(lsp.update(buffers), Vec::<String>::new());
+            assert!(Instant::now() < deadline, "timed out");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn mock_server() {
+        let dir = env::temp_dir().join(format!("ion-lsp-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("main.rs");
+        fs::write(&path, "// 🦀 é\nfn main() {}\n").unwrap();
+
+        let languages = Arc::new(Languages::new(Vec::new()));
+        let mut buffers = Buffers::default();
+        let id = buffers.open(&path, 1, &languages).unwrap();
+
+        let mut lsp = Lsp::default();
+        let script = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/mock_lsp.py");
+        lsp.set_command("rust", vec!["python3".into(), script.into()]);
+
+        // The server answers `initialize`, then is told `initialized` and
+        // about the buffer, or it exits.
+        update_until(&mut lsp, &mut buffers, |lsp, _| {
+            lsp.documents.contains_key(&id)
+        });
+
+        // The crab is two UTF-16 code units, and the server gets the text
+        // inserted after `é` at character 7.
+        let doc = &mut buffers.get_mut(id).unwrap().doc;
+        doc.replace(3..7, "ab");
+        doc.replace(8..8, "ö");
+
+        update_until(&mut lsp, &mut buffers, |_, buffers| {
+            !buffers.get(id).unwrap().doc.diagnostics().is_empty()
+        });
+
+        // The server's text matches, and its diagnostic covers the `ö`.
+        let doc = &mut buffers.get_mut(id).unwrap().doc;
+        let diagnostic = &doc.diagnostics()[0];
+        assert_eq!(diagnostic.message, "// ab éö\nfn main() {}\n");
+        assert_eq!(doc.rope().to_string(), diagnostic.message);
+        assert_eq!(diagnostic.range, 8..10);
+        assert_eq!(diagnostic.severity, Severity::Warning);
+
+        let area = Rect::new(0, 0, 40, 4);
+        let mut buf = buffer::Buffer::empty(area);
+        let language = buffers.get(id).unwrap().language.clone();
+        let doc = &mut buffers.get_mut(id).unwrap().doc;
+        _ = View::new(id).render(
+            doc,
+            1,
+            &language,
+            &mut Highlighter::new(languages),
+            &Theme::default(),
+            LineNumbers::Absolute,
+            None,
+            "main.rs",
+            area,
+            &mut buf,
+        );
+        assert_eq!(buf[(0, 0)].symbol(), "●");
+        assert_eq!(buf[(0, 1)].symbol(), " ");
+
+        // It answers `shutdown` and exits without being killed.
+        let start = Instant::now();
+        drop(lsp);
+        assert!(start.elapsed() < SHUTDOWN_TIMEOUT);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
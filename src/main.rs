@@ -1,43 +1,97 @@
-#![feature(
-    if_let_guard,
-    let_chains,
-    macro_metavar_expr,
-    round_char_boundary,
-    iter_map_windows,
-    str_as_str
-)]
-
-use clap::Parser;
+#![feature(iter_map_windows, str_as_str)]
+#![cfg_attr(test, feature(test))]
+
+use clap::{Parser, ValueEnum};
 use ratatui::crossterm::{self, cursor, event, execute, queue, terminal};
-use std::{io::stdout, path::PathBuf};
+use std::{io::stdout, path::PathBuf, process::ExitCode};
 
 use app::App;
+use config::Config;
 
 mod app;
+mod backend;
+#[cfg(test)]
+mod bench;
+mod buffer;
+mod cmdline;
 mod command;
+mod completion;
+mod config;
 mod default;
+mod diagnostic;
 mod document;
+mod files;
+mod fold;
+mod fuzzy;
+mod grammar;
+mod grep;
+mod highlighter;
+mod injection;
 mod input;
 mod keymap;
 mod language;
+mod lsp;
+mod operator;
+mod options;
+mod picker;
+mod quickfix;
+mod register;
+mod search;
+mod substitute;
+mod symbol;
+mod syntax;
 mod theme;
+mod view;
+mod window;
 
 #[derive(Parser)]
 struct Args {
-    file: Option<PathBuf>,
+    files: Vec<PathBuf>,
+    /// Read the config from this file instead of `~/.config/ion/config.toml`.
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// `build` compiles the tree-sitter grammar checkouts given as files into
+    /// the runtime directory instead of editing them.
+    #[arg(long, value_name = "ACTION")]
+    grammar: Option<GrammarAction>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum GrammarAction {
+    Build,
 }
 
-fn main() {
+fn main() -> ExitCode {
     let args = Args::parse();
 
-    let mut app = if let Some(file) = args.file {
-        App::open(&file)
-    } else {
-        App::new()
-    };
+    if let Some(GrammarAction::Build) = args.grammar {
+        return build_grammars(&args.files);
+    }
+
+    let mut app = App::new();
 
     default::init(&mut app);
 
+    let errors = match Config::load(args.config.as_deref()) {
+        Ok(config) => config.apply(&mut app),
+        Err(err) => vec![err],
+    };
+
+    if !errors.is_empty() {
+        app.report_error(format!("config: {}", errors.join("; ")));
+    }
+
+    // After the config, which can declare the languages they are in.
+    for file in &args.files {
+        if let Err(err) = app.open(file) {
+            app.report_error(err);
+        }
+    }
+
+    if let Some(first) = args.files.first() {
+        _ = app.open(first);
+    }
+
     queue!(
         stdout(),
         cursor::SetCursorStyle::SteadyBlock,
@@ -45,16 +99,45 @@ fn main() {
     )
     .unwrap();
 
-    let mut terminal = ratatui::init();
+    let mut terminal = backend::init();
 
     while !app.exit {
         execute!(stdout(), terminal::BeginSynchronizedUpdate).unwrap();
         terminal.draw(|frame| app.view(frame)).unwrap();
         queue!(stdout(), terminal::EndSynchronizedUpdate).unwrap();
 
+        if let Some(timeout) = app.poll_timeout()
+            && !event::poll(timeout).unwrap()
+        {
+            app.handle_timeout();
+            continue;
+        }
+
         let ev = crossterm::event::read().unwrap();
         app.handle_ev(ev);
     }
 
     ratatui::restore();
+    ExitCode::SUCCESS
+}
+
+fn build_grammars(checkouts: &[PathBuf]) -> ExitCode {
+    if checkouts.is_empty() {
+        eprintln!("no grammar checkouts given");
+        return ExitCode::FAILURE;
+    }
+
+    let mut code = ExitCode::SUCCESS;
+
+    for checkout in checkouts {
+        match grammar::build(checkout) {
+            Ok(path) => println!("built {}", path.display()),
+            Err(err) => {
+                eprintln!("{}: {err}", checkout.display());
+                code = ExitCode::FAILURE;
+            }
+        }
+    }
+
+    code
 }
@@ -0,0 +1,586 @@
+use std::{ops::Range, rc::Rc};
+
+use crate::{app::App, document::Document, keymap::Key, language::Language, syntax, view::View};
+
+/// An action applied to the text covered by a motion or text object, as in
+/// `d2w` or `ci(`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Delete,
+    Change,
+    Yank,
+    Indent,
+    Outdent,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MotionKind {
+    /// Covers the text up to the new position, e.g. `w`.
+    Exclusive,
+    /// Also covers the character at the new position, e.g. `e`.
+    Inclusive,
+    /// Covers whole lines, e.g. `j`.
+    Linewise,
+}
+
+/// A cursor movement that can also be the target of an operator.
+#[derive(Clone)]
+pub struct Motion {
+    kind: MotionKind,
+    #[allow(clippy::type_complexity)]
+    action: Rc<dyn Fn(&mut View, &Document, Option<usize>)>,
+}
+
+impl Motion {
+    pub fn new(
+        kind: MotionKind,
+        action: impl Fn(&mut View, &Document, Option<usize>) + 'static,
+    ) -> Self {
+        Self {
+            kind,
+            action: Rc::new(action),
+        }
+    }
+
+    /// A motion run once per count.
+    pub fn repeated(kind: MotionKind, action: impl Fn(&mut View, &Document) + 'static) -> Self {
+        Self::new(kind, move |view, doc, count| {
+            for _ in 0..count.unwrap_or(1) {
+                action(view, doc);
+            }
+        })
+    }
+
+    pub fn apply(&self, view: &mut View, doc: &Document, count: Option<usize>) {
+        (self.action)(view, doc, count)
+    }
+}
+
+/// A range around the cursor, such as a word or the inside of parentheses.
+#[derive(Clone)]
+pub struct TextObject {
+    #[allow(clippy::type_complexity)]
+    action: Rc<dyn Fn(&View, &Document, &Language, usize) -> Option<Range<usize>>>,
+}
+
+impl TextObject {
+    pub fn new(
+        action: impl Fn(&View, &Document, &Language, usize) -> Option<Range<usize>> + 'static,
+    ) -> Self {
+        Self {
+            action: Rc::new(action),
+        }
+    }
+
+    /// A word and, if `around` is set, the whitespace after it.
+    pub fn word(around: bool) -> Self {
+        Self::new(move |view, doc, _, count| word(doc, view.position_byte(), count, around))
+    }
+
+    /// The text between `open` and `close`, including them if `around` is set.
+    pub fn pair(open: char, close: char, around: bool) -> Self {
+        Self::new(move |view, doc, _, count| {
+            pair(doc, view.position_byte(), open, close, count, around)
+        })
+    }
+
+    /// The text between two `quote`s on the cursor line.
+    pub fn quote(quote: char, around: bool) -> Self {
+        Self::new(move |view, doc, _, _| self::quote(doc, view.position_byte(), quote, around))
+    }
+
+    /// A syntax node found by the language's `textobjects.scm`, e.g. the
+    /// function around the cursor, or only its body unless `around` is set.
+    pub fn syntax(name: &'static str, around: bool) -> Self {
+        Self::new(move |view, doc, language, count| {
+            syntax_object(doc, language, view.position_byte(), name, count, around)
+        })
+    }
+}
+
+#[derive(Clone)]
+pub enum Target {
+    Motion(Motion),
+    Object(TextObject),
+    /// Whole lines from the cursor, as in `dd`.
+    Line,
+}
+
+/// An operator waiting for its motion.
+#[derive(Debug, Clone, Copy)]
+pub struct Pending {
+    pub operator: Operator,
+    count: Option<usize>,
+}
+
+/// The last change, for `.` to repeat.
+#[derive(Clone)]
+pub struct LastChange {
+    operator: Operator,
+    target: Target,
+    count: Option<usize>,
+    /// The keys typed in insert mode after a change.
+    pub inserted: Vec<Key>,
+}
+
+/// Waits for the motion of `operator`.
+pub fn begin(app: &mut App, operator: Operator) {
+    let count = app.take_count();
+    app.operator = Some(Pending { operator, count });
+}
+
+/// Applies the pending operator to `target`.
+pub fn finish(app: &mut App, target: Target) {
+    let Some(pending) = app.operator.take() else {
+        return;
+    };
+
+    let count = match (pending.count, app.take_count()) {
+        (Some(a), Some(b)) => Some(a * b),
+        (a, b) => a.or(b),
+    };
+
+    apply(app, pending.operator, target, count);
+}
+
+/// Applies the pending operator to whole lines if it is `operator`, as for
+/// `dd` or `>>`.
+pub fn finish_line(app: &mut App, operator: Operator) {
+    match app.operator {
+        Some(pending) if pending.operator == operator => finish(app, Target::Line),
+        _ => app.operator = None,
+    }
+}
+
+/// Applies `operator` to `target` and remembers it for `.`.
+pub fn apply(app: &mut App, operator: Operator, target: Target, count: Option<usize>) {
+    run(app, operator, &target, count);
+
+    app.last_change = Some(LastChange {
+        operator,
+        target,
+        count,
+        inserted: Vec::new(),
+    });
+
+    if operator == Operator::Change {
+        app.recording = Some(Vec::new());
+    }
+}
+
+/// Repeats the last change, with a new count if one was given.
+pub fn repeat(app: &mut App) {
+    let Some(mut change) = app.last_change.clone() else {
+        return;
+    };
+
+    change.count = app.take_count().or(change.count);
+    run(app, change.operator, &change.target, change.count);
+
+    for key in &change.inserted {
+        app.handle_key(*key);
+    }
+
+    app.last_change = Some(change);
+}
+
+/// Selects the text covered by `object` in select mode, as in `vaf`.
+pub fn select(app: &mut App, object: &TextObject) {
+    let count = app.take_count().unwrap_or(1);
+    let language = app.buffer().language.clone();
+    let (view, doc) = app.current_mut();
+
+    if let Some(range) = (object.action)(view, doc, &language, count)
+        && !range.is_empty()
+    {
+        view.select(doc, range);
+        view.scroll_to_cursor(doc);
+    }
+}
+
+/// Applies `operator` to the selection in select mode.
+pub fn apply_selection(app: &mut App, operator: Operator) {
+    let width = app.options.tab_width;
+    let (view, doc) = app.current_mut();
+    let text = view.selected_text(doc);
+
+    match operator {
+        Operator::Delete => view.remove(doc),
+        Operator::Change => {
+            view.remove(doc);
+            view.enter_insert(doc);
+        }
+        Operator::Yank => view.enter_normal(doc),
+        Operator::Indent | Operator::Outdent => {
+            let lines = view.selected_lines(doc);
+            view.indent_lines(doc, lines, width, operator == Operator::Outdent);
+            view.enter_normal(doc);
+            return;
+        }
+    }
+
+    if let Err(err) = app.registers.yank(text) {
+        app.report_error(err);
+    }
+}
+
+fn run(app: &mut App, operator: Operator, target: &Target, count: Option<usize>) {
+    let width = app.options.tab_width;
+    let language = app.buffer().language.clone();
+    let (view, doc) = app.current_mut();
+
+    let Some((range, linewise)) = target_range(view, doc, &language, target, count) else {
+        return;
+    };
+
+    let rope = doc.rope();
+    let text = rope.byte_slice(range.clone()).to_string();
+
+    match operator {
+        Operator::Indent | Operator::Outdent => {
+            let last = range.end.saturating_sub(1).max(range.start);
+            let lines = rope.byte_to_line(range.start)..rope.byte_to_line(last) + 1;
+            view.indent_lines(doc, lines, width, operator == Operator::Outdent);
+            return;
+        }
+        Operator::Yank => view.move_to_byte(doc, range.start),
+        Operator::Delete => {
+            // Deleting the last lines also takes the newline before them.
+            let start = if linewise && range.end == rope.len_bytes() && !text.ends_with('\n') {
+                range.start.saturating_sub(1)
+            } else {
+                range.start
+            };
+
+            view.remove_range(doc, start..range.end);
+
+            if linewise {
+                let (_, mut line_idx) = view.position(doc);
+
+                // Stay off the empty line after a final newline.
+                if view.position_byte() == doc.rope().len_bytes() && line_idx > 0 {
+                    line_idx -= 1;
+                }
+
+                view.goto_line(doc, line_idx);
+            }
+        }
+        Operator::Change => {
+            // Changing lines keeps an empty line to type in.
+            let end = if linewise && text.ends_with('\n') {
+                range.end - 1
+            } else {
+                range.end
+            };
+
+            view.enter_insert(doc);
+            view.remove_range(doc, range.start..end);
+        }
+    }
+
+    let text = if linewise && !text.ends_with('\n') {
+        text + "\n"
+    } else {
+        text
+    };
+
+    if let Err(err) = app.registers.yank(text) {
+        app.report_error(err);
+    }
+}
+
+/// The range covered by `target`, and whether it is made of whole lines.
+fn target_range(
+    view: &View,
+    doc: &Document,
+    language: &Language,
+    target: &Target,
+    count: Option<usize>,
+) -> Option<(Range<usize>, bool)> {
+    let rope = doc.rope();
+    let cursor = view.position_byte();
+
+    let lines = |first: usize, last: usize| {
+        let end = rope.try_line_to_byte(last + 1).unwrap_or(rope.len_bytes());
+        (rope.line_to_byte(first)..end, true)
+    };
+
+    match target {
+        Target::Line => {
+            let (_, line_idx) = view.position(doc);
+            let last = (line_idx + count.unwrap_or(1) - 1).min(rope.len_lines() - 1);
+            Some(lines(line_idx, last))
+        }
+        Target::Object(object) => {
+            let range = (object.action)(view, doc, language, count.unwrap_or(1))?;
+            Some((range, false))
+        }
+        Target::Motion(motion) => {
+            let mut moved = view.clone();
+            motion.apply(&mut moved, doc, count);
+
+            let target = moved.position_byte();
+            let (start, end) = (cursor.min(target), cursor.max(target));
+
+            match motion.kind {
+                MotionKind::Exclusive => Some((start..end, false)),
+                MotionKind::Inclusive => Some((start..next_char(doc, end), false)),
+                MotionKind::Linewise => {
+                    Some(lines(rope.byte_to_line(start), rope.byte_to_line(end)))
+                }
+            }
+        }
+    }
+}
+
+fn next_char(doc: &Document, byte: usize) -> usize {
+    let rope = doc.rope();
+    let char_idx = rope.byte_to_char(byte);
+    rope.char_to_byte((char_idx + 1).min(rope.len_chars()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WordKind {
+    Word,
+    Punctuation,
+    Whitespace,
+    Newline,
+}
+
+impl WordKind {
+    fn new(ch: char) -> Self {
+        match ch {
+            '\n' | '\r' => Self::Newline,
+            ch if ch.is_whitespace() => Self::Whitespace,
+            ch if ch.is_alphanumeric() || ch == '_' => Self::Word,
+            _ => Self::Punctuation,
+        }
+    }
+}
+
+/// `count` words starting with the one under the cursor, with the whitespace
+/// after each if `around` is set.
+fn word(doc: &Document, byte: usize, count: usize, around: bool) -> Option<Range<usize>> {
+    let rope = doc.rope();
+    let kind_at = |char_idx: usize| rope.get_char(char_idx).map(WordKind::new);
+
+    let cursor = rope.byte_to_char(byte);
+    let kind = kind_at(cursor).filter(|kind| *kind != WordKind::Newline)?;
+
+    let mut start = cursor;
+    while start > 0 && kind_at(start - 1) == Some(kind) {
+        start -= 1;
+    }
+
+    let mut end = cursor;
+
+    for idx in 0..count {
+        if idx > 0 {
+            let Some(next) = kind_at(end).filter(|kind| *kind != WordKind::Newline) else {
+                break;
+            };
+
+            while kind_at(end) == Some(next) {
+                end += 1;
+            }
+        } else {
+            while kind_at(end) == Some(kind) {
+                end += 1;
+            }
+        }
+
+        if around && kind != WordKind::Whitespace {
+            while kind_at(end) == Some(WordKind::Whitespace) {
+                end += 1;
+            }
+        }
+    }
+
+    Some(rope.char_to_byte(start)..rope.char_to_byte(end))
+}
+
+/// The `count`th enclosing pair of `open` and `close` around the cursor.
+fn pair(
+    doc: &Document,
+    byte: usize,
+    open: char,
+    close: char,
+    count: usize,
+    around: bool,
+) -> Option<Range<usize>> {
+    let rope = doc.rope();
+    let cursor = rope.byte_to_char(byte);
+
+    let mut start = cursor + usize::from(rope.get_char(cursor) == Some(open));
+    let mut end = cursor + usize::from(rope.get_char(cursor) == Some(open));
+
+    for _ in 0..count {
+        let mut depth = 0usize;
+        start = loop {
+            start = start.checked_sub(1)?;
+
+            match rope.char(start) {
+                ch if ch == close => depth += 1,
+                ch if ch == open && depth == 0 => break start,
+                ch if ch == open => depth -= 1,
+                _ => {}
+            }
+        };
+
+        let mut depth = 0usize;
+        end = loop {
+            match rope.get_char(end)? {
+                ch if ch == open => depth += 1,
+                ch if ch == close && depth == 0 => break end,
+                ch if ch == close => depth -= 1,
+                _ => {}
+            }
+
+            end += 1;
+        };
+
+        end += 1;
+    }
+
+    let (start, end) = if around {
+        (start, end)
+    } else {
+        (start + 1, end - 1)
+    };
+
+    Some(rope.char_to_byte(start)..rope.char_to_byte(end))
+}
+
+/// The quoted text on the cursor line that contains or follows the cursor.
+fn quote(doc: &Document, byte: usize, quote: char, around: bool) -> Option<Range<usize>> {
+    let rope = doc.rope();
+    let line_idx = rope.byte_to_line(byte);
+    let line_start = rope.line_to_char(line_idx);
+    let cursor = rope.byte_to_char(byte) - line_start;
+
+    let quotes: Vec<usize> = rope
+        .line(line_idx)
+        .chars()
+        .enumerate()
+        .filter_map(|(idx, ch)| (ch == quote).then_some(idx))
+        .collect();
+
+    let (start, end) = quotes
+        .chunks_exact(2)
+        .map(|pair| (pair[0], pair[1]))
+        .find(|&(_, end)| end >= cursor)?;
+
+    let (start, end) = if around {
+        (start, end + 1)
+    } else {
+        (start + 1, end)
+    };
+
+    Some(rope.char_to_byte(line_start + start)..rope.char_to_byte(line_start + end))
+}
+
+/// The `count`th text object called `name` around the cursor, from the
+/// smallest one.
+fn syntax_object(
+    doc: &Document,
+    language: &Language,
+    byte: usize,
+    name: &str,
+    count: usize,
+    around: bool,
+) -> Option<Range<usize>> {
+    let query = language.grammar()?.textobjects()?;
+    let tree = doc.tree()?;
+
+    let mut objects: Vec<_> = syntax::objects(query, tree, doc.rope(), name, byte..byte + 1)
+        .into_iter()
+        .filter(|object| object.around.contains(&byte))
+        .collect();
+
+    objects.sort_by_key(|object| object.around.len());
+    objects.dedup_by(|a, b| a.around == b.around);
+
+    let object = objects.into_iter().nth(count - 1)?;
+
+    if around {
+        Some(object.around)
+    } else {
+        object.inside
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ropey::Rope;
+
+    use super::*;
+
+    /// Runs a text object on `text` with the cursor at the `|`, which is
+    /// removed, and returns the text it covers.
+    fn object(
+        text: &str,
+        find: impl Fn(&Document, usize) -> Option<Range<usize>>,
+    ) -> Option<String> {
+        let cursor = text.find('|').expect("a cursor");
+        let doc = Document::new(Rope::from_str(&text.replacen('|', "", 1)));
+        let range = find(&doc, cursor)?;
+        Some(doc.rope().byte_slice(range).to_string())
+    }
+
+    #[test]
+    fn words() {
+        let iw = |text, count| object(text, |doc, byte| word(doc, byte, count, false));
+        let aw = |text, count| object(text, |doc, byte| word(doc, byte, count, true));
+
+        assert_eq!(iw("let fo|o_bar = 1;", 1).as_deref(), Some("foo_bar"));
+        assert_eq!(aw("let fo|o_bar = 1;", 1).as_deref(), Some("foo_bar "));
+        assert_eq!(iw("a|.b", 1).as_deref(), Some("."));
+        assert_eq!(iw("a |  b", 1).as_deref(), Some("   "));
+        assert_eq!(aw("a |  b", 1).as_deref(), Some("   "));
+        assert_eq!(iw("|héllo wörld", 1).as_deref(), Some("héllo"));
+
+        // Counts take the following words, without crossing the line end.
+        assert_eq!(iw("|one two three", 3).as_deref(), Some("one two"));
+        assert_eq!(aw("|one two three", 2).as_deref(), Some("one two "));
+        assert_eq!(aw("|one two\nthree", 5).as_deref(), Some("one two"));
+        assert_eq!(iw("one|\n", 1), None);
+    }
+
+    #[test]
+    fn pairs() {
+        let i = |text, count| object(text, |doc, byte| pair(doc, byte, '(', ')', count, false));
+        let a = |text, count| object(text, |doc, byte| pair(doc, byte, '(', ')', count, true));
+
+        assert_eq!(i("f(a, |b)", 1).as_deref(), Some("a, b"));
+        assert_eq!(a("f(a, |b)", 1).as_deref(), Some("(a, b)"));
+        assert_eq!(i("f(a, g(|b), c)", 1).as_deref(), Some("b"));
+        assert_eq!(i("f(a, g(|b), c)", 2).as_deref(), Some("a, g(b), c"));
+        assert_eq!(i("f(a, g(b)|, c)", 1).as_deref(), Some("a, g(b), c"));
+        assert_eq!(i("f(a, g(b), (|c))", 1).as_deref(), Some("c"));
+
+        // On a bracket, the pair it opens or closes.
+        assert_eq!(a("f|(a, (b))", 1).as_deref(), Some("(a, (b))"));
+        assert_eq!(a("f(a, (b)|)", 1).as_deref(), Some("(a, (b))"));
+        assert_eq!(i("f(\n  |x\n)", 1).as_deref(), Some("\n  x\n"));
+        assert_eq!(i("f(|)", 1).as_deref(), Some(""));
+
+        assert_eq!(i("|f(a)", 1), None);
+        assert_eq!(i("f(|a", 1), None);
+        assert_eq!(i("f(|a)", 2), None);
+    }
+
+    #[test]
+    fn quotes() {
+        let i = |text| object(text, |doc, byte| quote(doc, byte, '"', false));
+        let a = |text| object(text, |doc, byte| quote(doc, byte, '"', true));
+
+        assert_eq!(i(r#"f("a|b", "c")"#).as_deref(), Some("ab"));
+        assert_eq!(a(r#"f("a|b", "c")"#).as_deref(), Some(r#""ab""#));
+        assert_eq!(i(r#"f("ab"|, "c")"#).as_deref(), Some("c"));
+        assert_eq!(i(r#"|f("ab", "c")"#).as_deref(), Some("ab"));
+        assert_eq!(i(r#"f("ab|")"#).as_deref(), Some("ab"));
+        assert_eq!(i(r#"f("")|"#), None);
+        assert_eq!(i("\"a\nb|\""), None);
+        assert_eq!(i(r#"f("é|ö")"#).as_deref(), Some("éö"));
+    }
+}
@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+use crate::register::Clipboard;
+
+pub struct Options {
+    pub tab_width: usize,
+    /// How long to wait for the next key of a sequence.
+    pub timeout: Duration,
+    pub line_numbers: LineNumbers,
+    /// The shell command `:make` runs.
+    pub make: String,
+    /// The clipboard behind the `+` and `*` registers, detected from the
+    /// environment if unset.
+    pub clipboard: Option<Clipboard>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineNumbers {
+    Absolute,
+    /// The distance to the cursor line, which shows its own number.
+    Relative,
+    None,
+}
+
+impl Options {
+    /// Sets an option by name, as in `:set tabwidth=2`.
+    pub fn set(&mut self, name: &str, value: Option<&str>) -> Result<(), String> {
+        let number = || {
+            let value = value.ok_or_else(|| format!("{name} needs a value"))?;
+            value
+                .parse::<usize>()
+                .map_err(|_| format!("invalid value for {name}: {value}"))
+        };
+
+        match name {
+            "tabwidth" | "tw" => {
+                self.tab_width = number()?.max(1);
+            }
+            "timeoutlen" | "tm" => {
+                self.timeout = Duration::from_millis(number()? as u64);
+            }
+            "linenumbers" | "nu" => {
+                self.line_numbers = match value {
+                    Some("absolute") => LineNumbers::Absolute,
+                    Some("relative") => LineNumbers::Relative,
+                    Some("none") => LineNumbers::None,
+                    Some(value) => return Err(format!("invalid value for {name}: {value}")),
+                    None => return Err(format!("{name} needs a value")),
+                };
+            }
+            "makeprg" | "mp" => {
+                self.make = value
+                    .ok_or_else(|| format!("{name} needs a value"))?
+                    .to_owned();
+            }
+            "clipboard" | "cb" => {
+                self.clipboard = match value {
+                    Some("auto") => None,
+                    Some("osc52") => Some(Clipboard::Osc52),
+                    Some(value) => return Err(format!("invalid value for {name}: {value}")),
+                    None => return Err(format!("{name} needs a value")),
+                };
+            }
+            _ => return Err(format!("unknown option: {name}")),
+        }
+
+        Ok(())
+    }
+
+    pub fn clipboard(&self) -> Clipboard {
+        self.clipboard.clone().unwrap_or_else(Clipboard::detect)
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            tab_width: 4,
+            timeout: Duration::from_millis(1000),
+            line_numbers: LineNumbers::Absolute,
+            make: "cargo check --message-format=json".into(),
+            clipboard: None,
+        }
+    }
+}
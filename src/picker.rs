@@ -0,0 +1,516 @@
+use ratatui::{
+    buffer::Buffer,
+    layout::{Constraint, Layout, Position, Rect},
+    text::{Line, Span},
+    widgets::{Block, Borders, Clear, Paragraph, Widget},
+};
+use ropey::Rope;
+use std::{
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::mpsc::{Receiver, TryRecvError},
+};
+
+use crate::{
+    app::App,
+    document::Document,
+    fuzzy::{self, Match},
+    highlighter::{Highlighter, Response},
+    language::{Language, Languages},
+    options::LineNumbers,
+    search::Search,
+    theme::Theme,
+    view::View,
+};
+
+/// The buffer id the preview is highlighted under, which no buffer has.
+pub const PREVIEW_BUFFER: usize = 0;
+
+/// The window id the preview keeps its highlights under.
+const PREVIEW_WINDOW: usize = 0;
+
+/// Files larger than this are not previewed.
+const PREVIEW_MAX_BYTES: u64 = 8 << 20;
+
+pub struct Item {
+    pub label: String,
+    /// The characters of the label to highlight, e.g. a search match.
+    pub highlight: Option<Range<usize>>,
+    /// Where the item is, previewed next to the list.
+    pub location: Option<Location>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub path: PathBuf,
+    pub line: usize,
+    /// The byte in the line.
+    pub column: usize,
+}
+
+impl Item {
+    pub fn new(label: impl Into<String>) -> Self {
+        Self {
+            label: label.into(),
+            highlight: None,
+            location: None,
+        }
+    }
+
+    /// An item labelled with the path of a file, previewed from its start.
+    pub fn file(path: PathBuf) -> Self {
+        Self {
+            label: path.display().to_string(),
+            highlight: None,
+            location: Some(Location {
+                path,
+                line: 0,
+                column: 0,
+            }),
+        }
+    }
+}
+
+/// A list of items filtered with a fuzzy query, drawn over the editor. The
+/// callbacks get the index of the highlighted item and the item.
+pub struct Picker {
+    title: String,
+    query: String,
+    items: Vec<Item>,
+    /// Sends more items, e.g. while walking a directory.
+    source: Option<Receiver<Item>>,
+    /// The items matching the query and how, best first.
+    matches: Vec<(usize, Match)>,
+    /// The highlighted match.
+    selected: usize,
+    scroll: usize,
+    /// Whether the locations of the items are shown next to the list.
+    previews: bool,
+    preview: Option<Preview>,
+    /// Highlights its matches in the preview.
+    search: Option<Search>,
+    #[allow(clippy::type_complexity)]
+    submit: Box<dyn FnOnce(usize, &Item, &mut App)>,
+    #[allow(clippy::type_complexity)]
+    change: Option<Box<dyn Fn(usize, &Item, &mut App)>>,
+    #[allow(clippy::type_complexity)]
+    cancel: Option<Box<dyn FnOnce(&mut App)>>,
+}
+
+impl Picker {
+    pub fn new(
+        title: impl Into<String>,
+        items: Vec<Item>,
+        submit: impl FnOnce(usize, &Item, &mut App) + 'static,
+    ) -> Self {
+        let mut picker = Self {
+            title: title.into(),
+            query: String::new(),
+            items,
+            source: None,
+            matches: Vec::new(),
+            selected: 0,
+            scroll: 0,
+            previews: false,
+            preview: None,
+            search: None,
+            submit: Box::new(submit),
+            change: None,
+            cancel: None,
+        };
+
+        picker.filter();
+        picker
+    }
+
+    /// Adds the items sent by `source` as they come in.
+    pub fn with_source(mut self, source: Receiver<Item>) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// Shows the file of the highlighted item next to the list.
+    pub fn with_previews(mut self) -> Self {
+        self.previews = true;
+        self
+    }
+
+    /// Highlights the matches of `search` in the preview.
+    pub fn with_search(mut self, search: Search) -> Self {
+        self.search = Some(search);
+        self
+    }
+
+    /// Called when another item is highlighted, e.g. to preview it.
+    pub fn with_on_change(mut self, change: impl Fn(usize, &Item, &mut App) + 'static) -> Self {
+        self.change = Some(Box::new(change));
+        self
+    }
+
+    pub fn with_on_cancel(mut self, cancel: impl FnOnce(&mut App) + 'static) -> Self {
+        self.cancel = Some(Box::new(cancel));
+        self
+    }
+
+    /// Highlights the item at `idx`, if it matches.
+    pub fn with_selected(mut self, idx: usize) -> Self {
+        self.selected = self
+            .matches
+            .iter()
+            .position(|(item, _)| *item == idx)
+            .unwrap_or_default();
+        self
+    }
+
+    pub fn insert(&mut self, ch: char) {
+        self.query.push(ch);
+
+        // A longer query only matches items the shorter one did.
+        let items = &self.items;
+        let matches = self
+            .matches
+            .iter()
+            .filter_map(|&(idx, _)| Some((idx, fuzzy::score(&self.query, &items[idx].label)?)));
+        self.matches = matches.collect();
+        self.matches
+            .sort_by_key(|(idx, mat)| (std::cmp::Reverse(mat.score), *idx));
+        self.selected = 0;
+        self.scroll = 0;
+    }
+
+    pub fn remove(&mut self) {
+        self.query.pop();
+        self.filter();
+    }
+
+    /// Highlights the item `by` rows down, or up if negative, wrapping around.
+    pub fn move_by(&mut self, by: isize) {
+        if !self.matches.is_empty() {
+            let len = self.matches.len() as isize;
+            self.selected = (self.selected as isize + by).rem_euclid(len) as usize;
+        }
+    }
+
+    /// The index of the highlighted item.
+    pub fn selected(&self) -> Option<usize> {
+        self.matches.get(self.selected).map(|(idx, _)| *idx)
+    }
+
+    pub fn submit(self, app: &mut App) {
+        if let Some(idx) = self.selected() {
+            (self.submit)(idx, &self.items[idx], app)
+        }
+    }
+
+    pub fn change(&self, app: &mut App) {
+        if let Some(change) = &self.change
+            && let Some(idx) = self.selected()
+        {
+            change(idx, &self.items[idx], app)
+        }
+    }
+
+    pub fn cancel(self, app: &mut App) {
+        if let Some(cancel) = self.cancel {
+            cancel(app)
+        }
+    }
+
+    /// Whether the source may still send items.
+    pub fn is_loading(&self) -> bool {
+        self.source.is_some()
+    }
+
+    /// Adds the items sent since the last call, keeping the highlighted one.
+    pub fn receive(&mut self) {
+        let Some(source) = &self.source else {
+            return;
+        };
+
+        let start = self.items.len();
+
+        loop {
+            match source.try_recv() {
+                Ok(item) => self.items.push(item),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.source = None;
+                    break;
+                }
+            }
+        }
+
+        if self.items.len() == start {
+            return;
+        }
+
+        let selected = self.selected();
+        let new = (start..self.items.len())
+            .filter_map(|idx| Some((idx, fuzzy::score(&self.query, &self.items[idx].label)?)));
+        self.matches.extend(new);
+        self.matches
+            .sort_by_key(|(idx, mat)| (std::cmp::Reverse(mat.score), *idx));
+
+        if let Some(selected) = selected {
+            self.selected = self
+                .matches
+                .iter()
+                .position(|(idx, _)| *idx == selected)
+                .unwrap_or_default();
+        }
+    }
+
+    /// Takes in the highlights of the preview.
+    pub fn receive_highlights(&mut self, response: Response) {
+        if let Some(Preview {
+            content: Ok((doc, _)),
+            ..
+        }) = &mut self.preview
+        {
+            doc.receive_highlights(response);
+        }
+    }
+
+    fn filter(&mut self) {
+        self.matches = fuzzy::filter(
+            &self.query,
+            self.items.iter().map(|item| item.label.as_str()),
+        );
+        self.selected = 0;
+        self.scroll = 0;
+    }
+
+    /// Draws the picker over `area`, returning where the cursor goes.
+    pub fn render(
+        &mut self,
+        theme: &Theme,
+        languages: &Languages,
+        highlighter: &mut Highlighter,
+        area: Rect,
+        buf: &mut Buffer,
+    ) -> Option<Position> {
+        self.receive();
+
+        let area = if self.previews {
+            centered(area)
+        } else {
+            bottom(area)
+        };
+        Clear.render(area, buf);
+
+        let block = Block::new()
+            .borders(Borders::ALL)
+            .title(format!(" {} ", self.title))
+            .style(theme.editor)
+            .border_style(theme.editor.patch(theme.line_numbers));
+        let inner = block.inner(area);
+        block.render(area, buf);
+
+        let (inner, preview) = if self.previews {
+            let [list, preview] =
+                Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                    .areas(inner);
+            (list, Some(preview))
+        } else {
+            (inner, None)
+        };
+
+        let [prompt, list] =
+            Layout::vertical([Constraint::Length(1), Constraint::Min(0)]).areas(inner);
+
+        let loading = if self.is_loading() { "…" } else { "" };
+        let count = format!("{}/{}{loading}", self.matches.len(), self.items.len());
+        Paragraph::new(Line::from_iter([
+            Span::raw("> "),
+            Span::raw(self.query.as_str()),
+        ]))
+        .render(prompt, buf);
+        Paragraph::new(count)
+            .style(theme.editor.patch(theme.placeholder))
+            .right_aligned()
+            .render(prompt, buf);
+
+        let height = usize::from(list.height);
+        self.scroll = self
+            .scroll
+            .clamp((self.selected + 1).saturating_sub(height), self.selected);
+
+        for (row, (idx, mat)) in list
+            .rows()
+            .zip(self.matches.iter().enumerate().skip(self.scroll))
+        {
+            let (item, mat) = (&self.items[mat.0], &mat.1);
+            let style = if idx == self.selected {
+                theme.editor.patch(theme.selection)
+            } else {
+                theme.editor
+            };
+
+            let mut indices = mat.indices.iter().peekable();
+            let highlight = item.highlight.clone().unwrap_or_default();
+            let spans = item.label.chars().enumerate().map(|(char_idx, ch)| {
+                if indices.next_if_eq(&&char_idx).is_some() || highlight.contains(&char_idx) {
+                    Span::styled(ch.to_string(), style.patch(theme.search_match))
+                } else {
+                    Span::styled(ch.to_string(), style)
+                }
+            });
+
+            buf.set_style(row, style);
+            Line::from_iter(spans).render(row, buf);
+        }
+
+        if let Some(area) = preview {
+            let block = Block::new()
+                .borders(Borders::LEFT)
+                .border_style(theme.editor.patch(theme.line_numbers));
+            let inner = block.inner(area);
+            block.render(area, buf);
+
+            self.render_preview(theme, languages, highlighter, inner, buf);
+        }
+
+        let x = prompt.x + 2 + self.query.chars().count() as u16;
+        Some(Position::new(
+            x.min(prompt.right().saturating_sub(1)),
+            prompt.y,
+        ))
+    }
+
+    /// Draws the file of the highlighted item, loading it if it changed, with
+    /// its location in the middle.
+    fn render_preview(
+        &mut self,
+        theme: &Theme,
+        languages: &Languages,
+        highlighter: &mut Highlighter,
+        area: Rect,
+        buf: &mut Buffer,
+    ) {
+        let Some(location) = self
+            .selected()
+            .and_then(|idx| self.items[idx].location.as_ref())
+        else {
+            return;
+        };
+
+        let preview = match &mut self.preview {
+            Some(preview) if preview.path == location.path => preview,
+            preview => preview.insert(Preview::load(&location.path, languages)),
+        };
+
+        let (doc, language) = match &mut preview.content {
+            Ok(content) => content,
+            Err(err) => {
+                Paragraph::new(err.as_str())
+                    .style(theme.editor.patch(theme.placeholder))
+                    .render(area, buf);
+                return;
+            }
+        };
+
+        let name = preview.path.display().to_string();
+        let view = &mut preview.view;
+
+        // The view only knows its height once drawn.
+        if preview.location.as_ref() != Some(location) {
+            _ = view.render(
+                doc,
+                PREVIEW_WINDOW,
+                language,
+                highlighter,
+                theme,
+                LineNumbers::Absolute,
+                None,
+                &name,
+                area,
+                buf,
+            );
+            view.move_to_byte(doc, location.byte(doc.rope()));
+            view.center_cursor(doc);
+            preview.location = Some(location.clone());
+        }
+
+        _ = view.render(
+            doc,
+            PREVIEW_WINDOW,
+            language,
+            highlighter,
+            theme,
+            LineNumbers::Absolute,
+            self.search.as_mut(),
+            &name,
+            area,
+            buf,
+        );
+    }
+}
+
+impl Location {
+    /// The byte of the location in `rope`, or the closest one in it.
+    pub fn byte(&self, rope: &Rope) -> usize {
+        let line = self.line.min(rope.len_lines() - 1);
+        let start = rope.line_to_byte(line);
+        let end = rope.line_to_byte((line + 1).min(rope.len_lines()));
+        (start + self.column).min(end)
+    }
+}
+
+/// A file shown next to the items.
+struct Preview {
+    path: PathBuf,
+    /// The location last moved to.
+    location: Option<Location>,
+    /// The file, or why it cannot be shown.
+    content: Result<(Document, Language), String>,
+    view: View,
+}
+
+impl Preview {
+    fn load(path: &Path, languages: &Languages) -> Self {
+        let content = match fs::metadata(path) {
+            Ok(metadata) if metadata.len() > PREVIEW_MAX_BYTES => Err("file too large".into()),
+            Ok(_) => match fs::read(path).map(String::from_utf8) {
+                Ok(Ok(text)) => {
+                    let text = Rope::from(text);
+                    let language = languages.detect(Some(path), &text);
+                    Ok((Document::new(text), language))
+                }
+                Ok(Err(_)) => Err("binary file".into()),
+                Err(err) => Err(err.to_string()),
+            },
+            Err(err) => Err(err.to_string()),
+        };
+
+        Self {
+            path: path.to_path_buf(),
+            location: None,
+            content,
+            view: View::new(PREVIEW_BUFFER),
+        }
+    }
+}
+
+/// The lower part of `area`, leaving the view above it visible for previews.
+fn bottom(area: Rect) -> Rect {
+    let [_, area] = Layout::vertical([Constraint::Min(0), Constraint::Percentage(40)]).areas(area);
+    area
+}
+
+/// Most of `area`, leaving a margin around it.
+fn centered(area: Rect) -> Rect {
+    let [_, area, _] = Layout::vertical([
+        Constraint::Percentage(5),
+        Constraint::Percentage(90),
+        Constraint::Percentage(5),
+    ])
+    .areas(area);
+    let [_, area, _] = Layout::horizontal([
+        Constraint::Percentage(5),
+        Constraint::Percentage(90),
+        Constraint::Percentage(5),
+    ])
+    .areas(area);
+    area
+}
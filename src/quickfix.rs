@@ -0,0 +1,318 @@
+use regex::Regex;
+use ropey::Rope;
+use serde::Deserialize;
+use std::{
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{
+        LazyLock,
+        mpsc::{self, Receiver},
+    },
+    thread,
+};
+
+use crate::{
+    buffer,
+    diagnostic::{Diagnostic, Severity},
+};
+
+/// `file:line:column: message`, optionally with a severity before the
+/// message, e.g. `src/main.c:3:5: error: expected ';'`.
+static LOCATION: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(concat!(
+        r"^([^\s:][^:]*):(\d+):(\d+):\s*",
+        r"(?:(fatal error|error|warning|note|info|help)\S*:\s*)?(.*)$",
+    ))
+    .unwrap()
+});
+
+/// A location reported by a build command, e.g. a compiler error. Lines and
+/// columns count characters from zero.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// The entries of the last build and the one jumped to.
+#[derive(Default)]
+pub struct Quickfix {
+    entries: Vec<Entry>,
+    current: Option<usize>,
+}
+
+/// A line of `cargo --message-format=json` or `rustc --error-format=json`
+/// output.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonLine {
+    Cargo {
+        reason: String,
+        message: Option<RustcMessage>,
+    },
+    Rustc(RustcMessage),
+}
+
+#[derive(Deserialize)]
+struct RustcMessage {
+    message: String,
+    level: String,
+    spans: Vec<RustcSpan>,
+}
+
+#[derive(Deserialize)]
+struct RustcSpan {
+    file_name: String,
+    line_start: usize,
+    column_start: usize,
+    line_end: usize,
+    column_end: usize,
+    is_primary: bool,
+}
+
+impl Entry {
+    /// The byte the entry starts at in `rope`, the text of its file.
+    pub fn start(&self, rope: &Rope) -> usize {
+        byte(rope, self.line, self.column)
+    }
+}
+
+impl Quickfix {
+    pub fn new(entries: Vec<Entry>) -> Self {
+        Self {
+            entries,
+            current: None,
+        }
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Moves to the next entry, or the previous one if `next` is false,
+    /// stopping at the ends. The first move goes to the first entry.
+    pub fn step(&mut self, next: bool) -> Option<(usize, &Entry)> {
+        let last = self.entries.len().checked_sub(1)?;
+
+        let idx = match self.current {
+            None => 0,
+            Some(idx) if next => (idx + 1).min(last),
+            Some(idx) => idx.saturating_sub(1),
+        };
+
+        self.current = Some(idx);
+        Some((idx, &self.entries[idx]))
+    }
+
+    /// The diagnostics of the entries for the file at `path`, whose text is
+    /// `rope`.
+    pub fn diagnostics(&self, path: &Path, rope: &Rope) -> Vec<Diagnostic> {
+        self.entries
+            .iter()
+            .filter(|entry| buffer::same_file(&entry.path, path))
+            .map(|entry| {
+                let start = entry.start(rope);
+                let end = byte(rope, entry.end_line, entry.end_column).max(start);
+
+                Diagnostic {
+                    source: "make",
+                    range: start..end,
+                    severity: entry.severity,
+                    message: entry.message.clone(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// The byte at a character of a line, or the closest one in `rope`.
+fn byte(rope: &Rope, line: usize, column: usize) -> usize {
+    if line >= rope.len_lines() {
+        return rope.len_bytes();
+    }
+
+    let start = rope.line_to_char(line);
+    let len = rope.line(line).len_chars();
+    rope.char_to_byte(start + column.min(len))
+}
+
+/// Runs `command` with the shell in the background, sending the entries
+/// parsed from its output once it exits, or why it could not run.
+pub fn run(command: &str) -> Receiver<Result<Vec<Entry>, String>> {
+    let (sender, receiver) = mpsc::channel();
+    let command = command.to_owned();
+
+    thread::spawn(move || {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|err| format!("{command}: {err}"))
+            .map(|output| {
+                let mut entries = parse(&String::from_utf8_lossy(&output.stdout));
+                entries.extend(parse(&String::from_utf8_lossy(&output.stderr)));
+                entries
+            });
+
+        _ = sender.send(output);
+    });
+
+    receiver
+}
+
+/// Parses rustc's JSON diagnostics, as printed by cargo or rustc, and lines
+/// starting with `file:line:column:`. Other lines are skipped.
+pub fn parse(output: &str) -> Vec<Entry> {
+    let mut entries = Vec::new();
+
+    for line in output.lines() {
+        if line.starts_with('{') {
+            let message = match serde_json::from_str(line) {
+                Ok(JsonLine::Cargo { reason, message }) if reason == "compiler-message" => message,
+                Ok(JsonLine::Rustc(message)) => Some(message),
+                _ => None,
+            };
+
+            entries.extend(message.and_then(rustc_entry));
+        } else if let Some(caps) = LOCATION.captures(line) {
+            let number = |idx: usize| caps[idx].parse::<usize>().unwrap_or(1).saturating_sub(1);
+            let (line, column) = (number(2), number(3));
+
+            entries.push(Entry {
+                path: PathBuf::from(&caps[1]),
+                line,
+                column,
+                end_line: line,
+                end_column: column,
+                severity: severity(caps.get(4).map_or("error", |level| level.as_str())),
+                message: caps[5].to_owned(),
+            });
+        }
+    }
+
+    entries
+}
+
+fn rustc_entry(message: RustcMessage) -> Option<Entry> {
+    let span = message.spans.into_iter().find(|span| span.is_primary)?;
+
+    Some(Entry {
+        path: PathBuf::from(span.file_name),
+        line: span.line_start.saturating_sub(1),
+        column: span.column_start.saturating_sub(1),
+        end_line: span.line_end.saturating_sub(1),
+        end_column: span.column_end.saturating_sub(1),
+        severity: severity(&message.level),
+        message: message.message,
+    })
+}
+
+fn severity(level: &str) -> Severity {
+    match level {
+        "error" | "fatal error" | "error: internal compiler error" => Severity::Error,
+        "warning" => Severity::Warning,
+        "help" => Severity::Hint,
+        _ => Severity::Info,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(
+        path: &str,
+        (line, column): (usize, usize),
+        severity: Severity,
+        message: &str,
+    ) -> Entry {
+        Entry {
+            path: PathBuf::from(path),
+            line,
+            column,
+            end_line: line,
+            end_column: column,
+            severity,
+            message: message.to_owned(),
+        }
+    }
+
+    #[test]
+    fn locations() {
+        let output = "\
+src/main.c:3:5: error: expected ';'
+src/main.c: In function 'main':
+lib/util.c:10:1: warning: unused variable 'x' [-Wunused-variable]
+src/lib.rs:7:12: error[E0308]: mismatched types
+./script.py:1:1: no severity
+  --> src/lib.rs:7:12
+make: *** [Makefile:4: all] Error 1
+";
+
+        assert_eq!(
+            parse(output),
+            [
+                entry("src/main.c", (2, 4), Severity::Error, "expected ';'"),
+                entry(
+                    "lib/util.c",
+                    (9, 0),
+                    Severity::Warning,
+                    "unused variable 'x' [-Wunused-variable]"
+                ),
+                entry("src/lib.rs", (6, 11), Severity::Error, "mismatched types"),
+                entry("./script.py", (0, 0), Severity::Error, "no severity"),
+            ]
+        );
+    }
+
+    #[test]
+    fn rustc_json() {
+        let span = |file: &str, primary| {
+            format!(
+                r#"{{"file_name":"{file}","line_start":4,"column_start":9,"line_end":5,"column_end":2,"is_primary":{primary}}}"#
+            )
+        };
+        let message = |level: &str, spans: &[String]| {
+            format!(
+                r#"{{"message":"{level} here","level":"{level}","spans":[{}],"children":[]}}"#,
+                spans.join(",")
+            )
+        };
+
+        let output = [
+            format!(
+                r#"{{"reason":"compiler-message","package_id":"ion","message":{}}}"#,
+                message(
+                    "warning",
+                    &[span("src/a.rs", false), span("src/b.rs", true)]
+                )
+            ),
+            r#"{"reason":"compiler-artifact","package_id":"ion"}"#.to_owned(),
+            message("error", &[span("src/c.rs", true)]),
+            message("help", &[span("src/d.rs", false)]),
+            r#"{"reason":"build-finished","success":false}"#.to_owned(),
+            "{not json".to_owned(),
+        ]
+        .join("\n");
+
+        let multiline = |path, severity, message| Entry {
+            end_line: 4,
+            end_column: 1,
+            ..entry(path, (3, 8), severity, message)
+        };
+
+        assert_eq!(
+            parse(&output),
+            [
+                multiline("src/b.rs", Severity::Warning, "warning here"),
+                multiline("src/c.rs", Severity::Error, "error here"),
+            ]
+        );
+    }
+}
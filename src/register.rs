@@ -0,0 +1,234 @@
+use base64::{Engine, engine::general_purpose::STANDARD};
+use std::{
+    collections::HashMap,
+    env,
+    io::{self, Write, stdout},
+    path::Path,
+    process::{self, Stdio},
+};
+
+pub struct Registers {
+    unnamed: String,
+    named: HashMap<char, String>,
+    selected: Option<char>,
+    clipboard_fallback: String,
+    pub clipboard: Clipboard,
+}
+
+impl Registers {
+    pub fn new(clipboard: Clipboard) -> Self {
+        Self {
+            unnamed: String::new(),
+            named: HashMap::new(),
+            selected: None,
+            clipboard_fallback: String::new(),
+            clipboard,
+        }
+    }
+
+    /// Selects the register used by the next yank or paste. Returns `false`
+    /// if `name` is not a register.
+    pub fn select(&mut self, name: char) -> bool {
+        let valid = matches!(name, '"' | '+' | '*' | 'a'..='z' | 'A'..='Z');
+
+        if valid {
+            self.selected = Some(name);
+        }
+
+        valid
+    }
+
+    /// Stores `text` in the selected register and the unnamed one. The text
+    /// is kept even if copying it to the clipboard fails.
+    pub fn yank(&mut self, text: String) -> io::Result<()> {
+        match self.selected.take() {
+            None | Some('"') => self.unnamed = text,
+            Some('+' | '*') => {
+                self.clipboard_fallback.clone_from(&text);
+                self.unnamed = text;
+                self.clipboard.copy(&self.unnamed)?;
+            }
+            Some(name @ 'A'..='Z') => {
+                let register = self.named.entry(name.to_ascii_lowercase()).or_default();
+                register.push_str(&text);
+                self.unnamed.clone_from(register);
+            }
+            Some(name) => {
+                self.named.insert(name, text.clone());
+                self.unnamed = text;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn paste(&mut self) -> io::Result<String> {
+        match self.selected.take() {
+            None | Some('"') => Ok(self.unnamed.clone()),
+            Some('+' | '*') => Ok(self
+                .clipboard
+                .paste()?
+                .unwrap_or_else(|| self.clipboard_fallback.clone())),
+            Some(name) => Ok(self
+                .named
+                .get(&name.to_ascii_lowercase())
+                .cloned()
+                .unwrap_or_default()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Clipboard {
+    /// Copies through the terminal with an OSC 52 escape sequence. Pasting
+    /// returns the last text copied from ion.
+    Osc52,
+    External {
+        copy: Vec<String>,
+        paste: Vec<String>,
+    },
+}
+
+impl Clipboard {
+    pub fn detect() -> Self {
+        let external = |copy: &[&str], paste: &[&str]| Self::External {
+            copy: copy.iter().map(|&s| s.to_owned()).collect(),
+            paste: paste.iter().map(|&s| s.to_owned()).collect(),
+        };
+
+        if env::var_os("WAYLAND_DISPLAY").is_some() && in_path("wl-copy") {
+            external(&["wl-copy"], &["wl-paste", "--no-newline"])
+        } else if env::var_os("DISPLAY").is_some() && in_path("xclip") {
+            external(
+                &["xclip", "-selection", "clipboard"],
+                &["xclip", "-selection", "clipboard", "-o"],
+            )
+        } else if in_path("pbcopy") {
+            external(&["pbcopy"], &["pbpaste"])
+        } else {
+            Self::Osc52
+        }
+    }
+
+    fn copy(&self, text: &str) -> io::Result<()> {
+        match self {
+            Self::Osc52 => {
+                let mut stdout = stdout();
+                write!(stdout, "\x1b]52;c;{}\x07", STANDARD.encode(text))?;
+                stdout.flush()
+            }
+            Self::External { copy, .. } => {
+                let mut child = command(copy)?
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .spawn()?;
+
+                child
+                    .stdin
+                    .take()
+                    .expect("stdin is piped")
+                    .write_all(text.as_bytes())?;
+
+                check_status(copy, child.wait()?)
+            }
+        }
+    }
+
+    fn paste(&self) -> io::Result<Option<String>> {
+        match self {
+            Self::Osc52 => Ok(None),
+            Self::External { paste, .. } => {
+                let output = command(paste)?
+                    .stdin(Stdio::null())
+                    .stderr(Stdio::null())
+                    .output()?;
+
+                check_status(paste, output.status)?;
+
+                String::from_utf8(output.stdout)
+                    .map(Some)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+            }
+        }
+    }
+}
+
+fn command(args: &[String]) -> io::Result<process::Command> {
+    let [program, args @ ..] = args else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "empty clipboard command",
+        ));
+    };
+
+    let mut command = process::Command::new(program);
+    command.args(args);
+    Ok(command)
+}
+
+fn check_status(args: &[String], status: process::ExitStatus) -> io::Result<()> {
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("`{}` {status}", args.join(" "))))
+    }
+}
+
+fn in_path(program: &str) -> bool {
+    env::var_os("PATH").is_some_and(|paths| {
+        env::split_paths(&paths).any(|dir| Path::new(&dir).join(program).is_file())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn external_clipboard() {
+        let dir = env::temp_dir().join(format!("ion-clipboard-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("clipboard.sh");
+        let contents = dir.join("contents");
+        fs::write(
+            &script,
+            format!(
+                "case $1 in\ncopy) cat > '{0}' ;;\npaste) cat '{0}' ;;\nesac\n",
+                contents.display(),
+            ),
+        )
+        .unwrap();
+
+        let script = script.to_str().unwrap().to_owned();
+        let mut registers = Registers::new(Clipboard::External {
+            copy: vec!["sh".into(), script.clone(), "copy".into()],
+            paste: vec!["sh".into(), script, "paste".into()],
+        });
+
+        registers.select('+');
+        registers.yank("fn main() {}\n".into()).unwrap();
+        assert_eq!(fs::read_to_string(&contents).unwrap(), "fn main() {}\n");
+
+        // Pasting goes through the script rather than the last yank.
+        fs::write(&contents, "copied elsewhere").unwrap();
+        registers.select('+');
+        assert_eq!(registers.paste().unwrap(), "copied elsewhere");
+        assert_eq!(registers.paste().unwrap(), "fn main() {}\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_copy_keeps_the_text() {
+        let mut registers = Registers::new(Clipboard::External {
+            copy: vec!["sh".into(), "-c".into(), "exit 1".into()],
+            paste: vec!["sh".into(), "-c".into(), "exit 1".into()],
+        });
+
+        registers.select('+');
+        assert!(registers.yank("kept".into()).is_err());
+        assert_eq!(registers.paste().unwrap(), "kept");
+    }
+}
@@ -0,0 +1,293 @@
+use regex::RegexBuilder;
+use regex_automata::{
+    Anchored, MatchError,
+    hybrid::{
+        dfa::{self, DFA},
+        regex::{Cache, Regex},
+    },
+    util::{start, syntax},
+};
+use ropey::Rope;
+use std::{error::Error, ops::Range};
+
+pub struct Search {
+    pattern: String,
+    regex: Regex,
+    cache: Cache,
+    /// Used where the DFA gives up, e.g. on a Unicode word boundary next to
+    /// non-ASCII text or when its cache keeps filling up.
+    fallback: regex::Regex,
+    pub backward: bool,
+}
+
+impl Search {
+    /// Compiles `pattern`. The search is case insensitive unless the pattern
+    /// contains an uppercase letter.
+    pub fn new(pattern: &str, backward: bool) -> Result<Self, Box<dyn Error>> {
+        let case_insensitive = !pattern.chars().any(char::is_uppercase);
+
+        let fallback = RegexBuilder::new(pattern)
+            .multi_line(true)
+            .case_insensitive(case_insensitive)
+            .build()?;
+
+        let regex = Regex::builder()
+            .syntax(
+                syntax::Config::new()
+                    .multi_line(true)
+                    .case_insensitive(case_insensitive),
+            )
+            .dfa(DFA::config().unicode_word_boundary(true))
+            .build(pattern)?;
+
+        Ok(Self {
+            pattern: pattern.to_owned(),
+            cache: regex.create_cache(),
+            regex,
+            fallback,
+            backward,
+        })
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Finds the leftmost match starting in `range`. The text past
+    /// `range.end` is only used for look-around assertions.
+    pub fn find(&mut self, rope: &Rope, range: Range<usize>) -> Option<Range<usize>> {
+        let (forward, reverse) = self.cache.as_parts_mut();
+
+        let end = match find_end(self.regex.forward(), forward, rope, range.clone()) {
+            Ok(end) => end?,
+            Err(_) => return self.find_fallback(rope, range),
+        };
+
+        match find_start(self.regex.reverse(), reverse, rope, range.start, end) {
+            Ok(start) => Some(start?..end),
+            Err(_) => self.find_fallback(rope, range),
+        }
+    }
+
+    /// Like [`Search::find`], searching a copy of the lines of `range`.
+    fn find_fallback(&self, rope: &Rope, range: Range<usize>) -> Option<Range<usize>> {
+        let len = rope.len_bytes();
+        let start = rope.line_to_byte(rope.byte_to_line(range.start.min(len)));
+        let end =
+            rope.line_to_byte((rope.byte_to_line(range.end.min(len)) + 1).min(rope.len_lines()));
+        let text = rope.byte_slice(start..end).to_string();
+
+        let mat = self.fallback.find_at(&text, range.start.min(len) - start)?;
+        (start + mat.start() <= range.end).then_some(start + mat.start()..start + mat.end())
+    }
+
+    /// Finds every match starting in `range`.
+    pub fn find_all(&mut self, rope: &Rope, range: Range<usize>) -> Vec<Range<usize>> {
+        let mut matches = Vec::new();
+        let mut from = range.start;
+
+        while from <= range.end
+            && let Some(mat) = self.find(rope, from..range.end)
+        {
+            from = next_start(rope, &mat);
+            matches.push(mat);
+        }
+
+        matches
+    }
+
+    /// Finds the first match after `byte`, wrapping around the end of the rope.
+    pub fn next(&mut self, rope: &Rope, byte: usize) -> Option<Range<usize>> {
+        let from = next_char_boundary(rope, byte);
+
+        self.find(rope, from..rope.len_bytes())
+            .or_else(|| self.find(rope, 0..rope.len_bytes()))
+    }
+
+    /// Finds the last match before `byte`, wrapping around the start of the
+    /// rope.
+    pub fn prev(&mut self, rope: &Rope, byte: usize) -> Option<Range<usize>> {
+        self.rfind(rope, byte)
+            .or_else(|| self.rfind(rope, rope.len_bytes() + 1))
+    }
+
+    /// Finds the last match starting in a chunk of lines before `before`,
+    /// doubling the lines searched until one does. Matches may span lines, but
+    /// a miss does not scan the rest of the rope for every line.
+    fn rfind(&mut self, rope: &Rope, before: usize) -> Option<Range<usize>> {
+        let mut line_idx = rope.byte_to_line(before.min(rope.len_bytes()));
+        let mut lines = 1;
+        let mut limit = before;
+
+        loop {
+            let chunk_start = rope.line_to_byte(line_idx);
+            let mut from = chunk_start;
+            let mut last = None;
+
+            while from < limit
+                && let Some(mat) = self.find(rope, from..rope.len_bytes())
+                && mat.start < limit
+            {
+                from = next_start(rope, &mat);
+                last = Some(mat);
+            }
+
+            if last.is_some() || line_idx == 0 {
+                return last;
+            }
+
+            line_idx = line_idx.saturating_sub(lines);
+            lines *= 2;
+            limit = chunk_start;
+        }
+    }
+}
+
+fn next_char_boundary(rope: &Rope, byte: usize) -> usize {
+    let char_idx = rope.byte_to_char(byte.min(rope.len_bytes()));
+    rope.char_to_byte((char_idx + 1).min(rope.len_chars()))
+}
+
+fn next_start(rope: &Rope, mat: &Range<usize>) -> usize {
+    if mat.is_empty() {
+        if mat.end == rope.len_bytes() {
+            mat.end + 1
+        } else {
+            next_char_boundary(rope, mat.end)
+        }
+    } else {
+        mat.end
+    }
+}
+
+/// Runs the forward DFA over the rope's bytes and returns the end of the
+/// leftmost match, or an error if the DFA gave up.
+fn find_end(
+    dfa: &DFA,
+    cache: &mut dfa::Cache,
+    rope: &Rope,
+    range: Range<usize>,
+) -> Result<Option<usize>, MatchError> {
+    let look_behind = range.start.checked_sub(1).map(|byte| rope.byte(byte));
+    let config = start::Config::new()
+        .anchored(Anchored::No)
+        .look_behind(look_behind);
+
+    let mut sid =
+        (dfa.start_state(cache, &config)).map_err(|_| MatchError::gave_up(range.start))?;
+    let mut end = None;
+
+    for (at, byte) in (range.start..range.end).zip(rope.bytes_at(range.start)) {
+        sid = (dfa.next_state(cache, sid, byte)).map_err(|_| MatchError::gave_up(at))?;
+
+        if sid.is_match() {
+            end = Some(at);
+        } else if sid.is_dead() {
+            return Ok(end);
+        } else if sid.is_quit() {
+            return Err(MatchError::quit(byte, at));
+        }
+    }
+
+    sid = match rope.get_byte(range.end) {
+        Some(byte) => dfa.next_state(cache, sid, byte),
+        None => dfa.next_eoi_state(cache, sid),
+    }
+    .map_err(|_| MatchError::gave_up(range.end))?;
+
+    if sid.is_match() {
+        end = Some(range.end);
+    } else if sid.is_quit() {
+        return Err(MatchError::gave_up(range.end));
+    }
+
+    Ok(end)
+}
+
+/// Runs the reverse DFA backwards from `end` and returns the start of the
+/// match, which is never before `min_start`, or an error if the DFA gave up.
+fn find_start(
+    dfa: &DFA,
+    cache: &mut dfa::Cache,
+    rope: &Rope,
+    min_start: usize,
+    end: usize,
+) -> Result<Option<usize>, MatchError> {
+    let config = start::Config::new()
+        .anchored(Anchored::Yes)
+        .look_behind(rope.get_byte(end));
+
+    let mut sid = (dfa.start_state(cache, &config)).map_err(|_| MatchError::gave_up(end))?;
+    let mut start = None;
+    let mut bytes = rope.bytes_at(end);
+
+    for at in (min_start..end).rev() {
+        let Some(byte) = bytes.prev() else {
+            break;
+        };
+        sid = (dfa.next_state(cache, sid, byte)).map_err(|_| MatchError::gave_up(at))?;
+
+        if sid.is_match() {
+            start = Some(at + 1);
+        } else if sid.is_dead() {
+            return Ok(start);
+        } else if sid.is_quit() {
+            return Err(MatchError::quit(byte, at));
+        }
+    }
+
+    sid = match min_start.checked_sub(1) {
+        Some(byte) => dfa.next_state(cache, sid, rope.byte(byte)),
+        None => dfa.next_eoi_state(cache, sid),
+    }
+    .map_err(|_| MatchError::gave_up(min_start))?;
+
+    if sid.is_match() {
+        start = Some(min_start);
+    } else if sid.is_quit() {
+        return Err(MatchError::gave_up(min_start));
+    }
+
+    Ok(start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unicode_word_boundary() {
+        // The DFA quits on the non-ASCII bytes next to `\b`.
+        let rope = Rope::from_str("let café = über;\nlet x = café;\n");
+        let mut search = Search::new(r"\bcafé\b", false).unwrap();
+
+        assert_eq!(search.find_all(&rope, 0..rope.len_bytes()), [4..9, 27..32]);
+        assert_eq!(search.next(&rope, 4), Some(27..32));
+        assert_eq!(search.prev(&rope, 27), Some(4..9));
+
+        let mut search = Search::new(r"\büber", false).unwrap();
+        assert_eq!(search.next(&rope, 0), Some(12..17));
+    }
+
+    #[test]
+    fn across_lines() {
+        let rope = Rope::from_str("one\ntwo\none\ntwo\n");
+        let mut search = Search::new(r"one\ntwo", false).unwrap();
+
+        assert_eq!(search.next(&rope, 0), Some(8..15));
+        assert_eq!(search.next(&rope, 8), Some(0..7));
+        assert_eq!(search.prev(&rope, 8), Some(0..7));
+        assert_eq!(search.prev(&rope, 0), Some(8..15));
+        assert_eq!(search.prev(&rope, 14), Some(8..15));
+    }
+
+    #[test]
+    fn backward_search_widens() {
+        let text = format!("match\n{}", "no\n".repeat(100));
+        let rope = Rope::from_str(&text);
+        let mut search = Search::new("match", true).unwrap();
+
+        assert_eq!(search.prev(&rope, rope.len_bytes()), Some(0..5));
+        assert_eq!(search.prev(&rope, 0), Some(0..5));
+    }
+}
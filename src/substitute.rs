@@ -0,0 +1,223 @@
+use ratatui::{
+    buffer::Buffer,
+    layout::Rect,
+    text::{Line, Span, Text},
+    widgets::{Paragraph, Widget},
+};
+use regex::{Regex, RegexBuilder};
+use ropey::Rope;
+use std::{borrow::Cow, ops::Range};
+
+use crate::theme::Theme;
+
+pub struct Substitution {
+    regex: Regex,
+    replacement: String,
+    global: bool,
+}
+
+impl Substitution {
+    /// Parses `/pattern/replacement/flags`, where any punctuation character can
+    /// be used instead of `/`. An empty pattern reuses `last_pattern`.
+    pub fn parse(args: &str, last_pattern: Option<&str>) -> Result<Self, String> {
+        let mut chars = args.chars();
+        let Some(delimiter) = chars
+            .next()
+            .filter(|ch| ch.is_ascii_punctuation() && *ch != '\\')
+        else {
+            return Err("usage: s/pattern/replacement/[giI]".into());
+        };
+
+        let (pattern, rest) = split_unescaped(chars.as_str(), delimiter);
+        let (replacement, flags) = split_unescaped(rest.unwrap_or_default(), delimiter);
+        let flags = flags.unwrap_or_default();
+
+        let pattern = match pattern.as_str() {
+            "" => last_pattern.ok_or("no previous search pattern")?.to_owned(),
+            _ => pattern,
+        };
+
+        let mut case_insensitive = !pattern.chars().any(char::is_uppercase);
+        let mut global = false;
+
+        for flag in flags.chars() {
+            match flag {
+                'g' => global = true,
+                'i' => case_insensitive = true,
+                'I' => case_insensitive = false,
+                _ => return Err(format!("invalid flag: {flag}")),
+            }
+        }
+
+        let regex = RegexBuilder::new(&pattern)
+            .multi_line(true)
+            .case_insensitive(case_insensitive)
+            .build()
+            .map_err(|err| err.to_string())?;
+
+        Ok(Self {
+            regex,
+            replacement: expand_replacement(&replacement),
+            global,
+        })
+    }
+
+    pub fn preview(&self, rope: &Rope, lines: Range<usize>) -> Preview {
+        let mut preview = Preview {
+            edits: Vec::new(),
+            lines: Vec::new(),
+            count: 0,
+        };
+
+        for line_idx in lines {
+            let text = Cow::from(rope.line(line_idx));
+            let old = text.trim_end_matches(['\n', '\r']);
+
+            let (count, new) = if self.global {
+                let count = self.regex.find_iter(old).count();
+                (count, self.regex.replace_all(old, &self.replacement))
+            } else {
+                let count = usize::from(self.regex.is_match(old));
+                (count, self.regex.replace(old, &self.replacement))
+            };
+
+            if count == 0 {
+                continue;
+            }
+
+            let start = rope.line_to_byte(line_idx);
+            preview
+                .edits
+                .push((start..start + old.len(), new.to_string()));
+            preview
+                .lines
+                .push((line_idx, old.to_owned(), new.into_owned()));
+            preview.count += count;
+        }
+
+        preview
+    }
+}
+
+pub struct Preview {
+    pub edits: Vec<(Range<usize>, String)>,
+    lines: Vec<(usize, String, String)>,
+    count: usize,
+}
+
+impl Preview {
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+
+    pub fn render(&self, theme: &Theme, area: Rect, buf: &mut Buffer) {
+        let minus = theme.editor.patch(theme.get_token_style("diff.minus"));
+        let plus = theme.editor.patch(theme.get_token_style("diff.plus"));
+        let numbers = theme.editor.patch(theme.line_numbers);
+
+        let header = format!(
+            " {} substitutions on {} lines, apply? (y/n)",
+            self.count,
+            self.lines.len()
+        );
+
+        let mut text = Text::from(Line::styled(header, theme.status_bar));
+
+        for (line_idx, old, new) in &self.lines {
+            let number = format!("{:>6}  ", line_idx + 1);
+
+            text.push_line(Line::from_iter([
+                Span::styled(number.clone(), numbers),
+                Span::styled(format!("- {old}"), minus),
+            ]));
+            text.push_line(Line::from_iter([
+                Span::styled(number, numbers),
+                Span::styled(format!("+ {new}"), plus),
+            ]));
+        }
+
+        buf.set_style(area, theme.editor);
+        Paragraph::new(text).render(area, buf);
+    }
+}
+
+/// Splits `s` at the first `delimiter` not preceded by a backslash. Escaped
+/// delimiters lose their backslash; other escapes are kept for the regex.
+fn split_unescaped(s: &str, delimiter: char) -> (String, Option<&str>) {
+    let mut part = String::new();
+    let mut chars = s.char_indices();
+
+    while let Some((idx, ch)) = chars.next() {
+        match ch {
+            '\\' => match chars.next() {
+                Some((_, ch)) if ch == delimiter => part.push(ch),
+                Some((_, ch)) => {
+                    part.push('\\');
+                    part.push(ch);
+                }
+                None => part.push('\\'),
+            },
+            ch if ch == delimiter => return (part, Some(&s[idx + ch.len_utf8()..])),
+            ch => part.push(ch),
+        }
+    }
+
+    (part, None)
+}
+
+/// Converts vim style `\1` group references and `\n` escapes to the syntax
+/// used by [`Regex::replace`]. A `$` is inserted as is.
+fn expand_replacement(replacement: &str) -> String {
+    let mut expanded = String::new();
+    let mut chars = replacement.chars();
+
+    while let Some(ch) = chars.next() {
+        match (ch, chars.clone().next()) {
+            ('\\', Some(digit @ '0'..='9')) => {
+                chars.next();
+                expanded.push_str(&format!("${{{digit}}}"));
+            }
+            ('\\', Some('n')) => {
+                chars.next();
+                expanded.push('\n');
+            }
+            ('\\', Some('t')) => {
+                chars.next();
+                expanded.push('\t');
+            }
+            ('\\', Some('$')) => {
+                chars.next();
+                expanded.push_str("$$");
+            }
+            ('\\', Some(escaped)) => {
+                chars.next();
+                expanded.push(escaped);
+            }
+            ('$', _) => expanded.push_str("$$"),
+            (ch, _) => expanded.push(ch),
+        }
+    }
+
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replacement() {
+        assert_eq!(expand_replacement(r"\1-\2"), "${1}-${2}");
+        assert_eq!(expand_replacement(r"a\nb\tc"), "a\nb\tc");
+        assert_eq!(expand_replacement(r"\$1"), "$$1");
+        assert_eq!(expand_replacement("$1 ${name} $"), "$$1 $${name} $$");
+        assert_eq!(expand_replacement(r"\/\\"), "/\\");
+
+        let regex = Regex::new(r"(\w+) (\w+)").unwrap();
+        let replacement = expand_replacement(r"\2 $1\n\1");
+        assert_eq!(
+            regex.replace("hello world", &replacement),
+            "world $1\nhello"
+        );
+    }
+}
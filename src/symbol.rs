@@ -0,0 +1,108 @@
+use ropey::Rope;
+use std::ops::Range;
+use tree_sitter::{Node, Query, QueryCursor, Tree};
+
+use crate::document::RopeTextProvider;
+
+/// A definition found by a `tags.scm` query, e.g. a function.
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    /// The keyword introducing it, e.g. `fn` or `impl`.
+    pub kind: String,
+    pub name: String,
+    pub range: Range<usize>,
+    /// Where its name starts, to jump to.
+    pub name_byte: usize,
+    /// The symbols it is defined in, as indices, outermost first.
+    pub parents: Vec<usize>,
+}
+
+impl Symbol {
+    /// The kind and the name, e.g. `fn main`.
+    pub fn label(&self) -> String {
+        format!("{} {}", self.kind, self.name)
+    }
+}
+
+/// The symbols `query` captures as `@definition.<kind>` with their `@name`,
+/// in the order they appear. References are left out.
+pub fn symbols(query: &Query, tree: &Tree, text: &Rope) -> Vec<Symbol> {
+    let names = query.capture_names();
+    let mut cursor = QueryCursor::new();
+    let mut symbols: Vec<Symbol> = Vec::new();
+
+    for mat in cursor.matches(query, tree.root_node(), RopeTextProvider::new(text)) {
+        let capture = |name: &str| {
+            mat.captures
+                .iter()
+                .find(|cap| names[cap.index as usize] == name)
+                .map(|cap| cap.node)
+        };
+
+        let definition = mat.captures.iter().find_map(|cap| {
+            let kind = names[cap.index as usize].strip_prefix("definition.")?;
+            Some((cap.node, kind))
+        });
+
+        let (Some((node, kind)), Some(name)) = (definition, capture("name")) else {
+            continue;
+        };
+
+        if symbols
+            .iter()
+            .any(|symbol| symbol.range == node.byte_range())
+        {
+            continue;
+        }
+
+        symbols.push(Symbol {
+            kind: keyword(node, text).unwrap_or_else(|| kind.to_owned()),
+            name: text.byte_slice(name.byte_range()).to_string(),
+            range: node.byte_range(),
+            name_byte: name.start_byte(),
+            parents: Vec::new(),
+        });
+    }
+
+    symbols.sort_by_key(|symbol| (symbol.range.start, std::cmp::Reverse(symbol.range.end)));
+
+    // Symbols come after the ones they are defined in.
+    let mut open: Vec<usize> = Vec::new();
+    for idx in 0..symbols.len() {
+        open.retain(|&parent| symbols[parent].range.end >= symbols[idx].range.end);
+        symbols[idx].parents = open.clone();
+        open.push(idx);
+    }
+
+    symbols
+}
+
+/// The symbols around `byte`, outermost first.
+pub fn around(symbols: &[Symbol], byte: usize) -> Vec<&Symbol> {
+    let idx = symbols.partition_point(|symbol| symbol.range.start <= byte);
+
+    let Some(symbol) = symbols[..idx]
+        .iter()
+        .rev()
+        .find(|symbol| symbol.range.contains(&byte))
+    else {
+        return Vec::new();
+    };
+
+    let mut path: Vec<&Symbol> = symbol.parents.iter().map(|&idx| &symbols[idx]).collect();
+    path.push(symbol);
+    path
+}
+
+/// The first unnamed token of a definition, which is its keyword in most
+/// languages, e.g. `fn` after `pub`.
+fn keyword(node: Node, text: &Rope) -> Option<String> {
+    let mut cursor = node.walk();
+    let token = node.children(&mut cursor).find(|child| !child.is_named())?;
+    let keyword = text.byte_slice(token.byte_range()).to_string();
+
+    keyword
+        .chars()
+        .all(|ch| ch.is_alphanumeric() || ch == '_' || ch == '!')
+        .then_some(keyword)
+}
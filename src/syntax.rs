@@ -0,0 +1,270 @@
+use ropey::Rope;
+use std::ops::Range;
+use tree_sitter::{Node, Query, QueryCursor, Tree};
+
+use crate::document::RopeTextProvider;
+
+/// The smallest syntax node around `range` that is larger than it, e.g. the
+/// statement around an expression.
+pub fn expand(tree: &Tree, range: Range<usize>) -> Option<Range<usize>> {
+    let mut node = tree
+        .root_node()
+        .named_descendant_for_byte_range(range.start, range.end)?;
+
+    while node.byte_range() == range || node.byte_range().is_empty() {
+        node = named_parent(node)?;
+    }
+
+    Some(node.byte_range())
+}
+
+/// The first syntax node inside `range` and smaller than it, e.g. the
+/// condition of an `if`.
+pub fn shrink(tree: &Tree, range: Range<usize>) -> Option<Range<usize>> {
+    let node = tree
+        .root_node()
+        .named_descendant_for_byte_range(range.start, range.end)?;
+
+    let mut cursor = node.walk();
+    let child = node
+        .named_children(&mut cursor)
+        .map(|child| child.byte_range())
+        .find(|child| !child.is_empty() && range.start <= child.start && child.end <= range.end);
+
+    child.filter(|child| *child != range)
+}
+
+/// The next or previous syntax node next to the one selected, or to the
+/// largest one starting at the cursor if `cursor` is set. Climbs to the
+/// parent nodes at the first or last child, but not to the root.
+pub fn sibling(tree: &Tree, range: Range<usize>, cursor: bool, next: bool) -> Option<Range<usize>> {
+    let mut node = tree
+        .root_node()
+        .named_descendant_for_byte_range(range.start, range.end)?;
+
+    while let Some(parent) = named_parent(node)
+        && parent.parent().is_some()
+        && parent.start_byte() == node.start_byte()
+        && (cursor || parent.end_byte() == node.end_byte())
+    {
+        node = parent;
+    }
+
+    loop {
+        let mut sibling = node;
+
+        while let Some(found) = if next {
+            sibling.next_named_sibling()
+        } else {
+            sibling.prev_named_sibling()
+        } {
+            if !found.byte_range().is_empty() {
+                return Some(found.byte_range());
+            }
+            sibling = found;
+        }
+
+        node = named_parent(node)?;
+    }
+}
+
+fn named_parent(node: Node) -> Option<Node> {
+    let mut parent = node.parent()?;
+
+    while !parent.is_named() {
+        parent = parent.parent()?;
+    }
+
+    Some(parent)
+}
+
+/// A text object found by a `textobjects.scm` query, e.g. a function and
+/// its body.
+pub struct Object {
+    pub around: Range<usize>,
+    pub inside: Option<Range<usize>>,
+}
+
+/// The text objects called `name`, e.g. `function`, overlapping `range`. A
+/// pattern captures them as `@function.around` and their inside as
+/// `@function.inside`, and the nodes of a capture make up one range, e.g. a
+/// parameter and the comma after it.
+pub fn objects(
+    query: &Query,
+    tree: &Tree,
+    text: &Rope,
+    name: &str,
+    range: Range<usize>,
+) -> Vec<Object> {
+    let names = query.capture_names();
+    let around = format!("{name}.around");
+    let inside = format!("{name}.inside");
+
+    let mut cursor = QueryCursor::new();
+    cursor.set_byte_range(range);
+
+    let mut objects = Vec::new();
+
+    for mat in cursor.matches(query, tree.root_node(), RopeTextProvider::new(text)) {
+        let span = |capture: &str| {
+            mat.captures
+                .iter()
+                .filter(|cap| names[cap.index as usize] == capture)
+                .map(|cap| cap.node.byte_range())
+                .reduce(|a, b| a.start.min(b.start)..a.end.max(b.end))
+        };
+
+        let inside = span(&inside);
+        if let Some(around) = span(&around).or_else(|| inside.clone()) {
+            objects.push(Object { around, inside });
+        }
+    }
+
+    objects
+}
+
+/// A `@local.scope`, with the names and ranges of the definitions found in it
+/// so far.
+pub struct Scope {
+    pub range: Range<usize>,
+    pub definitions: Vec<(String, Range<usize>)>,
+}
+
+impl Scope {
+    /// The range of the innermost definition of `name`.
+    pub fn find(&self, name: &str) -> Option<Range<usize>> {
+        (self.definitions.iter().rev())
+            .find(|(definition, _)| definition == name)
+            .map(|(_, range)| range.clone())
+    }
+}
+
+/// A name captured by a `locals` query, and the range of a reference.
+pub enum Local<'a> {
+    Definition(&'a str),
+    Reference(&'a str, Range<usize>),
+}
+
+/// Walks the `@local` captures of `query` in `range`, calling `visit` with
+/// each definition and reference and the scopes open around it, innermost
+/// last. The whole file is the outermost scope. A definition is added to its
+/// scope once visited.
+pub fn locals(
+    query: &Query,
+    root: Node,
+    text: &Rope,
+    range: Range<usize>,
+    mut visit: impl FnMut(Local, &[Scope]),
+) {
+    let names = query.capture_names();
+    let name = |node: Node| text.byte_slice(node.byte_range()).to_string();
+
+    let mut scopes = vec![Scope {
+        range: 0..usize::MAX,
+        definitions: Vec::new(),
+    }];
+
+    let mut cursor = QueryCursor::new();
+    cursor.set_byte_range(range);
+
+    for (mat, idx) in cursor.captures(query, root, RopeTextProvider::new(text)) {
+        let node = mat.captures[idx].node;
+
+        while scopes.len() > 1
+            && (scopes.last()).is_some_and(|scope| scope.range.end <= node.start_byte())
+        {
+            scopes.pop();
+        }
+
+        match names[mat.captures[idx].index as usize] {
+            "local.scope" => scopes.push(Scope {
+                range: node.byte_range(),
+                definitions: Vec::new(),
+            }),
+            "local.reference" => visit(Local::Reference(&name(node), node.byte_range()), &scopes),
+            capture if capture.starts_with("local.definition") => {
+                let name = name(node);
+                visit(Local::Definition(&name), &scopes);
+
+                let scope = scopes.last_mut().expect("the file scope is never closed");
+                scope.definitions.push((name, node.byte_range()));
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tree_sitter::Parser;
+
+    const TEXT: &str = "fn main() {\n    let x = foo(1, 2);\n    bar();\n}\n";
+
+    fn tree() -> Tree {
+        let mut parser = Parser::new();
+        parser.set_language(&tree_sitter_rust::language()).unwrap();
+        parser.parse(TEXT, None).unwrap()
+    }
+
+    /// The range of the first `text` in [`TEXT`].
+    fn find(text: &str) -> Range<usize> {
+        let start = TEXT.find(text).unwrap();
+        start..start + text.len()
+    }
+
+    #[test]
+    fn expand_and_shrink() {
+        let tree = tree();
+        let one = TEXT.find('1').unwrap();
+
+        // From the cursor on `1`, out to the arguments and the call.
+        let mut range = one..one + 1;
+        let mut expanded = Vec::new();
+        while let Some(parent) = expand(&tree, range.clone()) {
+            expanded.push(&TEXT[parent.clone()]);
+            range = parent;
+        }
+        assert_eq!(
+            expanded,
+            [
+                "(1, 2)",
+                "foo(1, 2)",
+                "let x = foo(1, 2);",
+                &TEXT[find("{").start..TEXT.len() - 1],
+                TEXT.trim_end(),
+                TEXT,
+            ]
+        );
+
+        assert_eq!(shrink(&tree, find("foo(1, 2)")), Some(find("foo")));
+        assert_eq!(shrink(&tree, find("(1, 2)")), Some(one..one + 1));
+        assert_eq!(shrink(&tree, one..one + 1), None);
+    }
+
+    #[test]
+    fn siblings() {
+        let tree = tree();
+        let one = TEXT.find('1').unwrap();
+
+        assert_eq!(sibling(&tree, one..one + 1, false, true), Some(find("2")));
+        assert_eq!(sibling(&tree, find("2"), false, false), Some(one..one + 1));
+
+        // From the last argument out to the next statement.
+        assert_eq!(sibling(&tree, find("2"), false, true), Some(find("bar();")));
+
+        // From the cursor on `let`, the whole statement is the node.
+        let let_start = TEXT.find("let").unwrap();
+        assert_eq!(
+            sibling(&tree, let_start..let_start + 1, true, true),
+            Some(find("bar();"))
+        );
+        assert_eq!(
+            sibling(&tree, find("bar();"), false, false),
+            Some(find("let x = foo(1, 2);"))
+        );
+
+        // The function has no siblings, and the root is not climbed to.
+        assert_eq!(sibling(&tree, 0..TEXT.len() - 1, false, true), None);
+    }
+}
@@ -1,188 +1,414 @@
-use ratatui::style::{Color, Style, Stylize};
-use std::collections::HashMap;
+use ratatui::style::{Color, Modifier, Style};
+use std::{collections::HashMap, fs, str::FromStr};
 
+use crate::{backend, config};
+
+/// Themes shipped with the editor. Files in `~/.config/ion/themes` take
+/// precedence over them.
+const BUILTIN: [(&str, &str); 2] = [
+    ("ayu_dark", include_str!("../runtime/themes/ayu_dark.toml")),
+    (
+        "ayu_light",
+        include_str!("../runtime/themes/ayu_light.toml"),
+    ),
+];
+
+const DEFAULT: &str = "ayu_dark";
+
+/// How deep `inherits` can nest, to catch cycles.
+const MAX_DEPTH: usize = 8;
+
+/// Styles for the editor UI and for highlight scopes, read from a theme file
+/// in the Helix format.
 pub struct Theme {
+    pub name: String,
     pub editor: Style,
     pub selection: Style,
     pub line_numbers: Style,
     pub active_line: Style,
     pub status_bar: Style,
     pub placeholder: Style,
-    pub tokens: HashMap<String, Style>,
+    pub search_match: Style,
+    pub fold: Style,
+    scopes: HashMap<String, Style>,
 }
 
 impl Theme {
+    /// Loads the theme called `name` from the themes directory, or a built-in
+    /// one.
+    pub fn load(name: &str) -> Result<Self, String> {
+        let name = if name == "default" { DEFAULT } else { name };
+        let table = read(name, true, 0)?;
+
+        Self::from_table(name, table).map_err(|err| format!("theme {name}: {err}"))
+    }
+
+    /// The names of the available themes.
+    pub fn names() -> Vec<String> {
+        let mut names: Vec<String> = BUILTIN.iter().map(|(name, _)| name.to_string()).collect();
+
+        if let Some(dir) = config::config_dir()
+            && let Ok(entries) = fs::read_dir(dir.join("themes"))
+        {
+            names.extend(entries.filter_map(|entry| {
+                let path = entry.ok()?.path();
+                let name = path.file_stem()?.to_str()?;
+                (path.extension()? == "toml").then(|| name.to_owned())
+            }));
+        }
+
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// The style of `scope`, or of the closest less specific scope, e.g.
+    /// `function` for `function.method.call`.
+    pub fn get(&self, scope: &str) -> Option<Style> {
+        let mut scope = scope;
+
+        loop {
+            if let Some(style) = self.scopes.get(scope) {
+                return Some(*style);
+            }
+
+            scope = &scope[..scope.rfind('.')?];
+        }
+    }
+
     pub fn get_token_style(&self, token: &str) -> Style {
-        self.tokens
-            .get(token)
-            .copied()
-            .unwrap_or(self.editor.patch(self.editor))
+        self.get(token).unwrap_or(self.editor)
+    }
+
+    fn ui(&self, scope: &str) -> Style {
+        self.get(scope).unwrap_or_default()
+    }
+
+    fn from_table(name: &str, mut table: toml::Table) -> Result<Self, String> {
+        let palette = match table.remove("palette") {
+            Some(toml::Value::Table(palette)) => palette,
+            Some(_) => return Err("palette must be a table".into()),
+            None => toml::Table::new(),
+        };
+
+        let scopes = table
+            .into_iter()
+            .map(|(scope, value)| {
+                let style =
+                    parse_style(&value, &palette).map_err(|err| format!("{scope}: {err}"))?;
+                Ok((scope, style))
+            })
+            .collect::<Result<HashMap<_, _>, String>>()?;
+
+        let mut theme = Self {
+            name: name.to_owned(),
+            editor: Style::default(),
+            selection: Style::default(),
+            line_numbers: Style::default(),
+            active_line: Style::default(),
+            status_bar: Style::default(),
+            placeholder: Style::default(),
+            search_match: Style::default(),
+            fold: Style::default(),
+            scopes,
+        };
+
+        theme.editor = theme.ui("ui.background").patch(theme.ui("ui.text"));
+        theme.selection = theme.ui("ui.selection");
+        theme.line_numbers = theme.ui("ui.linenr");
+        theme.active_line = theme.ui("ui.cursorline");
+        theme.status_bar = theme.ui("ui.statusline");
+        theme.placeholder = theme.ui("ui.text.inactive");
+        theme.search_match = theme.get("ui.search").unwrap_or(theme.selection);
+        theme.fold = theme.get("ui.fold").unwrap_or(theme.line_numbers);
+
+        Ok(theme)
     }
 }
 
 impl Default for Theme {
     fn default() -> Self {
-        Self {
-            editor: Style::new()
-                .fg(Color::Rgb(230, 225, 207))
-                .bg(Color::Rgb(25, 31, 38)),
-            selection: Style::new().on_light_blue(),
-            line_numbers: Style::new().fg(Color::Rgb(92, 103, 115)),
-            active_line: Style::new()
-                .fg(Color::Rgb(210, 205, 187))
-                .bg(Color::Rgb(39, 43, 46)),
-            status_bar: Style::new()
-                .fg(Color::Rgb(197, 197, 197))
-                .bg(Color::Rgb(15, 20, 25)),
-            placeholder: Style::new().fg(Color::Rgb(170, 165, 147)),
-            tokens: HashMap::from_iter([
-                // Basic tokens
-                (
-                    "attribute".into(),
-                    Style::new().fg(Color::from_u32(0x00ffb454)), // Ayu orange
-                ),
-                (
-                    "keyword".into(),
-                    Style::new().fg(Color::from_u32(0x00ff8f40)), // Ayu orange/red
-                ),
-                (
-                    "constructor".into(),
-                    Style::new().fg(Color::from_u32(0x0059c2ff)), // Ayu blue
-                ),
-                (
-                    "function".into(),
-                    Style::new().fg(Color::from_u32(0x00ffb454)), // Ayu orange
-                ),
-                (
-                    "function.method".into(),
-                    Style::new().fg(Color::from_u32(0x0059c2ff)), // Ayu blue
-                ),
-                (
-                    "punctuation.bracket".into(),
-                    Style::new().fg(Color::from_u32(0x00bfbdb6)), // Ayu light gray
-                ),
-                (
-                    "punctuation.delimiter".into(),
-                    Style::new().fg(Color::from_u32(0x00bfbdb6)), // Ayu light gray
-                ),
-                (
-                    "type".into(),
-                    Style::new().fg(Color::from_u32(0x0059c2ff)), // Ayu blue
-                ),
-                (
-                    "property".into(),
-                    Style::new().fg(Color::from_u32(0x00aad94c)), // Ayu green
-                ),
-                // Tree-sitter specific tokens
-                (
-                    "variable".into(),
-                    Style::new().fg(Color::from_u32(0x00e6e1cf)), // Ayu default text
-                ),
-                (
-                    "variable.parameter".into(),
-                    Style::new().fg(Color::from_u32(0x00e6e1cf)), // Ayu default text
-                ),
-                (
-                    "variable.builtin".into(),
-                    Style::new().fg(Color::from_u32(0x00ff8f40)), // Ayu orange/red
-                ),
-                (
-                    "constant".into(),
-                    Style::new().fg(Color::from_u32(0x00d4bfff)), // Ayu purple
-                ),
-                (
-                    "constant.builtin".into(),
-                    Style::new().fg(Color::from_u32(0x00d4bfff)), // Ayu purple
-                ),
-                (
-                    "constant.numeric".into(),
-                    Style::new().fg(Color::from_u32(0x00d4bfff)), // Ayu purple
-                ),
-                (
-                    "constant.character".into(),
-                    Style::new().fg(Color::from_u32(0x00c2d94c)), // Ayu lime green
-                ),
-                (
-                    "string".into(),
-                    Style::new().fg(Color::from_u32(0x00c2d94c)), // Ayu lime green
-                ),
-                (
-                    "string.escape".into(),
-                    Style::new().fg(Color::from_u32(0x0095e6cb)), // Ayu cyan
-                ),
-                (
-                    "string.regex".into(),
-                    Style::new().fg(Color::from_u32(0x0095e6cb)), // Ayu cyan
-                ),
-                (
-                    "comment".into(),
-                    Style::new().fg(Color::from_u32(0x005c6773)), // Ayu dark gray
-                ),
-                (
-                    "comment.documentation".into(),
-                    Style::new().fg(Color::from_u32(0x005c6773)), // Ayu dark gray
-                ),
-                (
-                    "tag".into(),
-                    Style::new().fg(Color::from_u32(0x00ff8f40)), // Ayu orange/red
-                ),
-                (
-                    "tag.attribute".into(),
-                    Style::new().fg(Color::from_u32(0x00ffb454)), // Ayu orange
-                ),
-                (
-                    "tag.delimiter".into(),
-                    Style::new().fg(Color::from_u32(0x00bfbdb6)), // Ayu light gray
-                ),
-                (
-                    "operator".into(),
-                    Style::new().fg(Color::from_u32(0x00ff8f40)), // Ayu orange/red
-                ),
-                (
-                    "label".into(),
-                    Style::new().fg(Color::from_u32(0x00ffb454)), // Ayu orange
-                ),
-                (
-                    "module".into(),
-                    Style::new().fg(Color::from_u32(0x0059c2ff)), // Ayu blue
-                ),
-                (
-                    "namespace".into(),
-                    Style::new().fg(Color::from_u32(0x0059c2ff)), // Ayu blue
-                ),
-                (
-                    "field".into(),
-                    Style::new().fg(Color::from_u32(0x00aad94c)), // Ayu green
-                ),
-                (
-                    "parameter".into(),
-                    Style::new().fg(Color::from_u32(0x00e6e1cf)), // Ayu default text
-                ),
-                (
-                    "macro".into(),
-                    Style::new().fg(Color::from_u32(0x00ff8f40)), // Ayu orange/red
-                ),
-                (
-                    "type.builtin".into(),
-                    Style::new().fg(Color::from_u32(0x0059c2ff)), // Ayu blue
-                ),
-                (
-                    "escape".into(),
-                    Style::new().fg(Color::from_u32(0x0095e6cb)), // Ayu cyan
-                ),
-                (
-                    "embedded".into(),
-                    Style::new().fg(Color::from_u32(0x00e6e1cf)), // Ayu default text
-                ),
-                (
-                    "error".into(),
-                    Style::new().fg(Color::from_u32(0x00f07178)), // Ayu red
-                ),
-                (
-                    "warning".into(),
-                    Style::new().fg(Color::from_u32(0x00ffb454)), // Ayu orange
-                ),
-            ]),
+        Self::load(DEFAULT).expect("valid built-in theme")
+    }
+}
+
+/// Reads the theme file of `name`, merged over the theme it inherits from.
+/// A theme inheriting its own name extends the built-in one.
+fn read(name: &str, user: bool, depth: usize) -> Result<toml::Table, String> {
+    read_from(name, user, depth, &source)
+}
+
+/// The text of the theme file of `name`: the user's if `user` is set and
+/// there is one, or else the built-in one.
+fn source(name: &str, user: bool) -> Result<String, String> {
+    let path = config::config_dir()
+        .map(|dir| dir.join("themes").join(format!("{name}.toml")))
+        .filter(|path| user && path.is_file());
+
+    match path {
+        Some(path) => fs::read_to_string(&path).map_err(|err| format!("{}: {err}", path.display())),
+        None => BUILTIN
+            .iter()
+            .find(|(builtin, _)| *builtin == name)
+            .map(|(_, text)| text.to_string())
+            .ok_or_else(|| format!("unknown theme: {name}")),
+    }
+}
+
+/// Like [`read`], with the theme files given by `source`.
+fn read_from(
+    name: &str,
+    user: bool,
+    depth: usize,
+    source: &dyn Fn(&str, bool) -> Result<String, String>,
+) -> Result<toml::Table, String> {
+    if depth > MAX_DEPTH {
+        return Err(format!("theme {name}: too many levels of inherits"));
+    }
+
+    let text = source(name, user)?;
+    let mut table: toml::Table =
+        toml::from_str(&text).map_err(|err| format!("theme {name}: {}", err.message()))?;
+
+    let Some(parent) = table.remove("inherits") else {
+        return Ok(table);
+    };
+
+    let parent = parent
+        .as_str()
+        .ok_or_else(|| format!("theme {name}: inherits must be a theme name"))?;
+    let mut base = read_from(parent, user && parent != name, depth + 1, source)?;
+
+    if let Some(toml::Value::Table(palette)) = table.remove("palette") {
+        match base.get_mut("palette") {
+            Some(toml::Value::Table(base)) => base.extend(palette),
+            _ => _ = base.insert("palette".into(), palette.into()),
+        }
+    }
+
+    base.extend(table);
+    Ok(base)
+}
+
+/// Parses a color name or `{ fg, bg, underline, modifiers }` table.
+fn parse_style(value: &toml::Value, palette: &toml::Table) -> Result<Style, String> {
+    let table = match value {
+        toml::Value::String(color) => return Ok(Style::new().fg(parse_color(color, palette)?)),
+        toml::Value::Table(table) => table,
+        _ => return Err("expected a color or a table".into()),
+    };
+
+    let mut style = Style::new();
+
+    for (key, value) in table {
+        match (key.as_str(), value) {
+            ("fg", toml::Value::String(color)) => style = style.fg(parse_color(color, palette)?),
+            ("bg", toml::Value::String(color)) => style = style.bg(parse_color(color, palette)?),
+            ("underline", toml::Value::Table(underline)) => {
+                style = style.add_modifier(Modifier::UNDERLINED);
+
+                for (key, value) in underline {
+                    match (key.as_str(), value) {
+                        ("color", toml::Value::String(color)) => {
+                            style = style.underline_color(parse_color(color, palette)?);
+                        }
+                        ("style", toml::Value::String(kind)) => {
+                            style = style.add_modifier(parse_underline(kind)?);
+                        }
+                        _ => return Err(format!("invalid underline {key}: {value}")),
+                    }
+                }
+            }
+            ("modifiers", toml::Value::Array(modifiers)) => {
+                for modifier in modifiers {
+                    style = style.add_modifier(parse_modifier(modifier)?);
+                }
+            }
+            _ => return Err(format!("invalid {key}: {value}")),
         }
     }
+
+    Ok(style)
+}
+
+/// Parses a palette name, a `#rrggbb` color or a terminal color name.
+fn parse_color(color: &str, palette: &toml::Table) -> Result<Color, String> {
+    let color = match palette.get(color) {
+        Some(toml::Value::String(color)) => color,
+        _ => color,
+    };
+
+    Color::from_str(color).map_err(|_| format!("invalid color: {color}"))
+}
+
+fn parse_underline(kind: &str) -> Result<Modifier, String> {
+    Ok(match kind {
+        "line" => Modifier::empty(),
+        "double_line" => backend::DOUBLE_UNDERLINE,
+        "curl" => backend::UNDERCURL,
+        "dotted" => backend::UNDERDOTTED,
+        "dashed" => backend::UNDERDASHED,
+        _ => return Err(format!("invalid underline style: {kind}")),
+    })
+}
+
+fn parse_modifier(modifier: &toml::Value) -> Result<Modifier, String> {
+    Ok(match modifier.as_str() {
+        Some("bold") => Modifier::BOLD,
+        Some("dim") => Modifier::DIM,
+        Some("italic") => Modifier::ITALIC,
+        Some("underlined") => Modifier::UNDERLINED,
+        Some("slow_blink") => Modifier::SLOW_BLINK,
+        Some("rapid_blink") => Modifier::RAPID_BLINK,
+        Some("reversed") => Modifier::REVERSED,
+        Some("hidden") => Modifier::HIDDEN,
+        Some("crossed_out") => Modifier::CROSSED_OUT,
+        _ => return Err(format!("invalid modifier: {modifier}")),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn theme(text: &str) -> Theme {
+        Theme::from_table("test", toml::from_str(text).unwrap()).unwrap()
+    }
+
+    /// Reads `name` from the given theme files, where a file is the user's
+    /// if its name starts with `user/`.
+    fn read_files(name: &str, files: &[(&str, &str)]) -> Result<toml::Table, String> {
+        let source = |name: &str, user: bool| {
+            let file = |name: &str| files.iter().find(|(file, _)| *file == name);
+            let user_file = format!("user/{name}");
+
+            user.then(|| file(&user_file))
+                .flatten()
+                .or_else(|| file(name))
+                .map(|(_, text)| text.to_string())
+                .ok_or_else(|| format!("unknown theme: {name}"))
+        };
+
+        read_from(name, true, 0, &source)
+    }
+
+    #[test]
+    fn dotted_scope_fallback() {
+        let theme = theme(
+            r##"
+            keyword = "red"
+            "keyword.control" = "blue"
+            function = { fg = "green", modifiers = ["bold"] }
+            "##,
+        );
+
+        assert_eq!(
+            theme.get("keyword.control"),
+            Some(Style::new().fg(Color::Blue))
+        );
+        assert_eq!(
+            theme.get("keyword.control.return"),
+            Some(Style::new().fg(Color::Blue))
+        );
+        assert_eq!(
+            theme.get("keyword.operator"),
+            Some(Style::new().fg(Color::Red))
+        );
+        assert_eq!(
+            theme.get("function.method.call"),
+            Some(Style::new().fg(Color::Green).add_modifier(Modifier::BOLD))
+        );
+        assert_eq!(theme.get("keywords"), None);
+        assert_eq!(theme.get("variable"), None);
+        assert_eq!(theme.get_token_style("variable"), theme.editor);
+    }
+
+    #[test]
+    fn palette() {
+        let theme = theme(
+            r##"
+            "ui.text" = "fg"
+            type = { fg = "#ff8000", bg = "bg" }
+            "diagnostic.error" = { underline = { color = "fg", style = "curl" } }
+
+            [palette]
+            fg = "#102030"
+            bg = "black"
+            "##,
+        );
+
+        assert_eq!(theme.editor, Style::new().fg(Color::Rgb(0x10, 0x20, 0x30)));
+        assert_eq!(
+            theme.get("type"),
+            Some(Style::new().fg(Color::Rgb(0xff, 0x80, 0)).bg(Color::Black))
+        );
+        assert_eq!(
+            theme.get("diagnostic.error"),
+            Some(
+                Style::new()
+                    .add_modifier(Modifier::UNDERLINED | backend::UNDERCURL)
+                    .underline_color(Color::Rgb(0x10, 0x20, 0x30))
+            )
+        );
+    }
+
+    #[test]
+    fn invalid_styles() {
+        let error = |text| Theme::from_table("test", toml::from_str(text).unwrap()).err();
+
+        assert_eq!(
+            error(r#"type = "nocolor""#),
+            Some("type: invalid color: nocolor".into())
+        );
+        assert_eq!(
+            error(r#"type = { underline = { style = "wavy" } }"#),
+            Some("type: invalid underline style: wavy".into())
+        );
+        assert_eq!(
+            error(r#"type = { modifiers = ["loud"] }"#),
+            Some(r#"type: invalid modifier: "loud""#.into())
+        );
+    }
+
+    #[test]
+    fn inherits() {
+        let files = [
+            (
+                "base",
+                "keyword = \"accent\"\ntype = \"red\"\n[palette]\naccent = \"blue\"",
+            ),
+            (
+                "child",
+                "inherits = \"base\"\ntype = \"green\"\n[palette]\naccent = \"cyan\"",
+            ),
+            ("user/base", "inherits = \"base\"\nfunction = \"white\""),
+        ];
+
+        // The child's palette applies to the scopes it inherits, and the user's
+        // `base` extends the built-in one.
+        let theme = Theme::from_table("child", read_files("child", &files).unwrap()).unwrap();
+        assert_eq!(theme.get("keyword"), Some(Style::new().fg(Color::Cyan)));
+        assert_eq!(theme.get("type"), Some(Style::new().fg(Color::Green)));
+        assert_eq!(theme.get("function"), Some(Style::new().fg(Color::White)));
+
+        let light = Theme::load("ayu_light").unwrap();
+        let dark = Theme::load("ayu_dark").unwrap();
+        assert_ne!(light.editor, dark.editor);
+        assert!(light.get("keyword").is_some());
+    }
+
+    #[test]
+    fn inherits_cycle() {
+        let files = [("a", "inherits = \"b\""), ("b", "inherits = \"a\"")];
+        assert_eq!(
+            read_files("a", &files),
+            Err("theme b: too many levels of inherits".into())
+        );
+
+        let files = [("a", "inherits = \"missing\"")];
+        assert_eq!(
+            read_files("a", &files),
+            Err("unknown theme: missing".into())
+        );
+    }
 }
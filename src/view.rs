@@ -0,0 +1,1117 @@
+use ratatui::{
+    buffer::{Buffer, Cell},
+    layout::{Alignment, Constraint, Layout, Position, Rect},
+    style::Style,
+    widgets::{Clear, Paragraph, Widget},
+};
+use ropey::RopeSlice;
+use std::{
+    collections::BTreeMap,
+    ops::{Add, Range, RangeInclusive},
+};
+use unicode_width::UnicodeWidthChar;
+
+use crate::{
+    diagnostic::Diagnostic,
+    document::{Document, Edit, Snapshot},
+    highlighter::Highlighter,
+    language::Language,
+    options::LineNumbers,
+    search::Search,
+    symbol, syntax,
+    theme::Theme,
+};
+
+/// A window's state over a document: the cursor, the mode and the scroll
+/// position. Several views can show the same document.
+#[derive(Debug, Clone)]
+pub struct View {
+    /// The id of the buffer shown.
+    pub buffer: usize,
+    mode: Mode,
+    position_byte: usize,
+    position_x: usize,
+    scroll_y: usize,
+    /// The selections before each syntax node expansion, for shrinking back
+    /// to them.
+    expansions: Vec<Range<usize>>,
+    // TODO: Invalidate this after resize?
+    last_view_area: Option<Rect>,
+}
+
+impl View {
+    pub fn new(buffer: usize) -> Self {
+        Self {
+            buffer,
+            mode: Mode::Normal,
+            position_byte: 0,
+            position_x: 0,
+            scroll_y: 0,
+            expansions: Vec::new(),
+            last_view_area: None,
+        }
+    }
+
+    /// Moves `other` onto the buffer of this view, keeping its window area.
+    pub fn replace(&mut self, other: View) -> View {
+        let area = self.last_view_area;
+        let old = std::mem::replace(self, other);
+        self.last_view_area = self.last_view_area.or(area);
+        old
+    }
+
+    pub fn enter_normal(&mut self, doc: &mut Document) {
+        self.mode = Mode::Normal;
+        doc.commit(self.snapshot());
+    }
+
+    pub fn enter_insert(&mut self, doc: &mut Document) {
+        doc.begin(self.snapshot());
+        self.mode = Mode::Insert;
+    }
+
+    pub fn enter_select(&mut self) {
+        self.mode = Mode::Select {
+            start_byte: self.position_byte,
+            line_mode: false,
+        };
+    }
+
+    pub fn enter_select_line(&mut self) {
+        self.mode = Mode::Select {
+            start_byte: self.position_byte,
+            line_mode: true,
+        }
+    }
+
+    /// Selects the syntax node around the selection, or around the cursor
+    /// in normal mode.
+    pub fn expand_selection(&mut self, doc: &Document) {
+        let current = self.selection_range(doc);
+
+        let Some(range) = doc
+            .tree()
+            .and_then(|tree| syntax::expand(tree, current.clone()))
+        else {
+            return;
+        };
+
+        if !matches!(self.mode, Mode::Select { .. }) {
+            self.expansions.clear();
+        }
+
+        self.expansions.push(current);
+        self.select(doc, range);
+    }
+
+    /// Goes back to the selection before the last expansion, or selects the
+    /// first syntax node inside the selection.
+    pub fn shrink_selection(&mut self, doc: &Document) {
+        if !matches!(self.mode, Mode::Select { .. }) {
+            return;
+        }
+
+        let current = self.selection_range(doc);
+
+        // The selection may have changed since the expansion.
+        let previous = self.expansions.pop().filter(|previous| {
+            current.start <= previous.start && previous.end <= current.end && *previous != current
+        });
+
+        if previous.is_none() {
+            self.expansions.clear();
+        }
+
+        let range = previous.or_else(|| {
+            doc.tree()
+                .and_then(|tree| syntax::shrink(tree, current.clone()))
+        });
+
+        if let Some(range) = range {
+            self.select(doc, range);
+        }
+    }
+
+    /// Selects the next or previous syntax node next to the selected one, or
+    /// moves the cursor to it in normal mode.
+    pub fn goto_sibling(&mut self, doc: &Document, next: bool) {
+        let select = matches!(self.mode, Mode::Select { .. });
+        let current = self.selection_range(doc);
+
+        let Some(range) = doc
+            .tree()
+            .and_then(|tree| syntax::sibling(tree, current, !select, next))
+        else {
+            return;
+        };
+
+        if select {
+            self.select(doc, range);
+        } else {
+            self.move_to_byte(doc, range.start);
+        }
+    }
+
+    /// Selects `range`, with the cursor on its last character.
+    pub fn select(&mut self, doc: &Document, range: Range<usize>) {
+        self.mode = Mode::Select {
+            start_byte: range.start,
+            line_mode: false,
+        };
+        self.move_to_byte(doc, range.end.saturating_sub(1).max(range.start));
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    #[must_use]
+    #[allow(clippy::too_many_arguments)]
+    pub fn render(
+        &mut self,
+        doc: &mut Document,
+        window: usize,
+        language: &Language,
+        highlighter: &mut Highlighter,
+        theme: &Theme,
+        line_numbers: LineNumbers,
+        search: Option<&mut Search>,
+        name: &str,
+        area: Rect,
+        buf: &mut Buffer,
+    ) -> Option<Position> {
+        Clear.render(area, buf);
+
+        let [editor, status] =
+            Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(area);
+
+        #[expect(unused_variables)]
+        let area = ();
+
+        // A cursor in a closed fold goes to its first line, which is shown.
+        let folds = doc.folds().lines(doc.rope());
+        let (_, y) = self.position(doc);
+        if folds.start(y) != y {
+            self.move_to(doc, folds.start(y), self.position_x);
+        }
+
+        self.scroll_y = folds.start(self.scroll_y);
+
+        let (_, y) = self.position(doc);
+        let selection = self.selection(doc);
+
+        // The line after the last one on screen.
+        let mut last_line = self.scroll_y;
+        for _ in 0..editor.height {
+            last_line = folds.next(last_line).min(doc.rope().len_lines());
+        }
+
+        let line_number_width = match line_numbers {
+            LineNumbers::None => 0,
+            _ => last_line.to_string().chars().count() as u16 + 4,
+        };
+
+        let layout =
+            Layout::horizontal([Constraint::Length(line_number_width), Constraint::Min(0)]);
+
+        self.last_view_area = Some(layout.areas::<2>(editor)[1]);
+
+        buf.set_style(editor, theme.editor);
+
+        let scrolled_bytes = doc.rope().line_to_byte(self.scroll_y);
+        let visible_bytes = doc.rope().line_to_byte(last_line);
+
+        doc.request_highlights(
+            self.buffer,
+            window,
+            language,
+            scrolled_bytes..visible_bytes,
+            highlighter,
+        );
+        let content = doc.rope();
+        let mut styles = doc.highlights(window).iter().peekable();
+
+        let matches = search
+            .map(|search| search.find_all(content, scrolled_bytes..visible_bytes))
+            .unwrap_or_default();
+        let mut matches = matches.iter().peekable();
+
+        // The diagnostics on screen are underlined, and the most severe one of
+        // each line is marked in the gutter and its message shown after it.
+        let diagnostics: Vec<&Diagnostic> = (doc.diagnostics().iter())
+            .filter(|diagnostic| {
+                diagnostic.range.start <= visible_bytes && diagnostic.range.end >= scrolled_bytes
+            })
+            .collect();
+
+        let mut line_diagnostics: BTreeMap<usize, &Diagnostic> = BTreeMap::new();
+        for &diagnostic in &diagnostics {
+            let line = content.byte_to_line(diagnostic.range.start.min(content.len_bytes()));
+            let most = line_diagnostics.entry(line).or_insert(diagnostic);
+            if diagnostic.severity > most.severity {
+                *most = diagnostic;
+            }
+        }
+
+        let mut rows = editor.rows();
+        let mut cursor = None;
+        let mut line_idx = self.scroll_y;
+
+        while line_idx < last_line {
+            let Some(row) = rows.next() else {
+                break;
+            };
+
+            let [nums, text] = layout.areas(row);
+            let line = content.line(line_idx);
+            let byte = content.line_to_byte(line_idx);
+
+            let line_style = theme.editor.patch(if line_idx == y {
+                theme.active_line
+            } else {
+                Style::default()
+            });
+
+            buf.set_style(row, line_style);
+
+            let number = match line_numbers {
+                LineNumbers::Relative if line_idx != y => line_idx.abs_diff(y),
+                _ => line_idx + 1,
+            };
+
+            Paragraph::new(format!("  {number}  "))
+                .style(line_style.patch(theme.line_numbers))
+                .alignment(Alignment::Right)
+                .render(nums, buf);
+
+            let line_diagnostic = line_diagnostics.get(&line_idx);
+            if let Some(diagnostic) = line_diagnostic
+                && nums.width > 0
+                && let Some(cell) = buf.cell_mut(nums)
+            {
+                let style = theme.get(diagnostic.severity.name());
+                let style = style.unwrap_or(theme.line_numbers);
+                cell.set_symbol("●").set_style(line_style.patch(style));
+            }
+
+            let mut byte_x = byte;
+
+            let mut positions = text.positions();
+            for (ch, pos) in line.chars().zip(&mut positions) {
+                let mut cell = Cell::EMPTY;
+                cell.set_char(ch);
+                cell.set_style(line_style);
+
+                while styles.peek().is_some_and(|p| p.before(byte_x)) {
+                    styles.next();
+                }
+
+                if let Some(peek) = styles.peek()
+                    && peek.contains(byte_x)
+                {
+                    cell.set_style(theme.get_token_style(&peek.style));
+                }
+
+                // Empty ranges underline the character they are at.
+                let underline = (diagnostics.iter())
+                    .filter(|diagnostic| {
+                        diagnostic.range.contains(&byte_x) || diagnostic.range.start == byte_x
+                    })
+                    .map(|diagnostic| diagnostic.severity)
+                    .max()
+                    .and_then(|severity| theme.get(&format!("diagnostic.{}", severity.name())));
+
+                if let Some(underline) = underline {
+                    cell.set_style(underline);
+                }
+
+                while matches.peek().is_some_and(|mat| mat.end <= byte_x) {
+                    matches.next();
+                }
+
+                if matches.peek().is_some_and(|mat| mat.contains(&byte_x)) {
+                    cell.set_style(theme.editor.patch(theme.search_match));
+                }
+
+                if selection.contains(&byte_x) {
+                    cell.set_style(theme.editor.patch(theme.selection));
+                }
+
+                if ch == '\n' {
+                    cell.set_char(' ');
+                }
+
+                buf[pos] = cell;
+
+                if self.position_byte == byte_x {
+                    cursor = Some(pos);
+                }
+
+                byte_x += ch.len_utf8();
+            }
+
+            if self.position_byte == byte_x {
+                cursor = positions.next()
+            }
+
+            // Fold markers and diagnostic messages go a column after the
+            // text of the line.
+            let width = display_width(line) + 1;
+
+            if let Some(fold) = folds.get(line_idx) {
+                let marker = format!(" ⋯ {} lines ", fold.end() - fold.start());
+                let area = text.intersection(Rect {
+                    x: text.x.saturating_add(width),
+                    width: text.width.saturating_sub(width),
+                    ..text
+                });
+                Paragraph::new(marker)
+                    .style(line_style.patch(theme.fold))
+                    .render(area, buf);
+            } else if let Some(diagnostic) = line_diagnostic {
+                let message = diagnostic.message.lines().next().unwrap_or_default();
+                let area = text.intersection(Rect {
+                    x: text.x.saturating_add(width),
+                    width: text.width.saturating_sub(width),
+                    ..text
+                });
+                let style = theme.get(diagnostic.severity.name()).unwrap_or_default();
+                Paragraph::new(message)
+                    .style(line_style.patch(style))
+                    .render(area, buf);
+            }
+
+            line_idx = folds.next(line_idx);
+        }
+
+        let mode = match self.mode {
+            Mode::Normal => "NOR",
+            Mode::Insert => "INS",
+            Mode::Select {
+                line_mode: false, ..
+            } => "SEL",
+            Mode::Select {
+                line_mode: true, ..
+            } => "LIN",
+        };
+
+        let modified = if doc.is_modified() { " [+]" } else { "" };
+        let breadcrumb: Vec<String> = symbol::around(doc.symbols(language), self.position_byte)
+            .iter()
+            .map(|symbol| symbol.label())
+            .collect();
+        let breadcrumb = if breadcrumb.is_empty() {
+            String::new()
+        } else {
+            format!("  {}", breadcrumb.join(" > "))
+        };
+
+        let (line_idx, x_offset) = self.position(doc);
+        let skipped = if doc.parse_failed() {
+            " [not highlighted]"
+        } else {
+            ""
+        };
+        let right = format!(
+            "{}{skipped}  {}:{} ",
+            language.name(),
+            line_idx + 1,
+            x_offset + 1
+        );
+
+        // The left text is cut short rather than running into the right.
+        let left = Rect {
+            width: status
+                .width
+                .saturating_sub(right.chars().count() as u16 + 1),
+            ..status
+        };
+        Paragraph::new(format!(" {mode}  {name}{modified}{breadcrumb}")).render(left, buf);
+
+        Paragraph::new(right)
+            .style(theme.status_bar)
+            .alignment(Alignment::Right)
+            .render(status, buf);
+
+        cursor
+    }
+
+    fn selection(&self, doc: &Document) -> RangeInclusive<usize> {
+        let content = doc.rope();
+
+        match self.mode {
+            Mode::Select {
+                start_byte,
+                line_mode: false,
+            } => self.position_byte.min(start_byte)..=self.position_byte.max(start_byte),
+            Mode::Select {
+                start_byte,
+                line_mode: true,
+            } => {
+                let start_line_idx = content.byte_to_line(start_byte);
+                let end_line_idx = content.byte_to_line(self.position_byte);
+
+                let first_line_idx = start_line_idx.min(end_line_idx);
+                let last_line_idx = start_line_idx.max(end_line_idx);
+
+                let end_line = content.line(last_line_idx);
+
+                let start_byte = content.line_to_byte(first_line_idx);
+                let end_byte = content
+                    .line_to_byte(last_line_idx)
+                    .add(end_line.len_chars())
+                    .saturating_sub(1);
+
+                start_byte.min(end_byte)..=start_byte.max(end_byte)
+            }
+            _ => self.position_byte..=self.position_byte,
+        }
+    }
+
+    fn selection_range(&self, doc: &Document) -> Range<usize> {
+        let content = doc.rope();
+        let range = self.selection(doc);
+        let end_char = content.byte_to_char(*range.end()) + 1;
+
+        *range.start()..content.char_to_byte(end_char.min(content.len_chars()))
+    }
+
+    pub fn position_byte(&self) -> usize {
+        self.position_byte
+    }
+
+    #[inline(always)]
+    pub fn position(&self, doc: &Document) -> (usize, usize) {
+        // TODO: That ain't efficient.
+        let line_idx = doc.rope().byte_to_line(self.position_byte);
+        let line_start = doc.rope().line_to_byte(line_idx);
+
+        (self.position_byte - line_start, line_idx)
+    }
+
+    #[inline(always)]
+    fn move_to(&mut self, doc: &Document, line_idx: usize, x_byte: usize) {
+        let content = doc.rope();
+        let last_line_idx = content.len_lines().saturating_sub(1);
+        let line_idx = line_idx.min(last_line_idx);
+
+        let line_start = content.line_to_byte(line_idx);
+
+        let line = content.line(line_idx);
+        let max_x_offset = line
+            .len_bytes()
+            .saturating_sub(if line_idx == last_line_idx { 0 } else { 1 });
+        let x_offset = x_byte.min(max_x_offset);
+
+        self.position_byte = line_start + x_offset;
+    }
+
+    #[inline(always)]
+    fn update_position_x(&mut self, doc: &Document) {
+        let (x, _) = self.position(doc);
+        self.position_x = x;
+    }
+
+    pub fn move_to_byte(&mut self, doc: &Document, byte: usize) {
+        self.position_byte = snap_to_char(doc, byte);
+        self.update_position_x(doc);
+    }
+
+    pub fn goto_line(&mut self, doc: &Document, line_idx: usize) {
+        self.move_to(doc, line_idx, 0);
+        self.update_position_x(doc);
+    }
+
+    /// Moves to the line above, over a closed fold as if it was one line.
+    pub fn move_up(&mut self, doc: &Document) {
+        let (x_offset, line_idx) = self.position(doc);
+        let folds = doc.folds().lines(doc.rope());
+        self.move_to(
+            doc,
+            folds.prev(line_idx).unwrap_or(0),
+            x_offset.max(self.position_x),
+        );
+    }
+
+    /// Moves to the line below, over a closed fold as if it was one line.
+    pub fn move_down(&mut self, doc: &Document) {
+        let (x_offset, line_idx) = self.position(doc);
+        let folds = doc.folds().lines(doc.rope());
+
+        if folds.next(line_idx) < doc.rope().len_lines() {
+            self.move_to(doc, folds.next(line_idx), x_offset.max(self.position_x));
+        }
+    }
+
+    pub fn move_left(&mut self, doc: &Document) {
+        let (x_offset, line_idx) = self.position(doc);
+        self.move_to(doc, line_idx, x_offset.saturating_sub(1));
+        self.update_position_x(doc);
+    }
+
+    pub fn move_right(&mut self, doc: &Document) {
+        let (x_offset, line_idx) = self.position(doc);
+        self.move_to(doc, line_idx, x_offset + 1);
+        self.update_position_x(doc);
+    }
+
+    pub fn move_line_start(&mut self, doc: &Document) {
+        let (_, line_idx) = self.position(doc);
+        self.goto_line(doc, line_idx);
+    }
+
+    /// Moves to the last character of the line, `lines` - 1 lines down.
+    pub fn move_line_end(&mut self, doc: &Document, lines: usize) {
+        let (_, line_idx) = self.position(doc);
+        let line_idx = (line_idx + lines.saturating_sub(1)).min(doc.rope().len_lines() - 1);
+        let line = doc.rope().line(line_idx);
+
+        let len = line.len_chars();
+        let len = len - usize::from(len > 0 && line.char(len - 1) == '\n');
+        let last_char = doc.rope().line_to_char(line_idx) + len.saturating_sub(1);
+
+        self.move_to_byte(doc, doc.rope().char_to_byte(last_char));
+        self.position_x = usize::MAX;
+    }
+
+    fn find_next_word(&self, doc: &Document) -> Option<usize> {
+        let content = doc.rope();
+        let mut offset = self.position_byte;
+        let chars = content.chars_at(content.byte_to_char(self.position_byte));
+        chars
+            .map(|ch| {
+                let result = (offset, ch);
+                offset += ch.len_utf8();
+                result
+            })
+            .map_windows(|[(_, a), (byte, b)]| (*byte, CharKind::new(*a) != CharKind::new(*b)))
+            .filter_map(|(byte, is_boundary)| is_boundary.then_some(byte))
+            .next()
+    }
+
+    fn find_next_word_end(&self, doc: &Document) -> Option<usize> {
+        let content = doc.rope();
+        let mut offset = self.position_byte;
+        let chars = content.chars_at(content.byte_to_char(self.position_byte));
+        chars
+            .map(|ch| {
+                let result = (offset, ch);
+                offset += ch.len_utf8();
+                result
+            })
+            .skip(1)
+            .map_windows(|[(byte, a), (_, b)]| (*byte, CharKind::new(*a) != CharKind::new(*b)))
+            .filter_map(|(byte, is_boundary)| (is_boundary).then_some(byte))
+            .next()
+    }
+
+    fn find_prev_word_start(&self, doc: &Document) -> Option<usize> {
+        let content = doc.rope();
+        let mut offset = self.position_byte
+            + content
+                .chars()
+                .next()
+                .map(char::len_utf8)
+                .unwrap_or_default();
+
+        content
+            .chars_at(
+                content
+                    .byte_to_char(self.position_byte)
+                    .add(1)
+                    .min(content.len_chars()),
+            )
+            .reversed()
+            .map(|ch| {
+                let byte = offset;
+                offset -= ch.len_utf8();
+                (byte, ch)
+            })
+            .skip(1)
+            .map_windows(|[(_, a), (byte, b)]| (*byte, CharKind::new(*a) != CharKind::new(*b)))
+            .filter_map(|(byte, is_boundary)| (is_boundary).then_some(byte))
+            .next()
+    }
+
+    pub fn move_next_word(&mut self, doc: &Document) {
+        self.position_byte = self.find_next_word(doc).unwrap_or(doc.rope().len_bytes());
+        self.update_position_x(doc);
+    }
+
+    pub fn move_next_word_end(&mut self, doc: &Document) {
+        self.position_byte = self
+            .find_next_word_end(doc)
+            .unwrap_or(doc.rope().len_bytes());
+        self.update_position_x(doc);
+    }
+
+    pub fn move_prev_word_start(&mut self, doc: &Document) {
+        self.position_byte = self.find_prev_word_start(doc).unwrap_or(0);
+        self.update_position_x(doc);
+    }
+
+    pub fn scroll_up(&mut self, doc: &Document) {
+        let folds = doc.folds().lines(doc.rope());
+        self.scroll_y = folds.prev(self.scroll_y).unwrap_or(0);
+    }
+
+    pub fn scroll_down(&mut self, doc: &Document) {
+        let folds = doc.folds().lines(doc.rope());
+        let next = folds.next(self.scroll_y);
+
+        if next < doc.rope().len_lines() {
+            self.scroll_y = next;
+        }
+    }
+
+    /// Scrolls the cursor line to the middle of the window.
+    pub fn center_cursor(&mut self, doc: &Document) {
+        let (_, y) = self.position(doc);
+        let Some(Rect { height, .. }) = self.last_view_area else {
+            return;
+        };
+
+        let folds = doc.folds().lines(doc.rope());
+        self.scroll_y = folds.start(y);
+        for _ in 0..height / 2 {
+            match folds.prev(self.scroll_y) {
+                Some(prev) => self.scroll_y = prev,
+                None => break,
+            }
+        }
+    }
+
+    pub fn scroll_to_cursor(&mut self, doc: &Document) {
+        let (_, y) = self.position(doc);
+        let Some(Rect { height, .. }) = self.last_view_area else {
+            return;
+        };
+
+        let folds = doc.folds().lines(doc.rope());
+        let y = folds.start(y);
+
+        // The first line with the cursor still on screen.
+        let mut top = y;
+        for _ in 1..height {
+            match folds.prev(top) {
+                Some(prev) => top = prev,
+                None => break,
+            }
+        }
+
+        self.scroll_y = folds.start(self.scroll_y).clamp(top, y);
+    }
+
+    pub fn move_to_view(&mut self, doc: &Document) {
+        let (_, y) = self.position(doc);
+        let Some(Rect { height, .. }) = self.last_view_area else {
+            return;
+        };
+
+        let folds = doc.folds().lines(doc.rope());
+        let last_line_idx = doc.rope().len_lines() - 1;
+
+        // The last line on screen.
+        let mut bottom = self.scroll_y;
+        for _ in 1..height {
+            match folds.next(bottom) {
+                next if next <= last_line_idx => bottom = next,
+                _ => break,
+            }
+        }
+
+        let y = folds.start(y.clamp(self.scroll_y, bottom));
+        self.move_to(doc, y, self.position_x);
+    }
+
+    pub fn insert(&mut self, doc: &mut Document, ch: char) {
+        self.transaction(doc, |view, doc| {
+            let byte = view.position_byte;
+            doc.replace(byte..byte, ch.encode_utf8(&mut [0; 4]));
+
+            view.position_byte += ch.len_utf8();
+            view.update_position_x(doc);
+        });
+    }
+
+    /// Replaces the text from `start` to the cursor with `text`, e.g. a
+    /// completion of the word typed, leaving the cursor after it.
+    pub fn replace_before(&mut self, doc: &mut Document, start: usize, text: &str) {
+        self.transaction(doc, |view, doc| {
+            let start = snap_to_char(doc, start.min(view.position_byte));
+            doc.replace(start..view.position_byte, text);
+
+            view.position_byte = start + text.len();
+            view.update_position_x(doc);
+        });
+    }
+
+    pub fn remove_before(&mut self, doc: &mut Document) {
+        let Some(idx) = doc.rope().byte_to_char(self.position_byte).checked_sub(1) else {
+            return;
+        };
+
+        self.transaction(doc, |view, doc| {
+            let byte_idx = doc.rope().char_to_byte(idx);
+            doc.replace(byte_idx..view.position_byte, "");
+
+            view.position_byte = byte_idx;
+            view.update_position_x(doc);
+        });
+    }
+
+    pub fn remove(&mut self, doc: &mut Document) {
+        if doc.rope().len_bytes() == 0 {
+            return;
+        }
+
+        let range = self.selection_range(doc);
+
+        self.transaction(doc, |view, doc| {
+            doc.replace(range.clone(), "");
+
+            view.position_byte = range.start;
+
+            if let Mode::Select { start_byte, .. } = &mut view.mode {
+                *start_byte = range.start;
+            }
+
+            view.update_position_x(doc);
+        });
+
+        self.scroll_to_cursor(doc);
+    }
+
+    pub fn remove_range(&mut self, doc: &mut Document, range: Range<usize>) {
+        self.transaction(doc, |view, doc| {
+            doc.replace(range.clone(), "");
+            view.move_to_byte(doc, range.start);
+        });
+
+        self.scroll_to_cursor(doc);
+    }
+
+    /// Indents each non-empty line by `width` spaces, or removes up to `width`
+    /// columns of leading whitespace if `outdent` is set.
+    pub fn indent_lines(
+        &mut self,
+        doc: &mut Document,
+        lines: Range<usize>,
+        width: usize,
+        outdent: bool,
+    ) {
+        let first = lines.start;
+
+        self.transaction(doc, |view, doc| {
+            for line_idx in lines.rev() {
+                let start = doc.rope().line_to_byte(line_idx);
+                let line = doc.rope().line(line_idx);
+
+                if outdent {
+                    let mut columns = 0;
+                    let len = line
+                        .chars()
+                        .take_while(|&ch| {
+                            let fits = columns < width && (ch == ' ' || ch == '\t');
+                            columns += if ch == '\t' { width } else { 1 };
+                            fits
+                        })
+                        .count();
+
+                    doc.replace(start..start + len, "");
+                } else if line.chars().any(|ch| !ch.is_whitespace()) {
+                    doc.replace(start..start, &" ".repeat(width));
+                }
+            }
+
+            view.goto_line(doc, first);
+        });
+    }
+
+    /// The indexes of the lines touched by the selection.
+    pub fn selected_lines(&self, doc: &Document) -> Range<usize> {
+        let range = self.selection_range(doc);
+        let last = range.end.saturating_sub(1).max(range.start);
+
+        doc.rope().byte_to_line(range.start)..doc.rope().byte_to_line(last) + 1
+    }
+
+    pub fn selected_text(&self, doc: &Document) -> String {
+        doc.rope().byte_slice(self.selection_range(doc)).to_string()
+    }
+
+    /// Pastes `text` after the cursor, or before it if `before` is set. Text
+    /// ending with a newline is pasted linewise. In select mode the selection
+    /// is replaced instead.
+    pub fn paste(&mut self, doc: &mut Document, text: &str, before: bool) {
+        if text.is_empty() {
+            return;
+        }
+
+        self.transaction(doc, |view, doc| {
+            if let Mode::Select { .. } = view.mode {
+                let range = view.selection_range(doc);
+                doc.replace(range.clone(), text);
+                view.mode = Mode::Normal;
+                view.position_byte = range.start;
+            } else if text.ends_with('\n') {
+                let (_, line_idx) = view.position(doc);
+                let line_idx = if before { line_idx } else { line_idx + 1 };
+
+                if line_idx < doc.rope().len_lines() {
+                    let byte = doc.rope().line_to_byte(line_idx);
+                    doc.replace(byte..byte, text);
+                    view.position_byte = byte;
+                } else {
+                    let byte = doc.rope().len_bytes();
+                    let text = format!("\n{}", text.strip_suffix('\n').unwrap_or(text));
+                    doc.replace(byte..byte, &text);
+                    view.position_byte = byte + 1;
+                }
+            } else {
+                let mut byte = view.position_byte;
+
+                if !before
+                    && let Some(ch) = doc.rope().get_char(doc.rope().byte_to_char(byte))
+                    && ch != '\n'
+                {
+                    byte += ch.len_utf8();
+                }
+
+                doc.replace(byte..byte, text);
+
+                let last_char = text.chars().next_back().map_or(0, char::len_utf8);
+                view.position_byte = byte + text.len() - last_char;
+            }
+
+            view.update_position_x(doc);
+        });
+
+        self.scroll_to_cursor(doc);
+    }
+
+    /// Replaces each range with its text as one undo step. The ranges must be
+    /// sorted and must not overlap.
+    pub fn apply_edits(&mut self, doc: &mut Document, edits: &[(Range<usize>, String)]) {
+        let Some((first, _)) = edits.first() else {
+            return;
+        };
+
+        self.transaction(doc, |view, doc| {
+            for (range, text) in edits.iter().rev() {
+                doc.replace(range.clone(), text);
+            }
+
+            view.move_to_byte(doc, first.start);
+        });
+    }
+
+    pub fn undo(&mut self, doc: &mut Document) {
+        if let Some(snapshot) = doc.undo(self.snapshot()) {
+            self.restore(doc, snapshot);
+        }
+    }
+
+    pub fn redo(&mut self, doc: &mut Document) {
+        if let Some(snapshot) = doc.redo(self.snapshot()) {
+            self.restore(doc, snapshot);
+        }
+    }
+
+    /// Keeps the cursor in place after edits made through another view.
+    pub fn map_edits(&mut self, doc: &Document, edits: &[Edit]) {
+        for edit in edits {
+            self.position_byte = edit.map(self.position_byte);
+
+            if let Mode::Select { start_byte, .. } = &mut self.mode {
+                *start_byte = edit.map(*start_byte);
+            }
+        }
+
+        self.clamp(doc);
+    }
+
+    /// Moves the cursor back into the document, e.g. after it was reloaded.
+    pub fn clamp(&mut self, doc: &Document) {
+        self.position_byte = snap_to_char(doc, self.position_byte);
+
+        if let Mode::Select { start_byte, .. } = &mut self.mode {
+            *start_byte = snap_to_char(doc, *start_byte);
+        }
+
+        self.scroll_y = self.scroll_y.min(doc.rope().len_lines().saturating_sub(1));
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            position_byte: self.position_byte,
+            mode: self.mode,
+        }
+    }
+
+    fn restore(&mut self, doc: &Document, snapshot: Snapshot) {
+        self.position_byte = snapshot.position_byte.min(doc.rope().len_bytes());
+        self.mode = snapshot.mode;
+        self.update_position_x(doc);
+    }
+
+    /// Groups every edit made by `f` into one undo step, unless a transaction
+    /// (e.g. an insert mode session) is already open.
+    fn transaction(&mut self, doc: &mut Document, f: impl FnOnce(&mut Self, &mut Document)) {
+        let started = doc.begin(self.snapshot());
+        f(self, doc);
+        if started {
+            doc.commit(self.snapshot());
+        }
+    }
+}
+
+/// The columns `line` takes on screen, without its line break. Control
+/// characters such as tabs take a column each.
+fn display_width(line: RopeSlice) -> u16 {
+    let width: usize = (line.chars())
+        .filter(|ch| !matches!(ch, '\n' | '\r'))
+        .map(|ch| ch.width().unwrap_or(1))
+        .sum();
+
+    u16::try_from(width).unwrap_or(u16::MAX)
+}
+
+fn snap_to_char(doc: &Document, byte: usize) -> usize {
+    let content = doc.rope();
+    let char_idx = content.byte_to_char(byte.min(content.len_bytes()));
+    content.char_to_byte(char_idx)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Mode {
+    #[default]
+    Normal,
+    Insert,
+    Select {
+        start_byte: usize,
+        line_mode: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CharKind {
+    Lf,
+    Whitespace,
+    Other,
+}
+
+impl CharKind {
+    fn new(ch: char) -> Self {
+        match ch {
+            '\n' | '\r' => Self::Lf,
+            ch if ch.is_whitespace() => Self::Whitespace,
+            _ => Self::Other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::language::Languages;
+    use ropey::Rope;
+    use std::{
+        sync::Arc,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    fn render(
+        view: &mut View,
+        window: usize,
+        doc: &mut Document,
+        language: &Language,
+        highlighter: &mut Highlighter,
+    ) -> Buffer {
+        let area = Rect::new(0, 0, 30, 10);
+        let mut buf = Buffer::empty(area);
+        _ = view.render(
+            doc,
+            window,
+            language,
+            highlighter,
+            &Theme::default(),
+            LineNumbers::None,
+            None,
+            "main.rs",
+            area,
+            &mut buf,
+        );
+        buf
+    }
+
+    #[test]
+    fn windows_keep_their_highlights() {
+        let languages = Arc::new(Languages::new(Vec::new()));
+        let language = languages.load("rust").unwrap();
+        let mut highlighter = Highlighter::new(languages);
+        let text: String = (0..200).map(|idx| format!("fn f{idx}() {{}}\n")).collect();
+        let mut doc = Document::new(Rope::from_str(&text));
+
+        // Two windows on the buffer, scrolled far apart.
+        let mut top = View::new(1);
+        let mut bottom = View::new(1);
+        bottom.goto_line(&doc, 150);
+        bottom.scroll_y = 150;
+
+        // Like the app, take in the responses and draw both windows, until
+        // neither sends a request.
+        let start = Instant::now();
+        let (top_buf, bottom_buf) = loop {
+            for response in highlighter.receive() {
+                doc.receive_highlights(response);
+            }
+
+            let top_buf = render(&mut top, 1, &mut doc, &language, &mut highlighter);
+            let bottom_buf = render(&mut bottom, 2, &mut doc, &language, &mut highlighter);
+
+            if !highlighter.is_busy() {
+                break (top_buf, bottom_buf);
+            }
+
+            assert!(start.elapsed() < Duration::from_secs(5), "never settled");
+            thread::sleep(Duration::from_millis(1));
+        };
+
+        // Both draw `fn` as a keyword, past the cursor on its `f`.
+        let keyword = Theme::default().get("keyword").unwrap().fg.unwrap();
+        for buf in [&top_buf, &bottom_buf] {
+            assert_eq!(buf[(1, 0)].symbol(), "n");
+            assert_eq!(buf[(1, 0)].fg, keyword);
+        }
+
+        // Each keeps the highlights of its own lines.
+        let line_150 = doc.rope().line_to_byte(150);
+        let covers = |window, byte| {
+            doc.highlights(window)
+                .iter()
+                .any(|style| style.contains(byte))
+        };
+        assert!(covers(1, 0) && !covers(1, line_150));
+        assert!(covers(2, line_150) && !covers(2, 0));
+
+        // Drawing them again needs no new highlights.
+        render(&mut top, 1, &mut doc, &language, &mut highlighter);
+        render(&mut bottom, 2, &mut doc, &language, &mut highlighter);
+        assert!(!highlighter.is_busy());
+
+        doc.retain_highlights(|window| window == 1);
+        assert!(doc.highlights(2).is_empty());
+    }
+
+    #[test]
+    fn line_width() {
+        let width = |text| display_width(Rope::from_str(text).slice(..));
+
+        assert_eq!(width("fn main() {}\n"), 12);
+        assert_eq!(width("\tx\r\n"), 2);
+        assert_eq!(width("日本語 é\n"), 8);
+        assert_eq!(width(""), 0);
+    }
+}
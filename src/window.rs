@@ -0,0 +1,225 @@
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use std::collections::BTreeMap;
+
+use crate::view::View;
+
+/// The windows on screen, laid out as a tree of splits. Each window has its
+/// own [`View`].
+pub struct Windows {
+    root: Node,
+    views: BTreeMap<usize, View>,
+    focus: usize,
+    last_id: usize,
+    last_area: Rect,
+}
+
+enum Node {
+    Window(usize),
+    Split(Direction, Vec<Node>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Left,
+    Down,
+    Up,
+    Right,
+}
+
+impl Windows {
+    pub fn new(view: View) -> Self {
+        Self {
+            root: Node::Window(1),
+            views: BTreeMap::from([(1, view)]),
+            focus: 1,
+            last_id: 1,
+            last_area: Rect::default(),
+        }
+    }
+
+    pub fn focus(&self) -> usize {
+        self.focus
+    }
+
+    pub fn focused(&self) -> &View {
+        &self.views[&self.focus]
+    }
+
+    pub fn focused_mut(&mut self) -> &mut View {
+        self.views.get_mut(&self.focus).unwrap()
+    }
+
+    pub fn get(&self, id: usize) -> Option<&View> {
+        self.views.get(&id)
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut View> {
+        self.views.get_mut(&id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &View)> {
+        self.views.iter().map(|(id, view)| (*id, view))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (usize, &mut View)> {
+        self.views.iter_mut().map(|(id, view)| (*id, view))
+    }
+
+    /// Splits the focused window in two and focuses the new one, which starts
+    /// as a copy of the focused view.
+    pub fn split(&mut self, direction: Direction) {
+        self.last_id += 1;
+        let id = self.last_id;
+
+        self.views.insert(id, self.focused().clone());
+        self.root.split(self.focus, id, direction);
+        self.focus = id;
+    }
+
+    /// Closes the focused window, unless it is the last one.
+    pub fn close(&mut self) -> bool {
+        if self.views.len() == 1 {
+            return false;
+        }
+
+        let order = self.root.windows();
+        let idx = order.iter().position(|&id| id == self.focus).unwrap();
+
+        self.root.remove(self.focus);
+        self.views.remove(&self.focus);
+        self.focus = order[if idx == 0 { 1 } else { idx - 1 }];
+        true
+    }
+
+    /// Focuses the next window, or the previous one if `reverse` is set.
+    pub fn cycle(&mut self, reverse: bool) {
+        let order = self.root.windows();
+        let idx = order.iter().position(|&id| id == self.focus).unwrap();
+
+        self.focus = if reverse {
+            order[idx.checked_sub(1).unwrap_or(order.len() - 1)]
+        } else {
+            order[(idx + 1) % order.len()]
+        };
+    }
+
+    /// Focuses the nearest window on the given side of the focused one, as
+    /// laid out on the last render.
+    pub fn focus_side(&mut self, side: Side) {
+        let areas = self.layout(self.last_area);
+        let Some(&(_, focused)) = areas.iter().find(|(id, _)| *id == self.focus) else {
+            return;
+        };
+
+        let overlaps_x = |area: Rect| area.x < focused.right() && focused.x < area.right();
+        let overlaps_y = |area: Rect| area.y < focused.bottom() && focused.y < area.bottom();
+
+        let nearest = areas
+            .iter()
+            .filter_map(|&(id, area)| {
+                let distance = match side {
+                    Side::Left if overlaps_y(area) => focused.x.checked_sub(area.right()),
+                    Side::Right if overlaps_y(area) => area.x.checked_sub(focused.right()),
+                    Side::Up if overlaps_x(area) => focused.y.checked_sub(area.bottom()),
+                    Side::Down if overlaps_x(area) => area.y.checked_sub(focused.bottom()),
+                    _ => None,
+                }?;
+
+                let offset = match side {
+                    Side::Left | Side::Right => area.y.abs_diff(focused.y),
+                    Side::Up | Side::Down => area.x.abs_diff(focused.x),
+                };
+
+                Some((distance, offset, id))
+            })
+            .min();
+
+        if let Some((_, _, id)) = nearest {
+            self.focus = id;
+        }
+    }
+
+    /// The area of each window, with a column left between side by side
+    /// windows for a separator.
+    pub fn layout(&mut self, area: Rect) -> Vec<(usize, Rect)> {
+        self.last_area = area;
+
+        let mut areas = Vec::new();
+        self.root.layout(area, &mut areas);
+        areas
+    }
+}
+
+impl Node {
+    fn windows(&self) -> Vec<usize> {
+        match self {
+            Self::Window(id) => vec![*id],
+            Self::Split(_, children) => children.iter().flat_map(Self::windows).collect(),
+        }
+    }
+
+    fn split(&mut self, target: usize, id: usize, direction: Direction) -> bool {
+        match self {
+            Self::Window(window) if *window == target => {
+                *self = Self::Split(direction, vec![Self::Window(id), Self::Window(target)]);
+                true
+            }
+            Self::Window(_) => false,
+            Self::Split(split, children) => {
+                if *split == direction
+                    && let Some(idx) = children.iter().position(|child| child.is(target))
+                {
+                    children.insert(idx, Self::Window(id));
+                    return true;
+                }
+
+                children
+                    .iter_mut()
+                    .any(|child| child.split(target, id, direction))
+            }
+        }
+    }
+
+    fn remove(&mut self, target: usize) -> bool {
+        let Self::Split(_, children) = self else {
+            return false;
+        };
+
+        if let Some(idx) = children.iter().position(|child| child.is(target)) {
+            children.remove(idx);
+        } else if !children.iter_mut().any(|child| child.remove(target)) {
+            return false;
+        }
+
+        if children.len() == 1 {
+            *self = children.pop().unwrap();
+        }
+
+        true
+    }
+
+    fn is(&self, target: usize) -> bool {
+        matches!(self, Self::Window(id) if *id == target)
+    }
+
+    fn layout(&self, area: Rect, areas: &mut Vec<(usize, Rect)>) {
+        match self {
+            Self::Window(id) => areas.push((*id, area)),
+            Self::Split(direction, children) => {
+                let spacing = if *direction == Direction::Horizontal {
+                    1
+                } else {
+                    0
+                };
+                let constraints = children.iter().map(|_| Constraint::Fill(1));
+                let layout = Layout::new(*direction, constraints)
+                    .spacing(spacing)
+                    .split(area);
+
+                for (child, area) in children.iter().zip(layout.iter()) {
+                    child.layout(*area, areas);
+                }
+            }
+        }
+    }
+}
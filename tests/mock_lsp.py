@@ -0,0 +1,105 @@
+#!/usr/bin/env python3
+"""A language server for ion's tests, speaking just enough of the protocol.
+
+It keeps the text of the open documents, applying incremental changes with
+UTF-16 positions, and after each change publishes one diagnostic over the
+text inserted last, whose message is the whole text it has.
+"""
+
+import json
+import sys
+
+
+def read():
+    length = None
+    while True:
+        line = sys.stdin.buffer.readline()
+        if not line:
+            return None
+        line = line.strip()
+        if not line:
+            break
+        name, _, value = line.decode().partition(":")
+        if name.lower() == "content-length":
+            length = int(value)
+    return json.loads(sys.stdin.buffer.read(length))
+
+
+def send(message):
+    body = json.dumps(dict(message, jsonrpc="2.0")).encode()
+    sys.stdout.buffer.write(b"Content-Length: %d\r\n\r\n" % len(body) + body)
+    sys.stdout.buffer.flush()
+
+
+def utf16_len(text):
+    return len(text.encode("utf-16-le")) // 2
+
+
+def offset(text, position):
+    """The index in `text` of an LSP position counting UTF-16 code units."""
+    lines = text.split("\n")
+    start = sum(len(line) + 1 for line in lines[: position["line"]])
+    line = lines[position["line"]] if position["line"] < len(lines) else ""
+    units = 0
+    for idx, ch in enumerate(line):
+        if units >= position["character"]:
+            return start + idx
+        units += utf16_len(ch)
+    return start + len(line)
+
+
+def position(text, idx):
+    before = text[:idx].split("\n")
+    return {"line": len(before) - 1, "character": utf16_len(before[-1])}
+
+
+def publish(uri, text, start, end):
+    send(
+        {
+            "method": "textDocument/publishDiagnostics",
+            "params": {
+                "uri": uri,
+                "diagnostics": [
+                    {
+                        "range": {"start": position(text, start), "end": position(text, end)},
+                        "severity": 2,
+                        "message": text,
+                    }
+                ],
+            },
+        }
+    )
+
+
+documents = {}
+initialized = False
+
+while (message := read()) is not None:
+    method = message.get("method")
+    params = message.get("params") or {}
+
+    if method == "initialize":
+        capabilities = {"textDocumentSync": 2, "completionProvider": {}}
+        send({"id": message["id"], "result": {"capabilities": capabilities}})
+    elif method == "initialized":
+        initialized = True
+    elif method == "shutdown":
+        send({"id": message["id"], "result": None})
+    elif method == "exit":
+        break
+    elif not initialized:
+        sys.exit(f"{method} before initialized")
+    elif method == "textDocument/didOpen":
+        document = params["textDocument"]
+        documents[document["uri"]] = document["text"]
+    elif method == "textDocument/didChange":
+        uri = params["textDocument"]["uri"]
+        text = documents[uri]
+        for change in params["contentChanges"]:
+            start = offset(text, change["range"]["start"])
+            end = offset(text, change["range"]["end"])
+            text = text[:start] + change["text"] + text[end:]
+        documents[uri] = text
+        publish(uri, text, start, start + len(change["text"]))
+    elif "id" in message:
+        send({"id": message["id"], "result": None})